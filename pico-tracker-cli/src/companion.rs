@@ -0,0 +1,158 @@
+//! standing in for the host's midi daemon: printing what the tracker sends, answering its device
+//! list and routing requests, and running the message bus.
+use crate::link::Link;
+use pico_tracker_types::{FromHost, FromTracker, PROTOCOL_VERSION, capability};
+use std::{
+    io::{Read, Write},
+    sync::mpsc::Receiver,
+};
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Companion {
+    /// the device names sent in answer to `RequestDevs`, which tracks can be routed to.
+    pub devs: Vec<String>,
+    /// the bus messages the tracker asked for with `ListenFor`.
+    pub listening: Vec<String>,
+}
+
+impl Companion {
+    pub fn new(devs: Vec<String>) -> Self {
+        Self {
+            devs,
+            listening: Vec::new(),
+        }
+    }
+
+    pub fn hello(&self) -> FromHost {
+        FromHost::Hello {
+            protocol_version: PROTOCOL_VERSION,
+            host_version: env!("CARGO_PKG_VERSION").into(),
+            capabilities: vec![
+                capability::ROUTING.into(),
+                capability::MESSAGE_BUS.into(),
+                capability::STOP_LISTENING.into(),
+                capability::NOTES_OUT.into(),
+            ],
+        }
+    }
+
+    /// a line describing a message from the tracker, and what to send back.
+    pub fn handle(&mut self, message: FromTracker) -> (String, Vec<FromHost>) {
+        match message {
+            FromTracker::Hello {
+                protocol_version,
+                firmware_version,
+                capabilities,
+            } => (
+                format!(
+                    "tracker {firmware_version} (protocol {protocol_version}) connected, it can do: {}",
+                    capabilities.join(", ")
+                ),
+                Vec::new(),
+            ),
+            FromTracker::Log {
+                message,
+                level,
+                millis,
+                module,
+            } => (
+                format!(
+                    "log {}.{:03} {} {module}: {message}",
+                    millis / 1000,
+                    millis % 1000,
+                    level.code()
+                ),
+                Vec::new(),
+            ),
+            FromTracker::RequestDevs => (
+                format!("sent the device list: {}", self.devs.join(", ")),
+                vec![FromHost::Devs {
+                    dev_names: self.devs.clone(),
+                }],
+            ),
+            FromTracker::ListenFor { message } => {
+                let line = format!("listening for {message:?}");
+
+                if !self.listening.contains(&message) {
+                    self.listening.push(message);
+                }
+
+                (line, Vec::new())
+            }
+            FromTracker::StopListeningFor { message } => {
+                let line = format!("stopped listening for {message:?}");
+                self.listening.retain(|listening| *listening != message);
+
+                (line, Vec::new())
+            }
+            // the tracker is on the bus too, so it hears the messages it listens for, whoever
+            // sent them.
+            FromTracker::MessageBus { message } => {
+                let replies = if self.listening.contains(&message) {
+                    vec![FromHost::MessageBus {
+                        message: message.clone(),
+                    }]
+                } else {
+                    Vec::new()
+                };
+
+                (format!("bus: {message:?}"), replies)
+            }
+            FromTracker::Connect {
+                id,
+                channel,
+                dev,
+                dev_channel,
+            } => {
+                let line = format!("route channel {} to {dev} {}", channel + 1, dev_channel + 1);
+                let reply = match (id, self.devs.contains(&dev)) {
+                    (Some(id), true) => Some(FromHost::Ack { id }),
+                    (id, false) => Some(FromHost::Error {
+                        id,
+                        message: format!("there's no device called {dev}"),
+                    }),
+                    (None, true) => None,
+                };
+
+                (line, reply.into_iter().collect())
+            }
+            FromTracker::NoteOn { channel, note, vel } => (
+                format!("ch {:>2} note on  {note:>3} vel {vel}", channel + 1),
+                Vec::new(),
+            ),
+            FromTracker::NoteOff { channel, note } => (
+                format!("ch {:>2} note off {note:>3}", channel + 1),
+                Vec::new(),
+            ),
+            message => (format!("{message:?}"), Vec::new()),
+        }
+    }
+}
+
+/// says hello, then prints and answers the tracker's messages and sends the ones from `input`,
+/// until the connection fails or the tracker closes it.
+pub fn run<P: Read + Write>(
+    link: &mut Link<P>,
+    companion: &mut Companion,
+    input: &Receiver<FromHost>,
+    out: &mut impl Write,
+) -> Result<(), String> {
+    link.send(&companion.hello())?;
+
+    loop {
+        // `input` being closed, like stdin reaching its end, just leaves nothing to send.
+        for message in input.try_iter() {
+            link.send(&message)?;
+        }
+
+        let Some(message) = link.poll()? else {
+            continue;
+        };
+        let (line, replies) = companion.handle(message);
+        writeln!(out, "{line}").map_err(|e| e.to_string())?;
+
+        for reply in replies {
+            link.send(&reply)?;
+        }
+    }
+}
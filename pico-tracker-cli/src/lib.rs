@@ -0,0 +1,4 @@
+//! talking to the pico-tracker from the computer it's plugged in to.
+pub mod companion;
+pub mod link;
+pub mod sync;
@@ -0,0 +1,158 @@
+//! a connection to the tracker over its serial port, or anything else that reads and writes
+//! frames.
+use pico_tracker_types::{
+    FromHost, FromTracker, PROTOCOL_VERSION,
+    codec::{FrameDecoder, encode},
+    is_compatible,
+};
+use std::io::{ErrorKind, Read, Write};
+
+pub struct Link<P> {
+    port: P,
+    decoder: FrameDecoder,
+    next_id: u32,
+    /// the tracker's firmware version, once it's said hello.
+    pub firmware_version: Option<String>,
+    pub capabilities: Vec<String>,
+}
+
+/// the id of the request a message from the tracker answers, if it's an answer.
+pub fn reply_id(message: &FromTracker) -> Option<u32> {
+    match message {
+        FromTracker::Ack { id } => Some(*id),
+        FromTracker::Error { id, .. }
+        | FromTracker::Steps { id, .. }
+        | FromTracker::Files { id, .. }
+        | FromTracker::FileChunk { id, .. } => *id,
+        _ => None,
+    }
+}
+
+impl<P: Read + Write> Link<P> {
+    pub fn new(port: P) -> Self {
+        Self {
+            port,
+            decoder: FrameDecoder::default(),
+            next_id: 0,
+            firmware_version: None,
+            capabilities: Vec::new(),
+        }
+    }
+
+    pub fn into_port(self) -> P {
+        self.port
+    }
+
+    pub fn has_capability(&self, capability: &str) -> bool {
+        self.capabilities.iter().any(|c| c == capability)
+    }
+
+    pub fn send(&mut self, message: &FromHost) -> Result<(), String> {
+        let frame = encode(message).map_err(|e| e.to_string())?;
+
+        self.port
+            .write_all(&frame)
+            .and_then(|_| self.port.flush())
+            .map_err(|e| format!("failed to write to the tracker: {e}"))
+    }
+
+    /// the next message from the tracker, or `None` if the port timed out before a whole one
+    /// arrived. frames that can't be read, like messages from newer firmware, are skipped.
+    pub fn poll(&mut self) -> Result<Option<FromTracker>, String> {
+        let mut buf = [0u8; 512];
+
+        loop {
+            while let Some(message) = self.decoder.decode::<FromTracker>() {
+                match message {
+                    Ok(message) => return Ok(Some(message)),
+                    Err(e) => eprintln!("skipped a message from the tracker: {e}"),
+                }
+            }
+
+            match self.port.read(&mut buf) {
+                Ok(0) => return Err("the tracker closed the connection".into()),
+                Ok(n) => self.decoder.push(&buf[..n]),
+                Err(e) if e.kind() == ErrorKind::Interrupted => {}
+                Err(e) if matches!(e.kind(), ErrorKind::TimedOut | ErrorKind::WouldBlock) => {
+                    return Ok(None);
+                }
+                Err(e) => return Err(format!("failed to read from the tracker: {e}")),
+            }
+        }
+    }
+
+    /// the next message from the tracker, waiting no longer than the port's timeout.
+    pub fn recv(&mut self) -> Result<FromTracker, String> {
+        self.poll()?
+            .ok_or_else(|| "timed out waiting for the tracker".into())
+    }
+
+    /// says hello and waits for the tracker's hello.
+    pub fn handshake(&mut self) -> Result<(), String> {
+        self.send(&FromHost::Hello {
+            protocol_version: PROTOCOL_VERSION,
+            host_version: env!("CARGO_PKG_VERSION").into(),
+            capabilities: Vec::new(),
+        })?;
+
+        loop {
+            match self.recv()? {
+                FromTracker::Hello {
+                    protocol_version,
+                    firmware_version,
+                    capabilities,
+                } => {
+                    if !is_compatible(protocol_version) {
+                        return Err(format!(
+                            "the tracker speaks protocol version {protocol_version}, expected {PROTOCOL_VERSION}"
+                        ));
+                    }
+
+                    self.firmware_version = Some(firmware_version);
+                    self.capabilities = capabilities;
+
+                    return Ok(());
+                }
+                message => log_message(&message),
+            }
+        }
+    }
+
+    /// sends the message `make` builds with a new request id, and waits for the tracker to
+    /// answer it. an `Error` answer is returned as the `Err`.
+    pub fn request(
+        &mut self,
+        make: impl FnOnce(Option<u32>) -> FromHost,
+    ) -> Result<FromTracker, String> {
+        let id = self.next_id;
+        self.next_id = self.next_id.wrapping_add(1);
+        self.send(&make(Some(id)))?;
+
+        loop {
+            let message = self.recv()?;
+
+            if reply_id(&message) != Some(id) {
+                log_message(&message);
+                continue;
+            }
+
+            return match message {
+                FromTracker::Error { message, .. } => Err(message),
+                message => Ok(message),
+            };
+        }
+    }
+}
+
+/// prints what the tracker logs while it's being waited on.
+fn log_message(message: &FromTracker) {
+    if let FromTracker::Log {
+        message,
+        level,
+        module,
+        ..
+    } = message
+    {
+        eprintln!("tracker {} {module}: {message}", level.code());
+    }
+}
@@ -0,0 +1,153 @@
+use pico_tracker_cli::{
+    companion::{self, Companion},
+    link::Link,
+    sync,
+};
+use pico_tracker_types::{FromHost, capability, ron};
+use std::{
+    io::BufRead,
+    path::Path,
+    process::ExitCode,
+    sync::mpsc::{Sender, channel},
+    thread,
+    time::Duration,
+};
+
+const USAGE: &str = "usage: pico-tracker-cli <serial port> <command>
+
+commands:
+    monitor [dev]...      print what the tracker sends, answer its requests with the devices
+                          given, and run the message bus. lines typed in are sent to the tracker
+                          as `FromHost` RON, e.g. `SetTempo(bpm: 140)`
+    send <message>        send the tracker one `FromHost` message, as RON
+    list                  list the files on the SD card
+    fetch <name> [out]    copy a file off of the SD card, to <name> unless <out> is given
+    push <file> [name]    copy a file on to the SD card, as the file's name unless <name> is given
+    delete <name>         delete a file from the SD card
+    backup <dir>          copy every file off of the SD card in to <dir>
+    restore <dir>         copy every file in <dir> on to the SD card";
+
+/// how long to wait on the tracker before giving up.
+const TIMEOUT: Duration = Duration::from_secs(5);
+/// how often the monitor stops waiting on the tracker to send what's been typed in.
+const POLL: Duration = Duration::from_millis(50);
+
+fn parse(message: &str) -> Result<FromHost, String> {
+    ron::from_str(message.trim()).map_err(|e| format!("not a FromHost message: {e}"))
+}
+
+/// sends each line typed in to `to_tracker`, until stdin closes.
+fn read_stdin(to_tracker: Sender<FromHost>) {
+    for line in std::io::stdin().lock().lines() {
+        let Ok(line) = line else {
+            return;
+        };
+
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        match parse(&line) {
+            Ok(message) => {
+                if to_tracker.send(message).is_err() {
+                    return;
+                }
+            }
+            Err(e) => eprintln!("{e}"),
+        }
+    }
+}
+
+fn run(args: &[String]) -> Result<(), String> {
+    let [port, command, rest @ ..] = args else {
+        return Err(USAGE.into());
+    };
+
+    let timeout = if command == "monitor" { POLL } else { TIMEOUT };
+    let mut port = serialport::new(port, 115_200)
+        .timeout(timeout)
+        .open()
+        .map_err(|e| format!("failed to open {port}: {e}"))?;
+    // the tracker says hello when the port is opened, which it sees as DTR going high. ptys
+    // don't have DTR, so failing to set it is fine.
+    let _ = port
+        .write_data_terminal_ready(false)
+        .and_then(|_| port.write_data_terminal_ready(true));
+
+    let mut link = Link::new(port);
+
+    match (command.as_str(), rest) {
+        ("monitor", devs) => {
+            let (to_tracker, input) = channel();
+            thread::spawn(move || read_stdin(to_tracker));
+
+            let mut companion = Companion::new(devs.to_vec());
+
+            return companion::run(&mut link, &mut companion, &input, &mut std::io::stdout());
+        }
+        ("send", [message]) => {
+            let message = parse(message)?;
+            link.send(&Companion::default().hello())?;
+
+            return link.send(&message);
+        }
+        _ => {}
+    }
+
+    link.handshake()?;
+
+    if !link.has_capability(capability::PROJECT_SYNC) {
+        return Err("the tracker's firmware doesn't support project sync".into());
+    }
+
+    match (command.as_str(), rest) {
+        ("list", []) => {
+            for file in sync::list(&mut link)? {
+                println!("{:>8}  {}", file.len, file.name);
+            }
+        }
+        ("fetch", [name, out @ ..]) if out.len() <= 1 => {
+            let bytes = sync::fetch(&mut link, name)?;
+            let out = out.first().unwrap_or(name);
+            std::fs::write(out, bytes).map_err(|e| format!("failed to write {out}: {e}"))?;
+        }
+        ("push", [file, name @ ..]) if name.len() <= 1 => {
+            let bytes = std::fs::read(file).map_err(|e| format!("failed to read {file}: {e}"))?;
+            let name = match name.first() {
+                Some(name) => name.clone(),
+                None => Path::new(file)
+                    .file_name()
+                    .and_then(|name| name.to_str())
+                    .ok_or_else(|| format!("{file} isn't a usable file name"))?
+                    .to_uppercase(),
+            };
+            sync::push(&mut link, &name, &bytes)?;
+        }
+        ("delete", [name]) => sync::delete(&mut link, name)?,
+        ("backup", [dir]) => {
+            for name in sync::backup(&mut link, Path::new(dir))? {
+                println!("{name}");
+            }
+        }
+        ("restore", [dir]) => {
+            for name in sync::restore(&mut link, Path::new(dir))? {
+                println!("{name}");
+            }
+        }
+        _ => return Err(USAGE.into()),
+    }
+
+    Ok(())
+}
+
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+
+    match run(&args) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("{e}");
+            ExitCode::FAILURE
+        }
+    }
+}
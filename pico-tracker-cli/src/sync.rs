@@ -0,0 +1,163 @@
+//! backing up and restoring the files on the tracker's SD card.
+use crate::link::Link;
+use pico_tracker_types::{
+    FileInfo, FromHost, FromTracker,
+    sync::{CHUNK_LEN, MAX_FILE_LEN, TEMP_FILE, crc32, from_hex, to_hex},
+};
+use std::{
+    fs,
+    io::{Read, Write},
+    path::Path,
+};
+
+fn unexpected(message: FromTracker) -> String {
+    format!("unexpected answer from the tracker: {message:?}")
+}
+
+pub fn list<P: Read + Write>(link: &mut Link<P>) -> Result<Vec<FileInfo>, String> {
+    match link.request(|id| FromHost::ListFiles { id })? {
+        FromTracker::Files { files, .. } => Ok(files),
+        message => Err(unexpected(message)),
+    }
+}
+
+/// reads all of `name` off of the SD card, a chunk at a time.
+pub fn fetch<P: Read + Write>(link: &mut Link<P>, name: &str) -> Result<Vec<u8>, String> {
+    let mut bytes = Vec::new();
+    // the length and checksum of the whole file, from the first chunk.
+    let mut file = None;
+
+    loop {
+        let offset = bytes.len() as u32;
+        let (data, crc, file_len, file_crc) = match link.request(|id| FromHost::ReadFile {
+            id,
+            name: name.into(),
+            offset,
+        })? {
+            FromTracker::FileChunk {
+                offset: at,
+                data,
+                crc,
+                file_len,
+                file_crc,
+                ..
+            } if at == offset => (data, crc, file_len, file_crc),
+            message => return Err(unexpected(message)),
+        };
+
+        let chunk = from_hex(&data).ok_or_else(|| format!("the chunk at {offset} isn't hex"))?;
+
+        if crc32(&chunk) != crc {
+            return Err(format!("the chunk at {offset} failed its checksum"));
+        }
+
+        if *file.get_or_insert((file_len, file_crc)) != (file_len, file_crc) {
+            return Err(format!("{name} changed while it was being fetched"));
+        }
+
+        bytes.extend_from_slice(&chunk);
+
+        if chunk.is_empty() || bytes.len() >= file_len as usize {
+            break;
+        }
+    }
+
+    match file {
+        Some((len, crc)) if bytes.len() == len as usize && crc32(&bytes) == crc => Ok(bytes),
+        _ => Err(format!("{name} failed its checksum")),
+    }
+}
+
+/// writes `bytes` to `name` on the SD card. the tracker only replaces the file once all of it
+/// has arrived intact.
+pub fn push<P: Read + Write>(link: &mut Link<P>, name: &str, bytes: &[u8]) -> Result<(), String> {
+    // the tracker would turn it away part way through.
+    if bytes.len() > MAX_FILE_LEN as usize {
+        return Err(format!("{name} is over {MAX_FILE_LEN} bytes"));
+    }
+
+    // an empty file is still started with a chunk at offset 0.
+    let chunks = bytes
+        .chunks(CHUNK_LEN)
+        .chain(bytes.is_empty().then_some(bytes));
+    let mut offset = 0;
+
+    for chunk in chunks {
+        link.request(|id| FromHost::WriteFile {
+            id,
+            name: name.into(),
+            offset,
+            data: to_hex(chunk),
+            crc: crc32(chunk),
+        })?;
+        offset += chunk.len() as u32;
+    }
+
+    link.request(|id| FromHost::FinishFile {
+        id,
+        name: name.into(),
+        len: bytes.len() as u32,
+        crc: crc32(bytes),
+    })?;
+
+    Ok(())
+}
+
+pub fn delete<P: Read + Write>(link: &mut Link<P>, name: &str) -> Result<(), String> {
+    link.request(|id| FromHost::DeleteFile {
+        id,
+        name: name.into(),
+    })?;
+
+    Ok(())
+}
+
+/// fetches every file on the SD card in to `dir`, returning their names.
+pub fn backup<P: Read + Write>(link: &mut Link<P>, dir: &Path) -> Result<Vec<String>, String> {
+    fs::create_dir_all(dir).map_err(|e| format!("failed to create {}: {e}", dir.display()))?;
+    let mut names = Vec::new();
+
+    // a push that never finished isn't worth keeping.
+    for file in list(link)?
+        .into_iter()
+        .filter(|file| file.name != TEMP_FILE)
+    {
+        let bytes = fetch(link, &file.name)?;
+        let path = dir.join(&file.name);
+        fs::write(&path, bytes).map_err(|e| format!("failed to write {}: {e}", path.display()))?;
+        names.push(file.name);
+    }
+
+    Ok(names)
+}
+
+/// pushes every file in `dir` to the SD card, returning their names. the names have to be 8.3,
+/// like the ones `backup` writes.
+pub fn restore<P: Read + Write>(link: &mut Link<P>, dir: &Path) -> Result<Vec<String>, String> {
+    let entries =
+        fs::read_dir(dir).map_err(|e| format!("failed to read {}: {e}", dir.display()))?;
+    let mut names = Vec::new();
+
+    for entry in entries {
+        let path = entry.map_err(|e| e.to_string())?.path();
+
+        if !path.is_file() {
+            continue;
+        }
+
+        let name = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .ok_or_else(|| format!("{} isn't a usable file name", path.display()))?
+            .to_uppercase();
+        let bytes =
+            fs::read(&path).map_err(|e| format!("failed to read {}: {e}", path.display()))?;
+
+        push(link, &name, &bytes)?;
+        names.push(name);
+    }
+
+    names.sort();
+
+    Ok(names)
+}
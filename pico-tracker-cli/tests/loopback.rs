@@ -0,0 +1,160 @@
+use pico_tracker_cli::{
+    companion::{self, Companion},
+    link::Link,
+};
+use pico_tracker_types::{
+    FromHost, FromTracker, LogLevel, PROTOCOL_VERSION, capability,
+    codec::{FrameDecoder, encode},
+};
+use std::{
+    io::{Read, Write},
+    os::unix::net::UnixStream,
+    sync::mpsc::channel,
+    thread,
+    time::Duration,
+};
+
+/// the tracker's end of the serial port.
+struct Tracker {
+    port: UnixStream,
+    decoder: FrameDecoder,
+}
+
+impl Tracker {
+    fn send(&mut self, message: FromTracker) {
+        self.port.write_all(&encode(&message).unwrap()).unwrap();
+    }
+
+    fn recv(&mut self) -> FromHost {
+        let mut buf = [0u8; 256];
+
+        loop {
+            if let Some(message) = self.decoder.decode() {
+                return message.unwrap();
+            }
+
+            let n = self.port.read(&mut buf).unwrap();
+            assert_ne!(n, 0, "the companion closed the port");
+            self.decoder.push(&buf[..n]);
+        }
+    }
+}
+
+#[test]
+fn companion_loopback() {
+    let (host, tracker) = UnixStream::pair().unwrap();
+    host.set_read_timeout(Some(Duration::from_millis(10)))
+        .unwrap();
+    tracker
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+
+    let simulated = thread::spawn(move || {
+        let mut tracker = Tracker {
+            port: tracker,
+            decoder: FrameDecoder::default(),
+        };
+
+        let FromHost::Hello {
+            protocol_version,
+            capabilities,
+            ..
+        } = tracker.recv()
+        else {
+            panic!("the companion didn't say hello first");
+        };
+        assert_eq!(protocol_version, PROTOCOL_VERSION);
+        assert!(capabilities.contains(&capability::MESSAGE_BUS.into()));
+
+        // typed in before the tracker said anything
+        assert_eq!(tracker.recv(), FromHost::SetTempo { id: None, bpm: 140 });
+
+        tracker.send(FromTracker::Hello {
+            protocol_version: PROTOCOL_VERSION,
+            firmware_version: "0.1.0".into(),
+            capabilities: Vec::new(),
+        });
+        tracker.send(FromTracker::Log {
+            message: "project loaded".into(),
+            level: LogLevel::Info,
+            millis: 2_048,
+            module: "project".into(),
+        });
+        tracker.send(FromTracker::RequestDevs);
+        assert_eq!(
+            tracker.recv(),
+            FromHost::Devs {
+                dev_names: vec!["synth".into()],
+            }
+        );
+
+        // only the messages the tracker listens for come back to it
+        tracker.send(FromTracker::ListenFor {
+            message: "drop".into(),
+        });
+        tracker.send(FromTracker::ListenFor {
+            message: "quiet".into(),
+        });
+        tracker.send(FromTracker::StopListeningFor {
+            message: "quiet".into(),
+        });
+        tracker.send(FromTracker::MessageBus {
+            message: "quiet".into(),
+        });
+        tracker.send(FromTracker::MessageBus {
+            message: "lights".into(),
+        });
+        tracker.send(FromTracker::MessageBus {
+            message: "drop".into(),
+        });
+        assert_eq!(
+            tracker.recv(),
+            FromHost::MessageBus {
+                message: "drop".into(),
+            }
+        );
+
+        tracker.send(FromTracker::Connect {
+            id: Some(7),
+            channel: 0,
+            dev: "synth".into(),
+            dev_channel: 9,
+        });
+        assert_eq!(tracker.recv(), FromHost::Ack { id: 7 });
+
+        tracker.send(FromTracker::Connect {
+            id: Some(8),
+            channel: 1,
+            dev: "gone".into(),
+            dev_channel: 0,
+        });
+        assert!(matches!(
+            tracker.recv(),
+            FromHost::Error { id: Some(8), .. }
+        ));
+        // the port closes as the tracker goes away
+    });
+
+    let (to_tracker, input) = channel();
+    to_tracker
+        .send(FromHost::SetTempo { id: None, bpm: 140 })
+        .unwrap();
+
+    let mut link = Link::new(host);
+    let mut companion = Companion::new(vec!["synth".into()]);
+    let mut out = Vec::new();
+
+    assert_eq!(
+        companion::run(&mut link, &mut companion, &input, &mut out),
+        Err("the tracker closed the connection".into())
+    );
+    simulated.join().unwrap();
+
+    let out = String::from_utf8(out).unwrap();
+    assert!(out.contains("tracker 0.1.0"));
+    assert!(out.contains("log 2.048 I project: project loaded"));
+    assert!(out.contains("bus: \"lights\""));
+    assert!(out.contains("stopped listening for \"quiet\""));
+    assert!(out.contains("route channel 1 to synth 10"));
+    assert_eq!(companion.listening, vec!["drop".to_string()]);
+}
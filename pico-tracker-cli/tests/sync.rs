@@ -0,0 +1,146 @@
+use pico_tracker_cli::{link::Link, sync};
+use pico_tracker_types::{
+    FromHost, FromTracker, LogLevel,
+    codec::{FrameDecoder, encode},
+    sync::{CHUNK_LEN, MAX_FILE_LEN, crc32, to_hex},
+};
+use std::io::{Cursor, Read, Write};
+
+/// a port that reads back the tracker's answers, written out ahead of time, and keeps what's
+/// written to it.
+struct Scripted {
+    answers: Cursor<Vec<u8>>,
+    sent: Vec<u8>,
+}
+
+impl Scripted {
+    fn new(answers: &[FromTracker]) -> Self {
+        Self {
+            answers: Cursor::new(answers.iter().flat_map(|a| encode(a).unwrap()).collect()),
+            sent: Vec::new(),
+        }
+    }
+
+    fn sent(&self) -> Vec<FromHost> {
+        let mut decoder = FrameDecoder::default();
+        decoder.push(&self.sent);
+
+        std::iter::from_fn(|| decoder.decode().map(Result::unwrap)).collect()
+    }
+}
+
+impl Read for Scripted {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.answers.read(buf)
+    }
+}
+
+impl Write for Scripted {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.sent.write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+fn chunk(id: u32, file: &[u8], offset: usize) -> FromTracker {
+    let data = &file[offset..file.len().min(offset + CHUNK_LEN)];
+
+    FromTracker::FileChunk {
+        id: Some(id),
+        name: "PROJECT.RON".into(),
+        offset: offset as u32,
+        data: to_hex(data),
+        crc: crc32(data),
+        file_len: file.len() as u32,
+        file_crc: crc32(file),
+    }
+}
+
+#[test]
+fn fetch_chunks() {
+    let file: Vec<u8> = (0..CHUNK_LEN + 10).map(|i| i as u8).collect();
+    let mut link = Link::new(Scripted::new(&[
+        FromTracker::Log {
+            message: "hi".into(),
+            level: LogLevel::Info,
+            millis: 0,
+            module: "sync".into(),
+        },
+        chunk(0, &file, 0),
+        chunk(1, &file, CHUNK_LEN),
+    ]));
+
+    assert_eq!(sync::fetch(&mut link, "PROJECT.RON"), Ok(file));
+
+    // a chunk that doesn't match its checksum is caught
+    let mut bad = chunk(0, b"(tempo: 120)", 0);
+    if let FromTracker::FileChunk { crc, .. } = &mut bad {
+        *crc += 1;
+    }
+    let mut link = Link::new(Scripted::new(&[bad]));
+    assert!(sync::fetch(&mut link, "PROJECT.RON").is_err());
+
+    // running out of answers
+    let mut link = Link::new(Scripted::new(&[]));
+    assert!(sync::fetch(&mut link, "PROJECT.RON").is_err());
+}
+
+#[test]
+fn push_chunks() {
+    let file = vec![b'x'; CHUNK_LEN + 1];
+    let mut link = Link::new(Scripted::new(&[
+        FromTracker::Ack { id: 0 },
+        FromTracker::Ack { id: 1 },
+        FromTracker::Ack { id: 2 },
+    ]));
+
+    assert_eq!(sync::push(&mut link, "PROJECT.RON", &file), Ok(()));
+    let sent = link.into_port().sent();
+    assert_eq!(sent.len(), 3);
+    assert!(matches!(
+        &sent[1],
+        FromHost::WriteFile { id: Some(1), offset, data, .. } if *offset == CHUNK_LEN as u32 && data == "78"
+    ));
+    assert_eq!(
+        sent[2],
+        FromHost::FinishFile {
+            id: Some(2),
+            name: "PROJECT.RON".into(),
+            len: file.len() as u32,
+            crc: crc32(&file),
+        }
+    );
+
+    // an empty file still gets a chunk to start it
+    let mut link = Link::new(Scripted::new(&[]));
+    let _ = sync::push(&mut link, "EMPTY.RON", &[]);
+    assert_eq!(
+        link.into_port().sent(),
+        vec![FromHost::WriteFile {
+            id: Some(0),
+            name: "EMPTY.RON".into(),
+            offset: 0,
+            data: String::new(),
+            crc: 0,
+        }]
+    );
+
+    // the tracker refusing a chunk stops the push
+    let mut link = Link::new(Scripted::new(&[FromTracker::Error {
+        id: Some(0),
+        message: "card full".into(),
+    }]));
+    assert_eq!(
+        sync::push(&mut link, "PROJECT.RON", &file),
+        Err("card full".into())
+    );
+
+    // a file too big for the tracker isn't sent at all
+    let mut link = Link::new(Scripted::new(&[]));
+    let big = vec![0; MAX_FILE_LEN as usize + 1];
+    assert!(sync::push(&mut link, "BIG.RON", &big).is_err());
+    assert_eq!(link.into_port().sent(), vec![]);
+}
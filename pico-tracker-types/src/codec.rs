@@ -0,0 +1,137 @@
+//! newline framing for the RON messages sent between the host and the tracker.
+//!
+//! every message is sent as its RON text followed by `\n`. RON escapes newlines inside of
+//! strings, so the only newline in a frame is the one that ends it. a NUL byte also ends a frame,
+//! and `\r`s and other whitespace around a frame are ignored, so hosts that terminate messages
+//! with `\0` or `\r\n` are understood too.
+
+use alloc::{
+    string::{String, ToString},
+    vec::Vec,
+};
+use core::fmt::Display;
+use serde::{Serialize, de::DeserializeOwned};
+
+/// the longest frame either side will send or accept, in bytes, not counting the delimiter.
+pub const MAX_FRAME_LEN: usize = 4096;
+
+/// the byte that ends every frame that gets sent.
+pub const FRAME_END: u8 = b'\n';
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum FrameError {
+    /// the frame was longer than the limit, its bytes were dropped.
+    TooLong,
+    /// the frame isn't valid utf-8.
+    NotUtf8,
+    /// the frame isn't a valid message.
+    Parse(String),
+    /// the message couldn't be turned in to RON.
+    Encode(String),
+}
+
+impl Display for FrameError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::TooLong => write!(f, "frame longer than {MAX_FRAME_LEN} bytes"),
+            Self::NotUtf8 => write!(f, "frame is not utf-8"),
+            Self::Parse(e) => write!(f, "failed to parse frame: {e}"),
+            Self::Encode(e) => write!(f, "failed to encode message: {e}"),
+        }
+    }
+}
+
+/// `message` as a frame, ready to be written to the serial port.
+pub fn encode<T: Serialize>(message: &T) -> Result<Vec<u8>, FrameError> {
+    let text = ron::to_string(message).map_err(|e| FrameError::Encode(e.to_string()))?;
+
+    if text.len() > MAX_FRAME_LEN {
+        return Err(FrameError::TooLong);
+    }
+
+    let mut frame = text.into_bytes();
+    frame.push(FRAME_END);
+
+    Ok(frame)
+}
+
+/// reassembles frames out of the bytes read from the serial port, however they were split up
+/// between reads.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FrameDecoder {
+    buf: Vec<u8>,
+    max_len: usize,
+    /// the frame being read went over the limit, its bytes are dropped up to the next delimiter.
+    discarding: bool,
+}
+
+impl Default for FrameDecoder {
+    fn default() -> Self {
+        Self::new(MAX_FRAME_LEN)
+    }
+}
+
+impl FrameDecoder {
+    pub fn new(max_len: usize) -> Self {
+        Self {
+            buf: Vec::new(),
+            max_len,
+            discarding: false,
+        }
+    }
+
+    /// adds bytes read from the serial port.
+    pub fn push(&mut self, bytes: &[u8]) {
+        self.buf.extend_from_slice(bytes);
+    }
+
+    /// how many bytes are waiting for the end of their frame.
+    pub fn pending(&self) -> usize {
+        self.buf.len()
+    }
+
+    /// the next complete frame, parsed as a `T`. `None` once there are no more complete frames.
+    /// a frame that goes over the length limit is reported once, as soon as it does.
+    pub fn decode<T: DeserializeOwned>(&mut self) -> Option<Result<T, FrameError>> {
+        loop {
+            let Some(end) = self.buf.iter().position(|byte| is_delimiter(*byte)) else {
+                if self.discarding {
+                    self.buf.clear();
+                } else if self.buf.len() > self.max_len {
+                    self.buf.clear();
+                    self.discarding = true;
+
+                    return Some(Err(FrameError::TooLong));
+                }
+
+                return None;
+            };
+
+            let frame: Vec<u8> = self.buf.drain(..=end).take(end).collect();
+
+            if core::mem::take(&mut self.discarding) {
+                continue;
+            }
+
+            if frame.len() > self.max_len {
+                return Some(Err(FrameError::TooLong));
+            }
+
+            let Ok(text) = core::str::from_utf8(&frame) else {
+                return Some(Err(FrameError::NotUtf8));
+            };
+            let text = text.trim();
+
+            // blank lines between frames.
+            if text.is_empty() {
+                continue;
+            }
+
+            return Some(ron::from_str(text).map_err(|e| FrameError::Parse(e.to_string())));
+        }
+    }
+}
+
+fn is_delimiter(byte: u8) -> bool {
+    byte == FRAME_END || byte == 0
+}
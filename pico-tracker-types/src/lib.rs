@@ -1,15 +1,116 @@
 #![no_std]
+//! the messages sent between the host and the tracker.
+//!
+//! # compatibility
+//!
+//! `PROTOCOL_VERSION` only changes when a change would break the other side: a variant or field
+//! being removed, renamed, or changing meaning. both sides send `Hello` when the link comes up
+//! and only talk to a peer with the same version. everything else is added without a version
+//! change, following these rules so that older peers keep working:
+//!
+//! - new fields on existing variants are `#[serde(default)]`, so messages from older peers
+//!   still parse. unknown fields from newer peers are ignored.
+//! - new variants are only sent to peers that list the matching capability in their `Hello`.
+//!   a variant the receiver doesn't know fails to parse and is answered with an `Error`.
+//! - capabilities are strings, so unknown ones are ignored rather than failing to parse.
 
 pub extern crate alloc;
 
 pub use ron;
 
+pub mod codec;
+pub mod sync;
+
 use alloc::{string::String, vec::Vec};
 use bevy::prelude::*;
 use serde::{Deserialize, Serialize};
 
-#[derive(Clone, PartialEq, Eq, PartialOrd, Ord, Deserialize, Serialize, Event)]
+/// bumped on breaking changes to the messages only, see the crate docs.
+pub const PROTOCOL_VERSION: u16 = 1;
+
+/// whether a peer that sent `version` in its `Hello` can be talked to.
+pub fn is_compatible(version: u16) -> bool {
+    version == PROTOCOL_VERSION
+}
+
+/// optional features that a side lists in its `Hello`.
+pub mod capability {
+    /// notes and CCs from the host's midi controllers (`FromHost::MidiNoteOn`, `MidiNoteOff` and
+    /// `MidiCC`).
+    pub const NOTES_IN: &str = "notes-in";
+    /// listing devices and routing channels to them (`RequestDevs`, `Devs` and `Connect`).
+    pub const ROUTING: &str = "routing";
+    /// the host's message bus (`ListenFor` and `MessageBus`).
+    pub const MESSAGE_BUS: &str = "message-bus";
+    /// telling the host to stop sending a bus message it was asked for
+    /// (`FromTracker::StopListeningFor`).
+    pub const STOP_LISTENING: &str = "stop-listening";
+    /// the notes of the tracker's tracks, for the host to send on to its devices
+    /// (`FromTracker::NoteOn`, `NoteOff`, `Cc`, `PitchBend` and `ProgramChange`).
+    pub const NOTES_OUT: &str = "notes-out";
+    /// the host driving the tracker: transport, tempo, mute/solo, and reading and writing steps
+    /// (`FromHost::Play` through `WriteSteps`, and `FromTracker::Steps`).
+    pub const REMOTE: &str = "remote";
+    /// listing, fetching, pushing and deleting the files on the tracker's SD card
+    /// (`FromHost::ListFiles` through `DeleteFile`, `FromTracker::Files` and `FileChunk`).
+    pub const PROJECT_SYNC: &str = "project-sync";
+    /// log levels the host can pick between (`FromHost::SetLogLevel`).
+    pub const LOG_LEVELS: &str = "log-levels";
+}
+
+/// how important a log message is, most important first.
+#[derive(
+    Clone, Copy, Default, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Deserialize, Serialize,
+)]
+pub enum LogLevel {
+    Error,
+    Warn,
+    #[default]
+    Info,
+    Debug,
+    Trace,
+}
+
+impl LogLevel {
+    /// one letter, for where there isn't room for more.
+    pub fn code(&self) -> char {
+        match self {
+            Self::Error => 'E',
+            Self::Warn => 'W',
+            Self::Info => 'I',
+            Self::Debug => 'D',
+            Self::Trace => 'T',
+        }
+    }
+}
+
+/// a file on the tracker's SD card.
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Deserialize, Serialize)]
+pub struct FileInfo {
+    /// 8.3, e.g. `PROJECT.RON`.
+    pub name: String,
+    pub len: u32,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Deserialize, Serialize, Event)]
 pub enum FromHost {
+    /// the host's half of the handshake, sent when it opens the serial port.
+    Hello {
+        protocol_version: u16,
+        #[serde(default)]
+        host_version: String,
+        #[serde(default)]
+        capabilities: Vec<String>,
+    },
+    /// the request from the tracker with this id was carried out.
+    Ack { id: u32 },
+    /// the request from the tracker with this id failed, or a message from the tracker couldn't be
+    /// read if `id` is `None`.
+    Error {
+        #[serde(default)]
+        id: Option<u32>,
+        message: String,
+    },
     /// a list of known devices
     Devs { dev_names: Vec<String> },
     /// a message was sent on the message bus
@@ -20,20 +121,164 @@ pub enum FromHost {
     MidiNoteOff { note: u8, channel: u8 },
     /// a midi CC param was sent on a pre-configured midi controller
     MidiCC { control: u8, param: u8, channel: u8 },
+    /// starts playing, or continues from where playback was paused or located to. like the other
+    /// remote commands, it's answered with `Ack` or `Error` if `id` is set.
+    Play {
+        #[serde(default)]
+        id: Option<u32>,
+    },
+    /// stops playing and rewinds to step 0.
+    Stop {
+        #[serde(default)]
+        id: Option<u32>,
+    },
+    /// moves playback to `step` without starting or stopping it.
+    Locate {
+        #[serde(default)]
+        id: Option<u32>,
+        step: u16,
+    },
+    /// starts playing the pattern from the row `step`.
+    Launch {
+        #[serde(default)]
+        id: Option<u32>,
+        step: u16,
+    },
+    SetTempo {
+        #[serde(default)]
+        id: Option<u32>,
+        bpm: u16,
+    },
+    /// mutes the track, or toggles its mute if `value` is `None`.
+    Mute {
+        #[serde(default)]
+        id: Option<u32>,
+        track: u8,
+        #[serde(default)]
+        value: Option<bool>,
+    },
+    /// solos the track, or toggles its solo if `value` is `None`.
+    Solo {
+        #[serde(default)]
+        id: Option<u32>,
+        track: u8,
+        #[serde(default)]
+        value: Option<bool>,
+    },
+    /// asks for `len` of the track's steps from `from` on, answered with `FromTracker::Steps`.
+    ReadSteps {
+        #[serde(default)]
+        id: Option<u32>,
+        track: u8,
+        from: u16,
+        len: u16,
+    },
+    /// writes steps over the track from `from` on, in the same form `FromTracker::Steps` sends
+    /// them. steps past the end of the track are dropped.
+    WriteSteps {
+        #[serde(default)]
+        id: Option<u32>,
+        track: u8,
+        from: u16,
+        steps: String,
+    },
+    /// asks for the files on the SD card, answered with `FromTracker::Files`.
+    ListFiles {
+        #[serde(default)]
+        id: Option<u32>,
+    },
+    /// asks for the chunk of a file that starts at `offset`, answered with
+    /// `FromTracker::FileChunk`.
+    ReadFile {
+        #[serde(default)]
+        id: Option<u32>,
+        name: String,
+        offset: u32,
+    },
+    /// a chunk of a file being pushed, see the `sync` module. `data` is hex and `crc` is the
+    /// chunk's `sync::crc32`. a chunk at offset 0 starts the file over, the rest have to follow
+    /// on from the one before.
+    WriteFile {
+        #[serde(default)]
+        id: Option<u32>,
+        name: String,
+        offset: u32,
+        data: String,
+        crc: u32,
+    },
+    /// ends a push. the file is only replaced if all `len` bytes arrived and their
+    /// `sync::crc32` is `crc`.
+    FinishFile {
+        #[serde(default)]
+        id: Option<u32>,
+        name: String,
+        len: u32,
+        crc: u32,
+    },
+    DeleteFile {
+        #[serde(default)]
+        id: Option<u32>,
+        name: String,
+    },
+    /// only log messages at least as important as `level`.
+    SetLogLevel {
+        #[serde(default)]
+        id: Option<u32>,
+        level: LogLevel,
+    },
 }
 
-#[derive(Clone, PartialEq, Eq, PartialOrd, Ord, Deserialize, Serialize, Event)]
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Deserialize, Serialize, Event)]
 pub enum FromTracker {
+    /// the tracker's half of the handshake, sent when the host opens the serial port.
+    Hello {
+        protocol_version: u16,
+        firmware_version: String,
+        #[serde(default)]
+        capabilities: Vec<String>,
+    },
     /// Log a message to the Hosts terminal
-    Log { message: String },
+    Log {
+        message: String,
+        #[serde(default)]
+        level: LogLevel,
+        /// when it was logged, in milliseconds since the tracker started.
+        #[serde(default)]
+        millis: u64,
+        /// the module of the firmware that logged it, e.g. `midi_plugin`.
+        #[serde(default)]
+        module: String,
+    },
+    /// the request from the host with this id was carried out.
+    Ack {
+        id: u32,
+    },
+    /// the request from the host with this id failed. if `id` is `None`, a frame from the host
+    /// couldn't be read or a command sent without an id failed.
+    Error {
+        #[serde(default)]
+        id: Option<u32>,
+        message: String,
+    },
     /// Request an updated list of device names from the host.
     RequestDevs,
     /// send a message to the message-bus
-    MessageBus { message: String },
+    MessageBus {
+        message: String,
+    },
     /// instructs the host to send messages that match this message.
-    ListenFor { message: String },
+    ListenFor {
+        message: String,
+    },
+    /// instructs the host to stop sending a message it was asked for with `ListenFor`.
+    StopListeningFor {
+        message: String,
+    },
     /// connects a channel to a midi dev
     Connect {
+        /// the host replies with `Ack` or `Error` carrying this id, if it's set.
+        #[serde(default)]
+        id: Option<u32>,
         /// output channel of tracker
         channel: u8,
         /// device that the midi commands should be sent to
@@ -41,6 +286,59 @@ pub enum FromTracker {
         /// the input channel on the dev that the midi should be sent to.
         dev_channel: u8,
     },
+    /// a note played by a track, `channel` being the track's output channel, the same as in
+    /// `Connect`.
+    NoteOn {
+        channel: u8,
+        note: u8,
+        vel: u8,
+    },
+    NoteOff {
+        channel: u8,
+        note: u8,
+    },
+    Cc {
+        channel: u8,
+        control: u8,
+        value: u8,
+    },
+    /// 14 bit pitch bend, 8192 being no bend.
+    PitchBend {
+        channel: u8,
+        value: u16,
+    },
+    ProgramChange {
+        channel: u8,
+        program: u8,
+    },
+    /// the answer to `FromHost::ReadSteps`. `steps` is the RON of the steps, tagged with the kind
+    /// of track they're from, e.g. `Midi([(note: Some(60), cmds: (None, None), vel: None, delay: 0)])`.
+    Steps {
+        #[serde(default)]
+        id: Option<u32>,
+        track: u8,
+        from: u16,
+        steps: String,
+    },
+    /// the answer to `FromHost::ListFiles`.
+    Files {
+        #[serde(default)]
+        id: Option<u32>,
+        files: Vec<FileInfo>,
+    },
+    /// the answer to `FromHost::ReadFile`: up to `sync::CHUNK_LEN` bytes, as hex, with their
+    /// `sync::crc32`. `file_len` and `file_crc` are for the whole file as it was when the chunk
+    /// was read, so a file that changes part way through a fetch is caught.
+    FileChunk {
+        #[serde(default)]
+        id: Option<u32>,
+        name: String,
+        offset: u32,
+        data: String,
+        crc: u32,
+        file_len: u32,
+        file_crc: u32,
+    },
 }
 
 // Devs(dev_names:["foo","bar"])
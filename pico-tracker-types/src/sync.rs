@@ -0,0 +1,87 @@
+//! the pieces shared by both ends of project sync: how files are split in to chunks, the
+//! checksums that guard them, and how chunk bytes are written in a message.
+//!
+//! a file is fetched by asking for it a chunk at a time with `FromHost::ReadFile`, each
+//! `FromTracker::FileChunk` carrying the whole file's length and checksum so the last chunk can
+//! be checked. a file is pushed with `FromHost::WriteFile` chunks, in order from offset 0, then
+//! `FromHost::FinishFile`. the tracker keeps the chunks in `TEMP_FILE` and only replaces the file
+//! once the whole of it has arrived with the right checksum. files over `MAX_FILE_LEN` are
+//! turned away.
+
+use alloc::{string::String, vec::Vec};
+
+/// the most file bytes in one chunk. as hex they're twice this, leaving room in a frame for the
+/// rest of the message.
+pub const CHUNK_LEN: usize = 1024;
+
+/// where a pushed file is kept until it's finished, 8.3 like every other name on the SD card.
+pub const TEMP_FILE: &str = "SYNC.TMP";
+
+/// the biggest file that can be pushed. projects are loaded whole, so this keeps a pushed one
+/// well inside the tracker's heap.
+pub const MAX_FILE_LEN: u32 = 128 * 1024;
+
+/// the CRC-32 (IEEE) of `bytes`, the same as zlib's `crc32`.
+pub fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = Crc32::default();
+    crc.update(bytes);
+
+    crc.finish()
+}
+
+/// a CRC-32 worked out a piece at a time, so a file can be checked without holding all of it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Crc32(u32);
+
+impl Default for Crc32 {
+    fn default() -> Self {
+        Self(!0)
+    }
+}
+
+impl Crc32 {
+    pub fn update(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 ^= *byte as u32;
+
+            // no table, files are small and flash isn't.
+            for _ in 0..8 {
+                let mask = (self.0 & 1).wrapping_neg();
+                self.0 = (self.0 >> 1) ^ (0xEDB8_8320 & mask);
+            }
+        }
+    }
+
+    /// the CRC-32 of everything passed to `update`.
+    pub fn finish(self) -> u32 {
+        !self.0
+    }
+}
+
+/// `bytes` as lower case hex, two characters a byte.
+pub fn to_hex(bytes: &[u8]) -> String {
+    const DIGITS: &[u8; 16] = b"0123456789abcdef";
+
+    bytes
+        .iter()
+        .flat_map(|byte| [DIGITS[(byte >> 4) as usize], DIGITS[(byte & 0xf) as usize]])
+        .map(char::from)
+        .collect()
+}
+
+/// the bytes in `hex`, either case. `None` if it isn't an even number of hex digits.
+pub fn from_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+
+    hex.as_bytes()
+        .chunks(2)
+        .map(|pair| {
+            let high = (pair[0] as char).to_digit(16)?;
+            let low = (pair[1] as char).to_digit(16)?;
+
+            Some((high << 4 | low) as u8)
+        })
+        .collect()
+}
@@ -0,0 +1,203 @@
+use pico_tracker_types::{
+    FromHost, FromTracker,
+    codec::{FrameDecoder, FrameError, MAX_FRAME_LEN, encode},
+};
+
+fn messages() -> Vec<FromHost> {
+    vec![
+        FromHost::Devs {
+            dev_names: vec!["foo".into(), "bar \"baz\"\n".into()],
+        },
+        FromHost::MessageBus {
+            message: "scene 2\r\n\0".into(),
+        },
+        FromHost::MidiNoteOn {
+            note: 60,
+            vel: 100,
+            channel: 1,
+        },
+        FromHost::MidiNoteOff {
+            note: 60,
+            channel: 1,
+        },
+        FromHost::MidiCC {
+            control: 7,
+            param: 127,
+            channel: 16,
+        },
+    ]
+}
+
+/// decodes every complete frame out of the decoder.
+fn drain(decoder: &mut FrameDecoder) -> Vec<Result<FromHost, FrameError>> {
+    std::iter::from_fn(|| decoder.decode()).collect()
+}
+
+/// xorshift, so the fuzz tests are repeatable.
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    fn below(&mut self, n: usize) -> usize {
+        (self.next() % n as u64) as usize
+    }
+}
+
+#[test]
+fn round_trip() {
+    for message in messages() {
+        let frame = encode(&message).unwrap();
+        assert_eq!(frame.iter().filter(|byte| **byte == b'\n').count(), 1);
+        assert_eq!(frame.last(), Some(&b'\n'));
+
+        let mut decoder = FrameDecoder::default();
+        decoder.push(&frame);
+        assert_eq!(drain(&mut decoder), vec![Ok(message)]);
+        assert_eq!(decoder.pending(), 0);
+    }
+
+    let message = FromTracker::Error {
+        id: Some(3),
+        message: "oops".into(),
+    };
+    let mut decoder = FrameDecoder::default();
+    decoder.push(&encode(&message).unwrap());
+    assert_eq!(decoder.decode(), Some(Ok(message)));
+}
+
+#[test]
+fn split_reads() {
+    let bytes: Vec<u8> = messages()
+        .iter()
+        .flat_map(|message| encode(message).unwrap())
+        .collect();
+
+    // one byte at a time
+    let mut decoder = FrameDecoder::default();
+    let mut decoded = Vec::new();
+    for byte in &bytes {
+        decoder.push(&[*byte]);
+        decoded.extend(drain(&mut decoder));
+    }
+    assert_eq!(decoded, messages().into_iter().map(Ok).collect::<Vec<_>>());
+
+    // random chunks
+    let mut rng = Rng(0x5eed);
+    for _ in 0..200 {
+        let mut decoder = FrameDecoder::default();
+        let mut decoded = Vec::new();
+        let mut rest = &bytes[..];
+        while !rest.is_empty() {
+            let n = (rng.below(64) + 1).min(rest.len());
+            decoder.push(&rest[..n]);
+            rest = &rest[n..];
+            decoded.extend(drain(&mut decoder));
+        }
+        assert_eq!(decoded, messages().into_iter().map(Ok).collect::<Vec<_>>());
+    }
+}
+
+#[test]
+fn other_terminators() {
+    let mut decoder = FrameDecoder::default();
+    decoder.push(b"MidiNoteOff(note:1,channel:2)\0\r\n\nMidiNoteOff(note:3,channel:4)\r\n");
+    assert_eq!(
+        drain(&mut decoder),
+        vec![
+            Ok(FromHost::MidiNoteOff {
+                note: 1,
+                channel: 2
+            }),
+            Ok(FromHost::MidiNoteOff {
+                note: 3,
+                channel: 4
+            }),
+        ]
+    );
+}
+
+#[test]
+fn bad_frames() {
+    let mut decoder = FrameDecoder::default();
+    decoder.push(b"NotAMessage\n\xff\xfe\nMidiNoteOff(note:1,channel:2)\n");
+    let decoded = drain(&mut decoder);
+    assert!(matches!(decoded[0], Err(FrameError::Parse(_))));
+    assert_eq!(decoded[1], Err(FrameError::NotUtf8));
+    assert_eq!(
+        decoded[2],
+        Ok(FromHost::MidiNoteOff {
+            note: 1,
+            channel: 2
+        })
+    );
+}
+
+#[test]
+fn length_limit() {
+    // a long device list is fine as long as it fits
+    let message = FromHost::Devs {
+        dev_names: (0..100).map(|i| format!("device number {i}")).collect(),
+    };
+    let frame = encode(&message).unwrap();
+    assert!(frame.len() > 512);
+    let mut decoder = FrameDecoder::default();
+    decoder.push(&frame);
+    assert_eq!(decoder.decode(), Some(Ok(message)));
+
+    let message = FromHost::MessageBus {
+        message: "x".repeat(MAX_FRAME_LEN),
+    };
+    assert_eq!(encode(&message), Err(FrameError::TooLong));
+
+    // an over long frame is reported once and dropped, the frame after it still comes through
+    let mut decoder = FrameDecoder::new(32);
+    decoder.push(&[b'a'; 20]);
+    assert_eq!(drain(&mut decoder), vec![]);
+    decoder.push(&[b'a'; 20]);
+    assert_eq!(drain(&mut decoder), vec![Err(FrameError::TooLong)]);
+    decoder.push(&[b'a'; 100]);
+    assert_eq!(drain(&mut decoder), vec![]);
+    assert_eq!(decoder.pending(), 0);
+    decoder.push(b"aaa\nMidiNoteOff(note:1,channel:2)\n");
+    assert_eq!(
+        drain(&mut decoder),
+        vec![Ok(FromHost::MidiNoteOff {
+            note: 1,
+            channel: 2
+        })]
+    );
+}
+
+#[test]
+fn fuzz() {
+    let mut rng = Rng(0xf022);
+    let valid = encode(&messages()[2]).unwrap();
+
+    for _ in 0..500 {
+        let mut decoder = FrameDecoder::new(256);
+
+        // garbage, then a delimiter so the decoder can resync, then a real message.
+        let garbage: Vec<u8> = (0..rng.below(600)).map(|_| rng.next() as u8).collect();
+        let mut bytes = garbage;
+        bytes.push(b'\n');
+        bytes.extend_from_slice(&valid);
+
+        let mut decoded = Vec::new();
+        let mut rest = &bytes[..];
+        while !rest.is_empty() {
+            let n = (rng.below(100) + 1).min(rest.len());
+            decoder.push(&rest[..n]);
+            rest = &rest[n..];
+            decoded.extend(drain(&mut decoder));
+            assert!(decoder.pending() <= 256 + 100);
+        }
+
+        assert_eq!(decoded.last(), Some(&Ok(messages()[2].clone())));
+    }
+}
@@ -0,0 +1,212 @@
+use pico_tracker_types::{
+    FromHost, FromTracker, LogLevel, PROTOCOL_VERSION,
+    codec::{FrameDecoder, FrameError, encode},
+    is_compatible,
+};
+
+fn decode<T: serde::de::DeserializeOwned>(text: &str) -> Result<T, FrameError> {
+    let mut decoder = FrameDecoder::default();
+    decoder.push(text.as_bytes());
+    decoder.push(b"\n");
+    decoder.decode().unwrap()
+}
+
+#[test]
+fn hello_round_trip() {
+    let hello = FromTracker::Hello {
+        protocol_version: PROTOCOL_VERSION,
+        firmware_version: "0.1.0".into(),
+        capabilities: vec!["notes-in".into()],
+    };
+    let frame = encode(&hello).unwrap();
+    let mut decoder = FrameDecoder::default();
+    decoder.push(&frame);
+    assert_eq!(decoder.decode(), Some(Ok(hello)));
+
+    assert!(is_compatible(PROTOCOL_VERSION));
+    assert!(!is_compatible(PROTOCOL_VERSION + 1));
+}
+
+#[test]
+fn older_peers() {
+    // fields added after a peer was written are filled in with defaults
+    assert_eq!(
+        decode::<FromHost>("Hello(protocol_version:1)"),
+        Ok(FromHost::Hello {
+            protocol_version: 1,
+            host_version: String::new(),
+            capabilities: Vec::new(),
+        })
+    );
+    assert_eq!(
+        decode::<FromTracker>(r#"Connect(channel:1,dev:"synth",dev_channel:2)"#),
+        Ok(FromTracker::Connect {
+            id: None,
+            channel: 1,
+            dev: "synth".into(),
+            dev_channel: 2,
+        })
+    );
+    assert_eq!(
+        decode::<FromHost>(r#"Error(message:"bad")"#),
+        Ok(FromHost::Error {
+            id: None,
+            message: "bad".into(),
+        })
+    );
+}
+
+#[test]
+fn newer_peers() {
+    // unknown fields and capabilities are ignored
+    assert_eq!(
+        decode::<FromHost>(
+            r#"Hello(protocol_version:1,host_version:"9.9",capabilities:["teleport"],colour:"red")"#
+        ),
+        Ok(FromHost::Hello {
+            protocol_version: 1,
+            host_version: "9.9".into(),
+            capabilities: vec!["teleport".into()],
+        })
+    );
+    assert_eq!(
+        decode::<FromHost>("Ack(id:4,extra:true)"),
+        Ok(FromHost::Ack { id: 4 })
+    );
+
+    // unknown variants fail to parse, without stopping the messages after them
+    let mut decoder = FrameDecoder::default();
+    decoder.push(b"Teleport(to:3)\nAck(id:1)\n");
+    assert!(matches!(
+        decoder.decode::<FromHost>(),
+        Some(Err(FrameError::Parse(_)))
+    ));
+    assert_eq!(decoder.decode(), Some(Ok(FromHost::Ack { id: 1 })));
+}
+
+#[test]
+fn track_notes() {
+    let notes = [
+        FromTracker::NoteOn {
+            channel: 2,
+            note: 60,
+            vel: 100,
+        },
+        FromTracker::NoteOff {
+            channel: 2,
+            note: 60,
+        },
+        FromTracker::Cc {
+            channel: 0,
+            control: 65,
+            value: 127,
+        },
+        FromTracker::PitchBend {
+            channel: 7,
+            value: 16383,
+        },
+        FromTracker::ProgramChange {
+            channel: 1,
+            program: 12,
+        },
+    ];
+    let mut decoder = FrameDecoder::default();
+
+    for note in notes.iter() {
+        decoder.push(&encode(note).unwrap());
+    }
+
+    for note in notes {
+        assert_eq!(decoder.decode(), Some(Ok(note)));
+    }
+
+    assert_eq!(
+        decode::<FromTracker>("NoteOn(channel:3,note:48,vel:90)"),
+        Ok(FromTracker::NoteOn {
+            channel: 3,
+            note: 48,
+            vel: 90,
+        })
+    );
+}
+
+#[test]
+fn remote_commands() {
+    assert_eq!(
+        decode::<FromHost>("Play()"),
+        Ok(FromHost::Play { id: None })
+    );
+    assert_eq!(
+        decode::<FromHost>("Locate(id:Some(4),step:16)"),
+        Ok(FromHost::Locate {
+            id: Some(4),
+            step: 16,
+        })
+    );
+    assert_eq!(
+        decode::<FromHost>("Mute(track:2)"),
+        Ok(FromHost::Mute {
+            id: None,
+            track: 2,
+            value: None,
+        })
+    );
+
+    // steps are RON inside of a RON string, so they still fit on one line
+    let write = FromHost::WriteSteps {
+        id: Some(9),
+        track: 1,
+        from: 4,
+        steps: "Midi([\n(note: Some(60), cmds: (None, None), vel: None, delay: 0)])".into(),
+    };
+    let frame = encode(&write).unwrap();
+    assert_eq!(frame.iter().filter(|byte| **byte == b'\n').count(), 1);
+
+    let mut decoder = FrameDecoder::default();
+    decoder.push(&frame);
+    assert_eq!(decoder.decode(), Some(Ok(write)));
+
+    let steps = FromTracker::Steps {
+        id: Some(9),
+        track: 1,
+        from: 4,
+        steps: r#"SF2([(note: None, cmds: (Custom(Atk(2)), None), vel: None, delay: 0)])"#.into(),
+    };
+    decoder.push(&encode(&steps).unwrap());
+    assert_eq!(decoder.decode(), Some(Ok(steps)));
+}
+
+#[test]
+fn log_levels() {
+    // a log from older firmware is info from nowhere in particular
+    assert_eq!(
+        decode::<FromTracker>("Log(message:\"project loaded\")"),
+        Ok(FromTracker::Log {
+            message: "project loaded".into(),
+            level: LogLevel::Info,
+            millis: 0,
+            module: String::new(),
+        })
+    );
+    assert_eq!(
+        decode::<FromTracker>("Log(message:\"x\",level:Error,millis:1500,module:\"sync\")"),
+        Ok(FromTracker::Log {
+            message: "x".into(),
+            level: LogLevel::Error,
+            millis: 1500,
+            module: "sync".into(),
+        })
+    );
+    assert_eq!(
+        decode::<FromHost>("SetLogLevel(level:Trace)"),
+        Ok(FromHost::SetLogLevel {
+            id: None,
+            level: LogLevel::Trace,
+        })
+    );
+
+    // more important levels come first
+    assert!(LogLevel::Error < LogLevel::Warn);
+    assert!(LogLevel::Debug < LogLevel::Trace);
+    assert_eq!(LogLevel::default(), LogLevel::Info);
+}
@@ -0,0 +1,65 @@
+use pico_tracker_types::{
+    FileInfo, FromHost, FromTracker,
+    codec::{MAX_FRAME_LEN, encode},
+    sync::{CHUNK_LEN, Crc32, crc32, from_hex, to_hex},
+};
+
+#[test]
+fn checksums() {
+    // the standard check value for CRC-32
+    assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+    assert_eq!(crc32(b""), 0);
+    assert_ne!(crc32(b"PROJECT.RON"), crc32(b"PROJECT.RO"));
+
+    // worked out a piece at a time it's the same
+    let mut crc = Crc32::default();
+    crc.update(b"1234");
+    crc.update(b"");
+    crc.update(b"56789");
+    assert_eq!(crc.finish(), 0xCBF4_3926);
+}
+
+#[test]
+fn hex_data() {
+    let bytes = [0x00, 0x7f, 0x80, 0xff, b'\n'];
+    assert_eq!(to_hex(&bytes), "007f80ff0a");
+    assert_eq!(from_hex("007F80ff0a"), Some(bytes.to_vec()));
+    assert_eq!(from_hex(""), Some(Vec::new()));
+    assert_eq!(from_hex("abc"), None);
+    assert_eq!(from_hex("zz"), None);
+}
+
+#[test]
+fn full_chunks_fit_a_frame() {
+    let data = vec![0xff; CHUNK_LEN];
+    let name = "LONGNAME.RON".to_string();
+
+    let chunk = FromTracker::FileChunk {
+        id: Some(u32::MAX),
+        name: name.clone(),
+        offset: u32::MAX,
+        data: to_hex(&data),
+        crc: u32::MAX,
+        file_len: u32::MAX,
+        file_crc: u32::MAX,
+    };
+    assert!(encode(&chunk).unwrap().len() <= MAX_FRAME_LEN);
+
+    let write = FromHost::WriteFile {
+        id: Some(u32::MAX),
+        name,
+        offset: u32::MAX,
+        data: to_hex(&data),
+        crc: u32::MAX,
+    };
+    assert!(encode(&write).unwrap().len() <= MAX_FRAME_LEN);
+
+    let files = FromTracker::Files {
+        id: None,
+        files: vec![FileInfo {
+            name: "PROJECT.RON".into(),
+            len: 2048,
+        }],
+    };
+    assert!(encode(&files).is_ok());
+}
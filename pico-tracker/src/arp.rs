@@ -0,0 +1,119 @@
+use crate::MidiNote;
+use bevy::prelude::*;
+use core::fmt::Display;
+use rand::{Rng, rngs::SmallRng};
+use serde::{Deserialize, Serialize};
+use strum_macros::Display;
+
+#[derive(
+    Clone, Copy, Default, Debug, PartialEq, Eq, PartialOrd, Hash, Display, Serialize, Deserialize,
+)]
+pub enum ArpMode {
+    #[default]
+    #[strum(to_string = "U")]
+    Up,
+    #[strum(to_string = "D")]
+    Down,
+    /// up then back down, without repeating the top and bottom notes.
+    #[strum(to_string = "B")]
+    UpDown,
+    #[strum(to_string = "R")]
+    Random,
+    /// in the order the notes were played, or the order of the chord before voicing.
+    #[strum(to_string = "P")]
+    AsPlayed,
+}
+
+impl ArpMode {
+    pub const ALL: [ArpMode; 5] = [
+        Self::Up,
+        Self::Down,
+        Self::UpDown,
+        Self::Random,
+        Self::AsPlayed,
+    ];
+
+    pub fn next(&self) -> Self {
+        let i = Self::ALL.iter().position(|mode| mode == self).unwrap_or(0);
+
+        Self::ALL[(i + 1) % Self::ALL.len()]
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Hash, Serialize, Deserialize)]
+pub struct Arp {
+    pub mode: ArpMode,
+    /// sync pulses from one note to the next.
+    pub rate: u8,
+    /// how many octaves the notes are repeated over.
+    pub octaves: u8,
+    /// how long each note is held for, as a percentage of `rate`.
+    pub gate: u8,
+    /// how many steps the arp plays for.
+    pub steps: u8,
+}
+
+impl Default for Arp {
+    fn default() -> Self {
+        Self {
+            mode: ArpMode::Up,
+            rate: 3,
+            octaves: 1,
+            gate: 50,
+            steps: 1,
+        }
+    }
+}
+
+impl Arp {
+    /// how many pulses after it's played each note is released. `None` if it's held until the
+    /// next note.
+    pub fn gate_pulses(&self) -> Option<usize> {
+        (self.gate < 100).then(|| (self.rate as usize * self.gate as usize / 100).max(1))
+    }
+
+    /// the `i`th note of the arp over `notes`.
+    pub fn note_at(&self, notes: &[MidiNote], i: usize, rng: &mut SmallRng) -> Option<MidiNote> {
+        let mut notes = notes.to_vec();
+
+        if self.mode != ArpMode::AsPlayed {
+            notes.sort();
+        }
+
+        let pool: Vec<MidiNote> = (0..self.octaves.max(1))
+            .flat_map(|octave| {
+                notes
+                    .iter()
+                    .map(move |note| *note as usize + 12 * octave as usize)
+            })
+            .filter(|note| *note < 128)
+            .map(|note| note as MidiNote)
+            .collect();
+        let n = pool.len();
+
+        if n == 0 {
+            return None;
+        }
+
+        let i = match self.mode {
+            ArpMode::Up | ArpMode::AsPlayed => i % n,
+            ArpMode::Down => n - 1 - i % n,
+            ArpMode::UpDown if n > 1 => {
+                let i = i % (2 * n - 2);
+
+                if i < n { i } else { 2 * n - 2 - i }
+            }
+            ArpMode::UpDown => 0,
+            ArpMode::Random => rng.random_range(0..n),
+        };
+
+        Some(pool[i])
+    }
+}
+
+/// shown in the command's cell as `Arp` followed by the mode.
+impl Display for Arp {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "Arp{}", self.mode)
+    }
+}
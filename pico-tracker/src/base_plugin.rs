@@ -6,14 +6,22 @@ use crate::hal::{
     powman::Powman,
     watchdog::Watchdog,
 };
+use crate::{
+    host::{HostClosed, hello},
+    logging::Log,
+    midi_monitor::UsbMidiIn,
+};
+use alloc::collections::VecDeque;
 use bevy::prelude::*;
 use display_interface_spi::SPIInterface;
 use embedded_hal::spi::MODE_3;
 use embedded_hal_bus::spi::ExclusiveDevice;
 use embedded_sdmmc::{SdCard, VolumeManager};
 use fugit::RateExtU32;
-use pico_tracker_types::ron;
-use pico_tracker_types::{FromHost, FromTracker};
+use pico_tracker_types::{
+    FromHost, FromTracker,
+    codec::{FrameDecoder, MAX_FRAME_LEN, encode},
+};
 use picocalc_bevy::{
     Display, DummyTimesource, FileSystemStruct, Keeb, KeyPresses, LoggingEnv, PicoTimer,
     XTAL_FREQ_HZ, clear_display, get_key_report,
@@ -21,12 +29,12 @@ use picocalc_bevy::{
     start_timer, tick_timer,
 };
 use usb_device::{
-    bus::UsbBusAllocator,
+    bus::{UsbBus, UsbBusAllocator},
     device::{StringDescriptors, UsbDeviceBuilder, UsbVidPid},
 };
 use usbd_midi::{
     CableNumber, UsbMidiClass,
-    message::{Channel, Message, Note, Velocity},
+    message::{Channel, ControlFunction, Message, Note, U7, U14, Velocity},
 };
 use usbd_serial::SerialPort;
 
@@ -196,45 +204,64 @@ impl Plugin for BasePlugin {
                 .unwrap()
                 .build();
 
+            // messages from the host can be split over any number of reads.
+            let mut decoder = FrameDecoder::default();
+            // whether the host has the serial port open.
+            let mut host_open = false;
+            let mut to_host = HostQueue::default();
+
             loop {
                 // let _ = usb_dev.poll(&mut [&mut serial]);
                 let _ = usb_dev.poll(&mut [&mut midi, &mut serial]);
                 // let _ = usb_dev.poll(&mut [&mut midi]);
 
-                let ser_write = |serial: &mut SerialPort<_>, message: String| {
-                    let res = serial.write(&message.into_bytes());
-                    _ = serial.write(&['\n' as u8, '\r' as u8]);
-
-                    res
-                };
+                // the host just opened the serial port, say hello. once it closes it, forget it.
+                let dtr = serial.dtr();
+                if dtr && !host_open {
+                    to_host.push(&hello());
+                } else if !dtr && host_open {
+                    to_host.clear();
+                    app.world_mut().send_event(HostClosed);
+                }
+                host_open = dtr;
 
                 let mut buf = [0u8; 512];
 
                 if let Ok(count) = serial.read(&mut buf[..]) {
-                    let raw_ron_msg: String = buf[..count]
-                        .into_iter()
-                        .filter_map(|byte| {
-                            let byte = *byte;
-                            // for the terminating Null byte.
-                            if byte != 0 { Some(byte as char) } else { None }
-                        })
-                        .collect();
-
-                    if let Ok(message) = ron::from_str(&raw_ron_msg) {
-                        let world = app.world_mut();
-                        if let Some(ref mut events) = world.get_resource_mut::<Events<FromHost>>() {
-                            events.send(message);
+                    decoder.push(&buf[..count]);
+                };
+
+                while let Some(message) = decoder.decode::<FromHost>() {
+                    match message {
+                        Ok(message) => {
+                            let world = app.world_mut();
+                            if let Some(ref mut events) =
+                                world.get_resource_mut::<Events<FromHost>>()
+                            {
+                                events.send(message);
+                            }
+                        }
+                        Err(e) => {
+                            let reply = FromTracker::Error {
+                                id: None,
+                                message: e.to_string(),
+                            };
+                            to_host.push(&reply);
                         }
-                    } else {
-                        // let _ = ron::to_string(&FromTracker::Log {
-                        //     message: format!("failed to read to ron: {raw_ron_msg}"),
-                        // })
-                        // .map(|msg| ser_write(&mut serial, msg));
                     }
-                };
+                }
 
                 let mut buf = [0u8; 64];
-                _ = midi.read(&mut buf);
+
+                if let Ok(count) = midi.read(&mut buf) {
+                    let world = app.world_mut();
+
+                    for packet in buf[..count].chunks_exact(4) {
+                        if let Some(midi_in) = UsbMidiIn::from_packet(packet) {
+                            world.send_event(midi_in);
+                        }
+                    }
+                }
 
                 // let _ = usb_dev.poll(&mut [&mut midi, &mut serial]);
                 app.update();
@@ -242,17 +269,8 @@ impl Plugin for BasePlugin {
 
                 {
                     let world = app.world_mut();
+                    // the logging plugin passes these on to the host as `Log`s.
                     if let Some(ref mut events) = world.get_resource_mut::<Events<LoggingEnv>>() {
-                        for event in events.iter_current_update_events() {
-                            // let _ = serial.write(&event.msg.clone().into_bytes());
-                            // let _ = serial.write(&['\n' as u8, '\r' as u8]);
-
-                            let _ = ron::to_string(&FromTracker::Log {
-                                message: event.msg.clone(),
-                            })
-                            .map(|msg| ser_write(&mut serial, msg));
-                        }
-
                         events.update();
                     }
                     // }
@@ -265,16 +283,22 @@ impl Plugin for BasePlugin {
                             // let _ = serial.write(&event.msg.clone().into_bytes());
                             // let _ = serial.write(&['\n' as u8, '\r' as u8]);
 
-                            let _ = ron::to_string(event).map(|msg| ser_write(&mut serial, msg));
+                            if host_open {
+                                to_host.push(event);
+                            }
                         }
 
                         events.update();
                     }
+
+                    to_host.flush(&mut serial);
                     // }
                     //
                     // {
                     //     let world = app.world_mut();
-                    if let Some(ref mut events) = world.get_resource_mut::<Events<MidiEnv>>() {
+                    let mut midi_errors = Vec::new();
+
+                    if let Some(ref mut events) = world.get_resource_mut::<Events<UsbMidiOut>>() {
                         // let cables = [
                         //     CableNumber::Cable0,
                         //     CableNumber::Cable1,
@@ -294,17 +318,10 @@ impl Plugin for BasePlugin {
                         //     CableNumber::Cable15,
                         // ];
                         for event in events.iter_current_update_events() {
-                            let packet = match *event {
-                                MidiEnv::On { note, vel } => Message::NoteOn(
-                                    Channel::Channel1,
-                                    Note::try_from(note).unwrap(),
-                                    Velocity::try_from(vel).unwrap(),
-                                ),
-                                MidiEnv::Off { note } => Message::NoteOff(
-                                    Channel::Channel1,
-                                    Note::try_from(note).unwrap(),
-                                    Velocity::try_from(120).unwrap(),
-                                ),
+                            let Some(packet) = usb_message(event) else {
+                                midi_errors
+                                    .push(Log::error(format!("dropped invalid midi {event:?}")));
+                                continue;
                             };
 
                             // usb_dev.poll(&mut [&mut midi, &mut serial]);
@@ -312,7 +329,7 @@ impl Plugin for BasePlugin {
                             if let Err(e) =
                                 midi.send_packet(packet.clone().into_packet(CableNumber::Cable0))
                             {
-                                _ = ser_write(&mut serial, format!("{e:?}"));
+                                midi_errors.push(Log::error(format!("{e:?}")));
                             }
 
                             // usb_dev.poll(&mut [&mut midi, &mut serial]);
@@ -322,6 +339,11 @@ impl Plugin for BasePlugin {
 
                         events.update();
                     }
+
+                    // logged next frame, so they're kept for the log screen too.
+                    for error in midi_errors {
+                        world.send_event(error);
+                    }
                 }
 
                 if let Some(exit) = app.should_exit() {
@@ -331,7 +353,8 @@ impl Plugin for BasePlugin {
         })
         .add_event::<LoggingEnv>()
         .add_event::<MidiOutEnv>()
-        .add_event::<MidiEnv>()
+        .add_event::<UsbMidiOut>()
+        .add_event::<UsbMidiIn>()
         .add_event::<FromHost>()
         .add_event::<FromTracker>()
         .insert_non_send_resource(Keeb {
@@ -356,13 +379,105 @@ impl Plugin for BasePlugin {
     }
 }
 
+/// how many bytes of frames can wait for room in the serial port's buffer. frames that don't fit
+/// are dropped whole, so the host never gets part of one.
+const HOST_QUEUE_LEN: usize = 4 * MAX_FRAME_LEN;
+
+/// frames waiting to be written to the host. the serial port's buffer is smaller than a long
+/// frame, so they're written a bit at a time as it empties.
+#[derive(Default)]
+struct HostQueue(VecDeque<u8>);
+
+impl HostQueue {
+    /// queues a message as a single frame.
+    fn push(&mut self, message: &FromTracker) {
+        let Ok(frame) = encode(message) else {
+            return;
+        };
+
+        if self.0.len() + frame.len() <= HOST_QUEUE_LEN {
+            self.0.extend(frame);
+        }
+    }
+
+    /// writes as much as the serial port will take without waiting, the rest is written on a
+    /// later loop.
+    fn flush<B: UsbBus>(&mut self, serial: &mut SerialPort<B>) {
+        while !self.0.is_empty() {
+            match serial.write(self.0.as_slices().0) {
+                Ok(0) | Err(_) => return,
+                Ok(n) => {
+                    self.0.drain(..n);
+                }
+            }
+        }
+    }
+
+    fn clear(&mut self) {
+        self.0.clear();
+    }
+}
+
 #[derive(Event, Clone, Eq, PartialEq, PartialOrd, Ord)]
 pub struct MidiOutEnv {
     pub msg: String,
 }
 
-#[derive(Event, Clone, Copy, Eq, PartialEq, PartialOrd, Ord)]
+#[derive(Event, Clone, Copy, Debug, Eq, PartialEq, PartialOrd, Ord)]
 pub enum MidiEnv {
-    On { note: u8, vel: u8 },
-    Off { note: u8 },
+    On {
+        note: u8,
+        vel: u8,
+    },
+    Off {
+        note: u8,
+    },
+    /// 14 bit pitch bend, 8192 being no bend.
+    PitchBend {
+        value: u16,
+    },
+    Cc {
+        cc: u8,
+        value: u8,
+    },
+    Program {
+        program: u8,
+    },
+}
+
+/// midi to send over usb.
+#[derive(Event, Clone, Copy, Debug, Eq, PartialEq)]
+pub struct UsbMidiOut {
+    /// 0 based.
+    pub channel: u8,
+    pub msg: MidiEnv,
+}
+
+/// the usb midi message for `event`, `None` if one of its values is out of range.
+fn usb_message(event: &UsbMidiOut) -> Option<Message> {
+    let channel = Channel::try_from(event.channel).ok()?;
+
+    Some(match event.msg {
+        MidiEnv::On { note, vel } => Message::NoteOn(
+            channel,
+            Note::try_from(note).ok()?,
+            Velocity::try_from(vel).ok()?,
+        ),
+        MidiEnv::Off { note } => Message::NoteOff(
+            channel,
+            Note::try_from(note).ok()?,
+            Velocity::try_from(120).ok()?,
+        ),
+        MidiEnv::PitchBend { value } => {
+            Message::PitchWheelChange(channel, U14::try_from(value).ok()?)
+        }
+        MidiEnv::Cc { cc, value } => Message::ControlChange(
+            channel,
+            ControlFunction(U7::try_from(cc).ok()?),
+            U7::try_from(value).ok()?,
+        ),
+        MidiEnv::Program { program } => {
+            Message::ProgramChange(channel, U7::try_from(program).ok()?)
+        }
+    })
 }
@@ -0,0 +1,102 @@
+use bevy::prelude::*;
+use core::fmt::Display;
+use serde::{Deserialize, Serialize};
+
+/// the 14 bit pitch bend value for no bend.
+pub const BEND_CENTER: u16 = 8192;
+/// midi portamento on/off CC.
+pub const PORTA_CC: u8 = 65;
+/// midi portamento time CC.
+pub const PORTA_TIME_CC: u8 = 5;
+
+/// bends the pitch of the track, straight away or ramping there over the rest of the step.
+#[derive(Clone, Copy, Default, Debug, PartialEq, Eq, PartialOrd, Hash, Serialize, Deserialize)]
+pub struct Bend {
+    /// how far to bend, -64 is all the way down and 63 all the way up.
+    pub amount: i8,
+    /// glide to the bend over the rest of the step instead of jumping to it.
+    #[serde(default)]
+    pub ramp: bool,
+}
+
+impl Bend {
+    /// the 14 bit pitch bend value of the bend.
+    pub fn value(&self) -> u16 {
+        (BEND_CENTER as i32 + self.amount as i32 * 128).clamp(0, 16383) as u16
+    }
+
+    /// nudges the amount, 8 at a time vertically and 1 at a time horizontally.
+    pub fn adjust(&mut self, vertical: isize, horizontal: isize) {
+        self.amount = (self.amount as isize + vertical * 8 + horizontal).clamp(-64, 63) as i8;
+    }
+}
+
+/// shown as `B`, or `~` for a ramp, followed by the signed amount, e.g. `~-32`.
+impl Display for Bend {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        let code = format!("{}{:+}", if self.ramp { '~' } else { 'B' }, self.amount);
+
+        write!(f, "{code:-<4}")
+    }
+}
+
+/// turns portamento on with the given glide time, or off when the time is 0.
+#[derive(Clone, Copy, Default, Debug, PartialEq, Eq, PartialOrd, Hash, Serialize, Deserialize)]
+pub struct Porta {
+    /// the portamento time CC value, 0-127.
+    pub time: u8,
+}
+
+impl Porta {
+    /// the CCs to send, as `(cc, value)` pairs. the time is only sent when portamento is on.
+    pub fn ccs(&self) -> Vec<(u8, u8)> {
+        if self.time == 0 {
+            alloc::vec![(PORTA_CC, 0)]
+        } else {
+            alloc::vec![(PORTA_TIME_CC, self.time.min(127)), (PORTA_CC, 127)]
+        }
+    }
+
+    /// nudges the time, 8 at a time vertically and 1 at a time horizontally.
+    pub fn adjust(&mut self, vertical: isize, horizontal: isize) {
+        self.time = (self.time as isize + vertical * 8 + horizontal).clamp(0, 127) as u8;
+    }
+}
+
+/// shown as `G` followed by the time, or `GOff`.
+impl Display for Porta {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        if self.time == 0 {
+            write!(f, "GOff")
+        } else {
+            write!(f, "G{:0>3}", self.time)
+        }
+    }
+}
+
+/// a glide from one pitch bend value to another.
+#[derive(Clone, Copy, Default, Debug, PartialEq, Eq, Hash)]
+pub struct BendRamp {
+    pub from: u16,
+    pub to: u16,
+    /// the sync pulse the ramp started on.
+    pub start_pulse: usize,
+    /// how many pulses it takes to get to `to`.
+    pub pulses: usize,
+}
+
+impl BendRamp {
+    /// the bend value `n_pulses` in to the ramp, held at `to` once it's done.
+    pub fn value_at(&self, n_pulses: usize) -> u16 {
+        let t = n_pulses
+            .saturating_sub(self.start_pulse)
+            .min(self.pulses.max(1)) as i32;
+        let (from, to) = (self.from as i32, self.to as i32);
+
+        (from + (to - from) * t / self.pulses.max(1) as i32) as u16
+    }
+
+    pub fn is_done(&self, n_pulses: usize) -> bool {
+        n_pulses >= self.start_pulse + self.pulses
+    }
+}
@@ -14,16 +14,42 @@ use embedded_graphics::{
 use hal::entry;
 // use picocalc_bevy::PicoCalcDefaultPlugins;
 use embedded_graphics::Drawable;
-use picocalc_bevy::{Display, KeyPresses, LoggingEnv as Log, Visible, keys::*};
+use pico_tracker_types::{FromTracker, LogLevel, capability};
+use picocalc_bevy::{Display, KeyPresses, Visible, keys::*};
 use picocalc_tracker_lib::{
-    CHAR_H, COL_W, CmdPallet, EdittingCell, FirstViewTrack, N_STEPS, Track, TrackID,
-    base_plugin::{BasePlugin, MidiEnv},
+    CELLS_PER_TRACK, CHAR_H, COL_W, ClockSource, CmdPallet, EditMode, EdittingCell, FirstViewTrack,
+    N_STEPS, N_TRACKS, OnScreen, PreviewStep, Screen, SetTrackFlag, StatusLine, Step,
+    TRACKS_ON_SCREEN, Tempo, Track, TrackFlag, TrackID, TrackOutput, TrackProgram, TrackerCmd,
+    Transport, TransportCmd, TransportState,
+    arp::Arp,
+    base_plugin::BasePlugin,
+    bend::{Bend, Porta},
+    bus::BusMsg,
+    chord::{Chord, ChordQuality},
+    clipboard::{Block, Clipboard, clear_steps},
+    cond::{Cond, Fill},
     display_midi_note,
     embedded::{Shape, TextComponent},
     exit, hal,
-    midi_plugin::{BPQ, MidiOutPlugin, SyncPulse, get_step_num},
-    row_from_line, x_from_col,
+    history::{EditKind, History, HistoryBudget},
+    host::{HostLink, HostPlugin},
+    logging::{LogBuffer, LoggingPlugin, Uptime, Verbosity},
+    midi_monitor::{MidiDirection, MidiLink, MidiMonitor, MidiMonitorPlugin},
+    midi_plugin::{
+        BPQ, MidiOutPlugin, SoundingNotes, SyncPulse, TrackMidi, TrackOut, get_step_num,
+        pulses_per_step, release_notes, send_program, song_position,
+    },
+    program::Program,
+    project::{ProjectEvent, ProjectPlugin},
+    roll::Roll,
+    routing::{HostDevs, Route, RouteStatus, TrackRoute, request_devs},
+    row_from_line,
+    scale::{Key, Scale},
+    scroll_view_to, track_from_cursor_col,
+    transform::{Transform, transpose_note},
+    x_from_col,
 };
+use rand::{SeedableRng, rngs::SmallRng};
 
 // pub use picocalc_bevy::hal;
 
@@ -36,15 +62,50 @@ pub static IMAGE_DEF: hal::block::ImageDef = hal::block::ImageDef::secure_exe();
 static HEAP: Heap = Heap::empty();
 const HEAP_SIZE: usize = 256 * 1024;
 
-#[derive(Resource, Clone, Copy, Eq, PartialEq, PartialOrd, Ord, Deref, DerefMut)]
-pub struct Playing(pub bool);
-
 #[derive(Component, Clone, Copy, Eq, PartialEq, PartialOrd, Ord)]
 pub struct PlayingMarker;
 
+#[derive(Component, Clone, Copy, Eq, PartialEq, PartialOrd, Ord)]
+pub struct StatusBar;
+
 #[derive(Component, Clone, Copy, Eq, PartialEq, PartialOrd, Ord)]
 pub struct DevDisplay;
 
+/// a line of the routing screen that changes.
+#[derive(Component, Clone, Copy, Eq, PartialEq, PartialOrd, Ord)]
+pub enum RoutingLine {
+    /// the host and how many devices it has.
+    Header,
+    /// a track's route.
+    Row(usize),
+    /// why the selected track's route failed.
+    Detail,
+}
+
+/// how many log entries the log screen shows at once.
+const LOG_ROWS: usize = 15;
+
+/// a line of the log screen.
+#[derive(Component, Clone, Copy, Eq, PartialEq, PartialOrd, Ord)]
+pub enum LogLine {
+    /// the host and the verbosity.
+    Header,
+    /// an entry, counting down from the top of the screen.
+    Row(usize),
+}
+
+/// how many midi messages the midi monitor screen shows at once.
+const MIDI_ROWS: usize = 15;
+
+/// a line of the midi monitor screen.
+#[derive(Component, Clone, Copy, Eq, PartialEq, PartialOrd, Ord)]
+pub enum MidiLine {
+    /// the filters, and whether the monitor is paused.
+    Header,
+    /// a message, counting down from the top of the screen.
+    Row(usize),
+}
+
 #[derive(Component, Clone, Copy, Eq, PartialEq, PartialOrd, Ord)]
 pub struct CellMarker {
     track: u8,
@@ -73,6 +134,34 @@ pub struct CursorLocation(pub usize, pub usize);
 #[derive(Resource, Default, Clone, Copy, Eq, PartialEq, PartialOrd, Ord)]
 pub struct DisplayStart(pub usize);
 
+/// the (track, step) corner of the block selection that stays put while the cursor moves.
+#[derive(Resource, Default, Clone, Copy, Eq, PartialEq, PartialOrd, Ord)]
+pub struct SelectionAnchor(pub Option<(usize, usize)>);
+
+/// the track selected on the routing screen.
+#[derive(Resource, Default, Clone, Copy, Eq, PartialEq, PartialOrd, Ord)]
+pub struct RoutingCursor(pub usize);
+
+/// how many entries the log screen is scrolled back from the newest.
+#[derive(Resource, Default, Clone, Copy, Eq, PartialEq, PartialOrd, Ord)]
+pub struct LogScroll(pub usize);
+
+/// how many messages the midi monitor screen is scrolled back from the newest.
+#[derive(Resource, Default, Clone, Copy, Eq, PartialEq, PartialOrd, Ord)]
+pub struct MidiScroll(pub usize);
+
+/// random numbers for humanising. seeded with the uptime the first time they're needed, which
+/// depends on when a key was pressed, so each session humanises differently.
+#[derive(Resource, Default)]
+pub struct TransformRng(pub Option<SmallRng>);
+
+impl TransformRng {
+    pub fn get(&mut self, uptime: &Uptime) -> &mut SmallRng {
+        self.0
+            .get_or_insert_with(|| SmallRng::seed_from_u64(uptime.0))
+    }
+}
+
 #[entry]
 fn main() -> ! {
     init_heap();
@@ -80,22 +169,60 @@ fn main() -> ! {
     App::new()
         .add_plugins(BasePlugin)
         .add_plugins(MidiOutPlugin)
+        .add_plugins(HostPlugin)
+        .add_plugins(ProjectPlugin)
+        .add_plugins(LoggingPlugin)
+        .add_plugins(MidiMonitorPlugin)
         .insert_resource(CmdPallet(false))
-        .insert_resource(Playing(false))
         .insert_resource(EdittingCell(false))
         .init_resource::<FirstViewTrack>()
         .init_resource::<CursorLocation>()
         .init_resource::<DisplayStart>()
-        .add_systems(Startup, (setup_tracks, setup_track_dis, setup_cursor))
+        .init_resource::<SelectionAnchor>()
+        .init_resource::<Clipboard>()
+        .init_resource::<History>()
+        .init_resource::<HistoryBudget>()
+        .init_resource::<TransformRng>()
+        .init_resource::<Screen>()
+        .init_resource::<RoutingCursor>()
+        .init_resource::<LogScroll>()
+        .init_resource::<MidiScroll>()
+        .add_systems(
+            Startup,
+            (
+                setup_tracks,
+                setup_track_dis,
+                setup_cursor,
+                setup_status_bar,
+                setup_routing_screen,
+                setup_log_screen,
+                setup_midi_screen,
+            ),
+        )
         .add_systems(
             Update,
             (
-                // toggle_playing
-                //     .run_if(enter_just_pressed)
-                //     .run_if(shift_pressed), // DEBUG
+                transport_keys,
+                screen_keys,
+                switch_screens,
+                display_status_bar,
+                display_titles,
                 display_tracks,
                 display_line_nums,
-                move_cursor.run_if(not(enter_pressed)),
+                project_keys,
+                display_step,
+                (routing_keys, display_routing).run_if(resource_equals(Screen::Routing)),
+                (log_keys, display_log).run_if(resource_equals(Screen::Log)),
+                (midi_keys, display_midi).run_if(resource_equals(Screen::Midi)),
+            ),
+        )
+        .add_systems(
+            Update,
+            (
+                (select_block, move_cursor)
+                    .chain()
+                    .run_if(not(enter_pressed))
+                    .run_if(not(alt_pressed)),
                 (
                     edit_note.run_if(note_selected),
                     edit_cmd.run_if(not(note_selected)),
@@ -103,10 +230,17 @@ fn main() -> ! {
                     .chain()
                     .run_if(enter_pressed),
                 delete_note.run_if(note_selected),
+                track_flag_keys,
+                program_keys,
+                clipboard_keys,
+                history_keys,
+                transform_keys,
+                scale_keys,
                 display_cursor,
-                display_step,
-            ),
+            )
+                .run_if(resource_equals(Screen::Tracker)),
         )
+        .add_systems(PreUpdate, update_history_budget)
         .add_systems(PostUpdate, render)
         .run();
 
@@ -124,23 +258,9 @@ fn setup_tracks(mut cmds: Commands) {
     //
     // let track = Track::Midi { steps };
 
-    cmds.spawn((
-        TrackID {
-            id: 0,
-            playing: true,
-        },
-        // track,
-        Track::default(),
-    ));
-    cmds.spawn((
-        TrackID {
-            id: 1,
-            playing: false,
-        },
-        Track::default(),
-    ));
-    // cmds.spawn((TrackID(2), Track::default()));
-    // cmds.spawn((TrackID(3), Track::default()));
+    for id in 0..N_TRACKS {
+        cmds.spawn((TrackID { id, ..default() }, Track::default()));
+    }
 }
 
 fn setup_cursor(mut cmds: Commands) {
@@ -152,13 +272,147 @@ fn setup_cursor(mut cmds: Commands) {
             ..default()
         },
         CursorText,
+        OnScreen(Screen::Tracker),
     ));
 }
 
+fn setup_status_bar(mut cmds: Commands) {
+    cmds.spawn((
+        TextComponent {
+            point: Point::new(x_from_col(0), row_from_line(1)),
+            color: Some(Rgb565::YELLOW),
+            ..default()
+        },
+        StatusBar,
+    ));
+}
+
+/// the routing screen's text, hidden until the screen is opened.
+fn setup_routing_screen(mut cmds: Commands) {
+    let lines = [(RoutingLine::Header, 0)]
+        .into_iter()
+        .chain((0..N_TRACKS).map(|track| (RoutingLine::Row(track), track + 3)))
+        .chain([(RoutingLine::Detail, N_TRACKS + 4)]);
+
+    for (line, line_n) in lines {
+        cmds.spawn((
+            TextComponent {
+                point: Point::new(x_from_col(0), row_from_line(line_n)),
+                color: (line == RoutingLine::Header).then_some(Rgb565::CYAN),
+                ..default()
+            },
+            Visible::new(false),
+            line,
+            OnScreen(Screen::Routing),
+        ));
+    }
+
+    let help = [
+        "up/down: track  left/right: device",
+        ",/.: device channel  enter: connect",
+        "backspace: clear  o: usb/host output",
+        "ctrl+d: list the host's devices",
+        "ctrl+r/esc: back to the tracker",
+    ];
+
+    for (i, text) in help.into_iter().enumerate() {
+        cmds.spawn((
+            TextComponent {
+                text: text.into(),
+                point: Point::new(x_from_col(0), row_from_line(N_TRACKS + 6 + i)),
+                color: Some(Rgb565::YELLOW),
+                ..default()
+            },
+            Visible::new(false),
+            OnScreen(Screen::Routing),
+        ));
+    }
+}
+
+/// the log screen's text, hidden until the screen is opened.
+fn setup_log_screen(mut cmds: Commands) {
+    let lines = [(LogLine::Header, 0)]
+        .into_iter()
+        .chain((0..LOG_ROWS).map(|row| (LogLine::Row(row), row + 3)));
+
+    for (line, line_n) in lines {
+        cmds.spawn((
+            TextComponent {
+                point: Point::new(x_from_col(0), row_from_line(line_n)),
+                color: (line == LogLine::Header).then_some(Rgb565::CYAN),
+                ..default()
+            },
+            Visible::new(false),
+            line,
+            OnScreen(Screen::Log),
+        ));
+    }
+
+    let help = [
+        "up/down: scroll  ,/.: verbosity",
+        "ctrl+g/esc: back to the tracker",
+    ];
+
+    for (i, text) in help.into_iter().enumerate() {
+        cmds.spawn((
+            TextComponent {
+                text: text.into(),
+                point: Point::new(x_from_col(0), row_from_line(LOG_ROWS + 4 + i)),
+                color: Some(Rgb565::YELLOW),
+                ..default()
+            },
+            Visible::new(false),
+            OnScreen(Screen::Log),
+        ));
+    }
+}
+
+/// the midi monitor screen's text, hidden until the screen is opened.
+fn setup_midi_screen(mut cmds: Commands) {
+    let lines = [(MidiLine::Header, 0)]
+        .into_iter()
+        .chain((0..MIDI_ROWS).map(|row| (MidiLine::Row(row), row + 3)));
+
+    for (line, line_n) in lines {
+        cmds.spawn((
+            TextComponent {
+                point: Point::new(x_from_col(0), row_from_line(line_n)),
+                color: (line == MidiLine::Header).then_some(Rgb565::CYAN),
+                ..default()
+            },
+            Visible::new(false),
+            line,
+            OnScreen(Screen::Midi),
+        ));
+    }
+
+    let help = [
+        "up/down: scroll  left/right: channel",
+        ",/.: type  space: pause  bksp: clear",
+        "u/h: usb/host  >/<: sent/received",
+        "ctrl+o/esc: back to the tracker",
+    ];
+
+    for (i, text) in help.into_iter().enumerate() {
+        cmds.spawn((
+            TextComponent {
+                text: text.into(),
+                point: Point::new(x_from_col(0), row_from_line(MIDI_ROWS + 4 + i)),
+                color: Some(Rgb565::YELLOW),
+                ..default()
+            },
+            Visible::new(false),
+            OnScreen(Screen::Midi),
+        ));
+    }
+}
+
 fn setup_track_dis(mut cmds: Commands) {
-    let n_col = 2;
+    let n_col = TRACKS_ON_SCREEN as u8;
+    let row_len = TRACKS_ON_SCREEN * CELLS_PER_TRACK;
 
     for col_n in 0u8..n_col {
+        let first_cursor = col_n as usize * CELLS_PER_TRACK;
         let x_offset = x_from_col(COL_W * col_n as usize);
 
         cmds.spawn((
@@ -168,6 +422,7 @@ fn setup_track_dis(mut cmds: Commands) {
                 ..default()
             },
             TitleMarker(col_n),
+            OnScreen(Screen::Tracker),
         ));
 
         for (i, line_n) in (2..CHAR_H - 1).enumerate() {
@@ -182,6 +437,7 @@ fn setup_track_dis(mut cmds: Commands) {
                     ..default()
                 },
                 LineNumMarker { track: col_n, row },
+                OnScreen(Screen::Tracker),
             ));
 
             cmds.spawn((
@@ -192,7 +448,8 @@ fn setup_track_dis(mut cmds: Commands) {
                     ..default()
                 },
                 // Visible::new(false),
-                CursorID(i * 6 + col_n as usize * 3 + 0),
+                CursorID(i * row_len + first_cursor),
+                OnScreen(Screen::Tracker),
             ));
 
             // Note display
@@ -207,6 +464,7 @@ fn setup_track_dis(mut cmds: Commands) {
                     column: 0,
                     row,
                 },
+                OnScreen(Screen::Tracker),
             ));
 
             cmds.spawn((
@@ -218,7 +476,8 @@ fn setup_track_dis(mut cmds: Commands) {
                     ..default()
                 },
                 // Visible::new(false),
-                CursorID(i * 6 + (col_n as usize * 3) + 1),
+                CursorID(i * row_len + first_cursor + 1),
+                OnScreen(Screen::Tracker),
             ));
 
             // cmd 1
@@ -233,6 +492,7 @@ fn setup_track_dis(mut cmds: Commands) {
                     column: 1,
                     row,
                 },
+                OnScreen(Screen::Tracker),
             ));
 
             cmds.spawn((
@@ -243,7 +503,8 @@ fn setup_track_dis(mut cmds: Commands) {
                     ..default()
                 },
                 // Visible::new(false),
-                CursorID(i * 6 + (col_n as usize * 3) + 2),
+                CursorID(i * row_len + first_cursor + 2),
+                OnScreen(Screen::Tracker),
             ));
 
             // cmd 2
@@ -258,6 +519,7 @@ fn setup_track_dis(mut cmds: Commands) {
                     column: 2,
                     row,
                 },
+                OnScreen(Screen::Tracker),
             ));
         }
     }
@@ -283,16 +545,64 @@ fn setup_track_dis(mut cmds: Commands) {
 //     // ));
 // }
 
+fn display_titles(
+    text_comps: Query<(&mut TextComponent, &TitleMarker)>,
+    tracks: Query<(&TrackID, &TrackProgram)>,
+    first_view: Res<FirstViewTrack>,
+) {
+    for (mut text, col_n) in text_comps {
+        let track = first_view.0 + **col_n as usize;
+        let (flags, program) = tracks
+            .iter()
+            .find(|(id, _)| id.id == track)
+            .map(|(id, program)| {
+                (
+                    id.flags_display(),
+                    program
+                        .map(|program| format!(" {:>3}", program.program))
+                        .unwrap_or_default(),
+                )
+            })
+            .unwrap_or_default();
+
+        text.set_text(format!("Channel:{: >2} {flags}{program}", track + 1));
+    }
+}
+
 fn display_tracks(
     text_comps: Query<(&mut TextComponent, &CellMarker)>,
     tracks: Query<(&Track, &TrackID)>,
     display_start: Res<DisplayStart>,
+    first_view: Res<FirstViewTrack>,
+    location: Res<CursorLocation>,
+    anchor: Res<SelectionAnchor>,
+    key: Res<Key>,
 ) {
     let mut tracks: Vec<(&Track, &TrackID)> = tracks.into_iter().collect();
     tracks.sort_by_key(|(_track, id): &(&Track, &TrackID)| id.id);
+    let selection = selected_block(&anchor, &location, &display_start);
 
     for (ref mut text, cell) in text_comps {
-        let track = tracks[cell.track as usize].0;
+        let track_i = first_view.0 + cell.track as usize;
+        let step_i = (cell.row as usize + display_start.0) % N_STEPS;
+        let note = match tracks[track_i].0 {
+            Track::Midi { steps } => steps[step_i].note,
+            Track::SF2 { steps } => steps[step_i].note,
+        };
+        let out_of_key = cell.column == 0 && note.is_some_and(|note| !key.contains(note));
+        let color = if selection.is_some_and(|block| block.contains(track_i, step_i)) {
+            Some(Rgb565::MAGENTA)
+        } else if out_of_key {
+            Some(Rgb565::RED)
+        } else {
+            None
+        };
+
+        if text.color != color {
+            text.color = color;
+        }
+
+        let track = tracks[track_i].0;
         // track.
         match track {
             Track::Midi { steps } => {
@@ -326,6 +636,10 @@ fn display_line_nums(
 }
 
 fn shift_pressed(keys: Res<KeyPresses>) -> bool {
+    shift_held(&keys)
+}
+
+fn shift_held(keys: &KeyPresses) -> bool {
     keys.is_pressed(KEY_MOD_SHL) || keys.is_pressed(KEY_MOD_SHR)
 }
 
@@ -333,8 +647,10 @@ fn move_cursor(
     keys: Res<KeyPresses>,
     mut location: ResMut<CursorLocation>,
     mut display_start: ResMut<DisplayStart>,
+    mut first_view: ResMut<FirstViewTrack>,
 ) {
     let CursorLocation(x, y) = *location;
+    let last_col = N_TRACKS * CELLS_PER_TRACK - 1;
 
     if (keys.just_pressed(KEY_UP) || keys.is_pressed(KEY_UP))
         && !keys.is_pressed(KEY_DOWN)
@@ -368,7 +684,7 @@ fn move_cursor(
         && !keys.is_pressed(KEY_RIGHT)
     {
         if x == 0 {
-            location.0 = 5;
+            location.0 = last_col;
         } else {
             location.0 -= 1;
         }
@@ -377,24 +693,611 @@ fn move_cursor(
         && !keys.is_pressed(KEY_DOWN)
         && !keys.is_pressed(KEY_LEFT)
     {
-        if x == 5 {
+        if x == last_col {
             location.0 = 0;
         } else {
             location.0 += 1;
         };
     }
+
+    let track = track_from_cursor_col(location.0);
+    let new_first = scroll_view_to(first_view.0, track);
+
+    if new_first != first_view.0 {
+        first_view.0 = new_first;
+    }
+}
+
+/// the tracks ordered by id, so they can be indexed by track id. only the tracks an edit derefs
+/// mutably are marked as changed.
+fn sorted_tracks<'a>(tracks: &'a mut Query<(&mut Track, &TrackID)>) -> Vec<Mut<'a, Track>> {
+    let mut tracks: Vec<(Mut<Track>, &TrackID)> = tracks.iter_mut().collect();
+    tracks.sort_by_key(|(_track, id)| id.id);
+
+    tracks.into_iter().map(|(track, _id)| track).collect()
+}
+
+/// the undo history may grow to use half of the free heap.
+fn history_budget(history: &History) -> usize {
+    history.n_bytes() + HEAP.free() / 2
+}
+
+/// keeps the budget for edits made outside of the binary, like steps written by the host.
+fn update_history_budget(history: Res<History>, mut budget: ResMut<HistoryBudget>) {
+    budget.0 = history_budget(&history);
+}
+
+/// ctrl+z undo, ctrl+y redo.
+fn history_keys(
+    keys: Res<KeyPresses>,
+    mut history: ResMut<History>,
+    mut tracks: Query<(&mut Track, &TrackID)>,
+) {
+    if !keys.is_pressed(KEY_MOD_CTRL) {
+        return;
+    }
+
+    if keys.just_pressed(b'z') {
+        history.undo(&mut sorted_tracks(&mut tracks));
+    } else if keys.just_pressed(b'y') {
+        history.redo(&mut sorted_tracks(&mut tracks));
+    }
+}
+
+/// the selected block, if a selection is being made.
+fn selected_block(
+    anchor: &SelectionAnchor,
+    location: &CursorLocation,
+    display_start: &DisplayStart,
+) -> Option<Block> {
+    let cursor = (
+        track_from_cursor_col(location.0),
+        cursor_step(location, display_start),
+    );
+
+    anchor.0.map(|anchor| Block::new(anchor, cursor))
+}
+
+/// shift+arrows starts (or grows) a block selection, moving without shift drops it.
+fn select_block(
+    keys: Res<KeyPresses>,
+    location: Res<CursorLocation>,
+    display_start: Res<DisplayStart>,
+    mut anchor: ResMut<SelectionAnchor>,
+) {
+    if ![KEY_UP, KEY_DOWN, KEY_LEFT, KEY_RIGHT]
+        .into_iter()
+        .any(|key| keys.just_pressed(key))
+    {
+        return;
+    }
+
+    if !shift_held(&keys) {
+        if anchor.0.is_some() {
+            anchor.0 = None;
+        }
+    } else if anchor.0.is_none() {
+        anchor.0 = Some((
+            track_from_cursor_col(location.0),
+            cursor_step(&location, &display_start),
+        ));
+    }
+}
+
+/// ctrl+c copy, ctrl+x cut, ctrl+v paste, ctrl+b paste without overwriting.
+fn clipboard_keys(
+    keys: Res<KeyPresses>,
+    location: Res<CursorLocation>,
+    display_start: Res<DisplayStart>,
+    anchor: Res<SelectionAnchor>,
+    mut clipboard: ResMut<Clipboard>,
+    mut history: ResMut<History>,
+    mut tracks: Query<(&mut Track, &TrackID)>,
+) {
+    if !keys.is_pressed(KEY_MOD_CTRL) {
+        return;
+    }
+
+    let cut = keys.just_pressed(b'x');
+    let copy = keys.just_pressed(b'c') || cut;
+    let mix = keys.just_pressed(b'b');
+    let paste = keys.just_pressed(b'v') || mix;
+
+    if !(copy || paste) {
+        return;
+    }
+
+    let cursor = (
+        track_from_cursor_col(location.0),
+        cursor_step(&location, &display_start),
+    );
+    let block =
+        selected_block(&anchor, &location, &display_start).unwrap_or(Block::new(cursor, cursor));
+
+    let mut tracks = sorted_tracks(&mut tracks);
+    let budget = history_budget(&history);
+
+    if copy {
+        let src: Vec<&Track> = tracks.iter().map(|track| &**track).collect();
+        *clipboard = Clipboard::copy(&src, block);
+    }
+
+    if cut {
+        history.apply(&mut tracks, EditKind::Cut, block, budget, |tracks| {
+            tracks
+                .iter_mut()
+                .skip(block.first_track)
+                .take(block.last_track - block.first_track + 1)
+                .for_each(|track| clear_steps(track, block.first_step, block.n_steps()))
+        });
+    }
+
+    if paste {
+        let paste_block = clipboard.block_at(block.first_track, block.first_step);
+
+        history.apply(
+            &mut tracks,
+            EditKind::Paste,
+            paste_block,
+            budget,
+            |tracks| clipboard.paste(tracks, block.first_track, block.first_step, mix),
+        );
+    }
+}
+
+/// alt+arrows transpose, alt+r reverse, alt+,/. rotate, alt+2..8 fill every nth step from the
+/// first, alt+h humanise, alt+q quantise to the key. works on the selection, or the cursor's step.
+#[allow(clippy::too_many_arguments)]
+fn transform_keys(
+    keys: Res<KeyPresses>,
+    location: Res<CursorLocation>,
+    display_start: Res<DisplayStart>,
+    anchor: Res<SelectionAnchor>,
+    key: Res<Key>,
+    bpq: Res<BPQ>,
+    uptime: Res<Uptime>,
+    mut rng: ResMut<TransformRng>,
+    mut history: ResMut<History>,
+    mut tracks: Query<(&mut Track, &TrackID)>,
+) {
+    if !keys.is_pressed(KEY_MOD_ALT) {
+        return;
+    }
+
+    let transform = if keys.just_pressed(KEY_UP) {
+        Transform::Transpose(1)
+    } else if keys.just_pressed(KEY_DOWN) {
+        Transform::Transpose(-1)
+    } else if keys.just_pressed(KEY_LEFT) {
+        Transform::Transpose(-12)
+    } else if keys.just_pressed(KEY_RIGHT) {
+        Transform::Transpose(12)
+    } else if keys.just_pressed(b'r') {
+        Transform::Reverse
+    } else if keys.just_pressed(b',') {
+        Transform::Rotate(-1)
+    } else if keys.just_pressed(b'.') {
+        Transform::Rotate(1)
+    } else if keys.just_pressed(b'h') {
+        Transform::Humanise {
+            vel: 16,
+            timing: (pulses_per_step(&bpq) - 1) as u8,
+        }
+    } else if keys.just_pressed(b'q') {
+        Transform::Quantise(*key)
+    } else if let Some(n) = (b'2'..=b'8').find(|digit| keys.just_pressed(*digit)) {
+        Transform::FillEvery((n - b'0') as usize)
+    } else {
+        return;
+    };
+
+    let cursor = (
+        track_from_cursor_col(location.0),
+        cursor_step(&location, &display_start),
+    );
+    let block =
+        selected_block(&anchor, &location, &display_start).unwrap_or(Block::new(cursor, cursor));
+    let budget = history_budget(&history);
+    let rng = rng.get(&uptime);
+
+    history.apply(
+        &mut sorted_tracks(&mut tracks),
+        EditKind::Transform,
+        block,
+        budget,
+        |tracks| {
+            tracks
+                .iter_mut()
+                .skip(block.first_track)
+                .take(block.last_track - block.first_track + 1)
+                .for_each(|track| transform.apply(track, block.first_step, block.n_steps(), rng))
+        },
+    );
+}
+
+/// ctrl+k moves the key's root up a semitone (down with shift), ctrl+m cycles the scale, ctrl+l
+/// locks note entry to the key, and ctrl+u makes a custom scale out of the notes in the track
+/// under the cursor.
+fn scale_keys(
+    keys: Res<KeyPresses>,
+    location: Res<CursorLocation>,
+    mut key: ResMut<Key>,
+    tracks: Query<(&Track, &TrackID)>,
+) {
+    if !keys.is_pressed(KEY_MOD_CTRL) {
+        return;
+    }
+
+    let by = if shift_held(&keys) { -1 } else { 1 };
+
+    if keys.just_pressed(b'k') {
+        key.root = (key.root as i16 + by).rem_euclid(12) as u8;
+    } else if keys.just_pressed(b'm') {
+        key.scale = key.scale.cycle(by as isize);
+    } else if keys.just_pressed(b'l') {
+        key.lock = !key.lock;
+    } else if keys.just_pressed(b'u') {
+        let track_i = track_from_cursor_col(location.0);
+        let Some((track, _)) = tracks.iter().find(|(_, id)| id.id == track_i) else {
+            return;
+        };
+
+        let notes: Vec<u8> = match track {
+            Track::Midi { steps } => steps.iter().filter_map(|step| step.note).collect(),
+            Track::SF2 { steps } => steps.iter().filter_map(|step| step.note).collect(),
+        };
+        key.scale = Scale::custom(key.root, notes);
+    }
+}
+
+/// mutes, solos, or arms the track under the cursor.
+fn track_flag_keys(
+    keys: Res<KeyPresses>,
+    location: Res<CursorLocation>,
+    mut flags: EventWriter<SetTrackFlag>,
+) {
+    let flag = if keys.just_pressed(KEY_F6) {
+        TrackFlag::Mute
+    } else if keys.just_pressed(KEY_F7) {
+        TrackFlag::Solo
+    } else if keys.just_pressed(KEY_F8) {
+        TrackFlag::Arm
+    } else {
+        return;
+    };
+
+    flags.write(SetTrackFlag {
+        track: track_from_cursor_col(location.0),
+        flag,
+        value: None,
+    });
+}
+
+/// ctrl+, and ctrl+. move the program of the track under the cursor down and up, shift+ctrl its
+/// bank. going below 0 unsets it. the new program is sent straight away so it can be heard.
+fn program_keys(
+    keys: Res<KeyPresses>,
+    location: Res<CursorLocation>,
+    mut tracks: Query<(&TrackID, &mut TrackProgram)>,
+    mut midi_out: EventWriter<TrackMidi>,
+) {
+    if !keys.is_pressed(KEY_MOD_CTRL) {
+        return;
+    }
+
+    let by = if keys.just_pressed(b',') {
+        -1
+    } else if keys.just_pressed(b'.') {
+        1
+    } else {
+        return;
+    };
+    let track = track_from_cursor_col(location.0);
+    let Some((_, mut program)) = tracks.iter_mut().find(|(id, _)| id.id == track) else {
+        return;
+    };
+
+    program.0 = if shift_held(&keys) {
+        program.0.map(|program| Program {
+            bank: match program.bank {
+                None if by > 0 => Some(0),
+                Some(0) if by < 0 => None,
+                None => None,
+                Some(bank) => Some((bank as isize + by).min(16383) as u16),
+            },
+            ..program
+        })
+    } else {
+        match program.0 {
+            None if by > 0 => Some(Program::default()),
+            Some(Program { program: 0, .. }) if by < 0 => None,
+            None => None,
+            Some(mut program) => {
+                program.adjust(0, by);
+                Some(program)
+            }
+        }
+    };
+
+    if let Some(program) = program.0 {
+        send_program(&program, &mut TrackOut::new(track, &mut midi_out));
+    }
+}
+
+fn project_keys(keys: Res<KeyPresses>, mut project: EventWriter<ProjectEvent>) {
+    if keys.just_pressed(KEY_F9) {
+        project.write(ProjectEvent::Save);
+    } else if keys.just_pressed(KEY_F10) {
+        project.write(ProjectEvent::Load);
+    }
 }
 
 fn note_selected(location: Res<CursorLocation>) -> bool {
     let CursorLocation(x, _) = *location;
-    x % 3 == 0
+    x % CELLS_PER_TRACK == 0
+}
+
+/// a change to a command cell. what each one does depends on the command in the cell.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum CmdEdit {
+    /// chord quality, or arp rate.
+    Vertical(isize),
+    /// chord inversion, or how many steps an arp lasts.
+    Horizontal(isize),
+    Voicing,
+    /// chord octave doubling, or the arp's octave range.
+    Octave,
+    Gate,
+    /// turns the command in to an arp, or cycles the arp mode.
+    Arp,
+    /// turns the command in to a condition, or cycles the kind of condition.
+    Condition,
+    /// the key's triad, or seventh chord, on the step's note.
+    Diatonic {
+        seventh: bool,
+    },
+    /// turns the command in to a roll.
+    Roll,
+    /// how many semitones each hit of a roll moves.
+    PitchStep(isize),
+    /// turns the command in to a pitch bend, or toggles the bend ramping.
+    Bend,
+    /// turns the command in to a portamento command.
+    Porta,
+    Legato,
+    /// turns the command in to a program change.
+    Program,
+    /// turns the command in to a message bus command.
+    Bus,
+    Clear,
 }
 
+/// alters the selected command. on a chord up/down cycle the chord quality, left/right the
+/// inversion, v the voicing and o toggles octave doubling. on an arp up/down change the rate,
+/// left/right the number of steps, o the octave range, g the gate, and a the mode. p makes the
+/// command a condition or cycles the kind of condition, up/down and left/right then change its
+/// values. r makes the command a roll, up/down then change the number of hits (no more than there
+/// are pulses in a step), left/right the velocity ramp, and , and . the pitch step. b makes the
+/// command a pitch bend or toggles its ramp, t makes it portamento, up/down and left/right then
+/// change the bend amount or the portamento time, and l makes it legato. i makes it a program
+/// change, up/down and left/right then change the program by 10 and 1, and m makes it a bus
+/// message, picked the same way. c puts the key's triad on the step's note in the command, 7 its
+/// seventh chord, and backspace clears the command.
 fn edit_cmd(
     keys: Res<KeyPresses>,
     location: Res<CursorLocation>,
     mut tracks: Query<(&mut Track, &TrackID)>,
+    display_start: Res<DisplayStart>,
+    mut history: ResMut<History>,
+    key: Res<Key>,
+    bpq: Res<BPQ>,
 ) {
+    let edit = if keys.just_pressed(KEY_UP) {
+        CmdEdit::Vertical(1)
+    } else if keys.just_pressed(KEY_DOWN) {
+        CmdEdit::Vertical(-1)
+    } else if keys.just_pressed(KEY_LEFT) {
+        CmdEdit::Horizontal(-1)
+    } else if keys.just_pressed(KEY_RIGHT) {
+        CmdEdit::Horizontal(1)
+    } else if keys.just_pressed(b'v') {
+        CmdEdit::Voicing
+    } else if keys.just_pressed(b'o') {
+        CmdEdit::Octave
+    } else if keys.just_pressed(b'g') {
+        CmdEdit::Gate
+    } else if keys.just_pressed(b'a') {
+        CmdEdit::Arp
+    } else if keys.just_pressed(b'p') {
+        CmdEdit::Condition
+    } else if keys.just_pressed(b'c') {
+        CmdEdit::Diatonic { seventh: false }
+    } else if keys.just_pressed(b'7') {
+        CmdEdit::Diatonic { seventh: true }
+    } else if keys.just_pressed(b'r') {
+        CmdEdit::Roll
+    } else if keys.just_pressed(b',') {
+        CmdEdit::PitchStep(-1)
+    } else if keys.just_pressed(b'.') {
+        CmdEdit::PitchStep(1)
+    } else if keys.just_pressed(b'b') {
+        CmdEdit::Bend
+    } else if keys.just_pressed(b't') {
+        CmdEdit::Porta
+    } else if keys.just_pressed(b'l') {
+        CmdEdit::Legato
+    } else if keys.just_pressed(b'i') {
+        CmdEdit::Program
+    } else if keys.just_pressed(b'm') {
+        CmdEdit::Bus
+    } else if keys.just_pressed(KEY_BACKSPACE) || keys.just_pressed(KEY_DEL) {
+        CmdEdit::Clear
+    } else {
+        return;
+    };
+
+    let CursorLocation(x, _) = *location;
+    let y = cursor_step(&location, &display_start);
+    let track_i = track_from_cursor_col(x);
+    let budget = history_budget(&history);
+    let pulses_per_step = pulses_per_step(&bpq);
+    let cell = Block::new((track_i, y), (track_i, y));
+    let kind = if edit == CmdEdit::Clear {
+        EditKind::Delete
+    } else {
+        EditKind::Cmd
+    };
+
+    history.apply(
+        &mut sorted_tracks(&mut tracks),
+        kind,
+        cell,
+        budget,
+        |tracks| {
+            let Some(track) = tracks.get_mut(track_i) else {
+                return;
+            };
+
+            match **track {
+                Track::Midi { ref mut steps } => {
+                    apply_cmd_edit(&mut steps[y], x, &key, pulses_per_step, edit)
+                }
+                Track::SF2 { ref mut steps } => {
+                    apply_cmd_edit(&mut steps[y], x, &key, pulses_per_step, edit)
+                }
+            }
+        },
+    );
+}
+
+/// applies `edit` to the command in cursor column `x`. chord edits on a command that isn't a chord
+/// or an arp turn it in to a major chord first.
+fn apply_cmd_edit<Cmd>(
+    step: &mut Step<Cmd>,
+    x: usize,
+    key: &Key,
+    pulses_per_step: usize,
+    edit: CmdEdit,
+) where
+    Cmd:
+        Clone + Default + PartialEq + PartialOrd + core::fmt::Display + ToString + core::fmt::Debug,
+{
+    // diatonic chords are built on the root of the key if the step has no note.
+    let note = step.note.unwrap_or(60 + key.root);
+    let cmd = if x % CELLS_PER_TRACK == 1 {
+        &mut step.cmds.0
+    } else {
+        &mut step.cmds.1
+    };
+
+    match (edit, &mut *cmd) {
+        (CmdEdit::Clear, _) => *cmd = TrackerCmd::None,
+        (CmdEdit::Diatonic { seventh }, _) => {
+            if let Some(chord) = key.chord(note, seventh) {
+                *cmd = TrackerCmd::Chord { chord };
+            }
+        }
+        (CmdEdit::Condition, TrackerCmd::Cond { cond }) => *cond = cond.next(),
+        (CmdEdit::Vertical(by), TrackerCmd::Cond { cond }) => cond.adjust(by, 0),
+        (CmdEdit::Horizontal(by), TrackerCmd::Cond { cond }) => cond.adjust(0, by),
+        (CmdEdit::Condition, _) => {
+            *cmd = TrackerCmd::Cond {
+                cond: Cond::default(),
+            }
+        }
+        (CmdEdit::Vertical(by), TrackerCmd::Roll { roll }) => roll.adjust(by, 0, pulses_per_step),
+        (CmdEdit::Horizontal(by), TrackerCmd::Roll { roll }) => roll.adjust(0, by, pulses_per_step),
+        (CmdEdit::PitchStep(by), TrackerCmd::Roll { roll }) => {
+            roll.pitch_step = (roll.pitch_step as isize + by).clamp(-12, 12) as i8
+        }
+        (CmdEdit::Roll, _) => {
+            *cmd = TrackerCmd::Roll {
+                roll: Roll::default(),
+            }
+        }
+        (CmdEdit::PitchStep(_), _) => {}
+        (CmdEdit::Bend, TrackerCmd::Bend { bend }) => bend.ramp = !bend.ramp,
+        (CmdEdit::Vertical(by), TrackerCmd::Bend { bend }) => bend.adjust(by, 0),
+        (CmdEdit::Horizontal(by), TrackerCmd::Bend { bend }) => bend.adjust(0, by),
+        (CmdEdit::Bend, _) => {
+            *cmd = TrackerCmd::Bend {
+                bend: Bend::default(),
+            }
+        }
+        (CmdEdit::Vertical(by), TrackerCmd::Porta { porta }) => porta.adjust(by, 0),
+        (CmdEdit::Horizontal(by), TrackerCmd::Porta { porta }) => porta.adjust(0, by),
+        (CmdEdit::Porta, _) => {
+            *cmd = TrackerCmd::Porta {
+                porta: Porta { time: 32 },
+            }
+        }
+        (CmdEdit::Legato, _) => *cmd = TrackerCmd::Legato,
+        (CmdEdit::Vertical(by), TrackerCmd::Program { program }) => program.adjust(by, 0),
+        (CmdEdit::Horizontal(by), TrackerCmd::Program { program }) => program.adjust(0, by),
+        (CmdEdit::Program, _) => {
+            *cmd = TrackerCmd::Program {
+                program: Program::default(),
+            }
+        }
+        (CmdEdit::Vertical(by), TrackerCmd::Bus { msg }) => msg.adjust(by, 0),
+        (CmdEdit::Horizontal(by), TrackerCmd::Bus { msg }) => msg.adjust(0, by),
+        (CmdEdit::Bus, _) => {
+            *cmd = TrackerCmd::Bus {
+                msg: BusMsg::default(),
+            }
+        }
+        (edit, TrackerCmd::Arp { arp }) => match edit {
+            CmdEdit::Vertical(by) => arp.rate = (arp.rate as isize + by).clamp(1, 96) as u8,
+            CmdEdit::Horizontal(by) => {
+                arp.steps = (arp.steps as isize + by).clamp(1, N_STEPS as isize) as u8
+            }
+            CmdEdit::Octave => arp.octaves = arp.octaves % 4 + 1,
+            CmdEdit::Gate => arp.gate = arp.gate % 100 + 25,
+            CmdEdit::Arp => arp.mode = arp.mode.next(),
+            _ => {}
+        },
+        (CmdEdit::Arp, _) => {
+            *cmd = TrackerCmd::Arp {
+                arp: Arp::default(),
+            }
+        }
+        (CmdEdit::Gate, _) => {}
+        (edit, cmd) => {
+            let new = !matches!(cmd, TrackerCmd::Chord { .. });
+
+            if new {
+                *cmd = TrackerCmd::Chord {
+                    chord: Chord::default(),
+                };
+            }
+
+            let TrackerCmd::Chord { chord } = cmd else {
+                return;
+            };
+            let n_notes = chord.quality.intervals().len() as isize;
+
+            match edit {
+                CmdEdit::Vertical(by) => {
+                    // a new chord cycles from the start, so up makes it major.
+                    let from = if new {
+                        ChordQuality::ALL[0]
+                    } else {
+                        chord.quality
+                    };
+                    chord.quality = from.cycle(by);
+                    chord.inversion = chord
+                        .inversion
+                        .min(chord.quality.intervals().len() as u8 - 1);
+                }
+                CmdEdit::Horizontal(by) => {
+                    chord.inversion = (chord.inversion as isize + by).rem_euclid(n_notes) as u8
+                }
+                CmdEdit::Voicing => chord.voicing = chord.voicing.next(),
+                CmdEdit::Octave => chord.double = !chord.double,
+                _ => {}
+            }
+        }
+    }
 }
 
 fn delete_note(
@@ -402,19 +1305,30 @@ fn delete_note(
     location: Res<CursorLocation>,
     mut tracks: Query<(&mut Track, &TrackID)>,
     display_start: Res<DisplayStart>,
+    mut history: ResMut<History>,
 ) {
     let CursorLocation(x, y) = *location;
     let y = (y + display_start.0) % N_STEPS;
+    let track_i = track_from_cursor_col(x);
+    let budget = history_budget(&history);
 
     if keys.just_pressed(KEY_BACKSPACE) || keys.just_pressed(KEY_DEL) {
-        for (mut track, id) in tracks.iter_mut() {
-            if id.id == (x / 3) {
-                match *track {
-                    Track::Midi { ref mut steps } => steps[y].note = None,
-                    Track::SF2 { ref mut steps } => steps[y].note = None,
+        let cell = Block::new((track_i, y), (track_i, y));
+
+        history.apply(
+            &mut sorted_tracks(&mut tracks),
+            EditKind::Delete,
+            cell,
+            budget,
+            |tracks| {
+                if let Some(track) = tracks.get_mut(track_i) {
+                    match **track {
+                        Track::Midi { ref mut steps } => steps[y].note = None,
+                        Track::SF2 { ref mut steps } => steps[y].note = None,
+                    }
                 }
-            }
-        }
+            },
+        );
     }
 }
 
@@ -424,10 +1338,14 @@ fn edit_note(
     location: Res<CursorLocation>,
     mut tracks: Query<(&mut Track, &TrackID)>,
     display_start: Res<DisplayStart>,
+    mut history: ResMut<History>,
+    key: Res<Key>,
     // mut log: EventWriter<Log>,
 ) {
     let CursorLocation(x, y) = *location;
     let y = (y + display_start.0) % N_STEPS;
+    let track_i = track_from_cursor_col(x);
+    let budget = history_budget(&history);
 
     let by = if keys.is_pressed(KEY_UP) || keys.just_pressed(KEY_UP) {
         // little up
@@ -447,40 +1365,66 @@ fn edit_note(
 
     // log.write(Log::info("EDIT NOTE-2"));
 
-    for (mut track, id) in tracks.iter_mut() {
-        if id.id != (x / 3) {
-            continue;
-        }
+    // with the key locked, up and down step through the scale and new notes start in the key.
+    let snap = |note: u8| if key.lock { key.quantise(note) } else { note };
+    let cell = Block::new((track_i, y), (track_i, y));
+
+    history.apply(
+        &mut sorted_tracks(&mut tracks),
+        EditKind::Note,
+        cell,
+        budget,
+        |tracks| {
+            let Some(track) = tracks.get_mut(track_i) else {
+                return;
+            };
+
+            if let Some(note) = match **track {
+                Track::Midi { ref mut steps } => &mut steps[y].note,
+                Track::SF2 { ref mut steps } => &mut steps[y].note,
+            } {
+                *note = if key.lock && by.abs() == 1 {
+                    key.step(*note, by)
+                } else {
+                    transpose_note(*note, by)
+                };
+            } else if by < 0 {
+                let note = snap(127 - by.abs() as u8);
+
+                match **track {
+                    Track::Midi { ref mut steps } => steps[y].note = Some(note),
+                    Track::SF2 { ref mut steps } => steps[y].note = Some(note),
+                }
+            } else if by > 0 {
+                let note = snap(by.abs() as u8 - 1);
 
-        if let Some(note) = match *track {
-            Track::Midi { ref mut steps } => &mut steps[y].note,
-            Track::SF2 { ref mut steps } => &mut steps[y].note,
-        } {
-            *note = ((*note as i16 + by) % 128) as u8;
-        } else if by < 0 {
-            match *track {
-                Track::Midi { ref mut steps } => steps[y].note = Some(127 - by.abs() as u8),
-                Track::SF2 { ref mut steps } => steps[y].note = Some(127 - by.abs() as u8),
-            }
-        } else if by > 0 {
-            match *track {
-                Track::Midi { ref mut steps } => steps[y].note = Some(by.abs() as u8 - 1),
-                Track::SF2 { ref mut steps } => steps[y].note = Some(by.abs() as u8 - 1),
-            }
-        } else if by == 0 {
-            match *track {
-                Track::Midi { ref mut steps } => steps[y].note = None,
-                Track::SF2 { ref mut steps } => steps[y].note = None,
+                match **track {
+                    Track::Midi { ref mut steps } => steps[y].note = Some(note),
+                    Track::SF2 { ref mut steps } => steps[y].note = Some(note),
+                }
+            } else if by == 0 {
+                match **track {
+                    Track::Midi { ref mut steps } => steps[y].note = None,
+                    Track::SF2 { ref mut steps } => steps[y].note = None,
+                }
             }
-        }
-    }
+        },
+    );
 }
 
 fn display_cursor(
     cursors: Query<(&mut Visible, &CursorID), With<TextComponent>>,
     loc: Res<CursorLocation>,
+    first_view: Res<FirstViewTrack>,
 ) {
-    let target = loc.1 * 6 + loc.0;
+    let row_len = TRACKS_ON_SCREEN * CELLS_PER_TRACK;
+    let view_col = loc.0.wrapping_sub(first_view.0 * CELLS_PER_TRACK);
+    // the cursor is off screen until move_cursor scrolls the view to it.
+    let target = if view_col < row_len {
+        loc.1 * row_len + view_col
+    } else {
+        usize::MAX
+    };
 
     for (ref mut vis, CursorID(id)) in cursors {
         // if *id == target && !vis.should_show() {
@@ -495,41 +1439,457 @@ fn enter_pressed(keys: Res<KeyPresses>) -> bool {
     keys.is_pressed(KEY_ENTER)
 }
 
+fn alt_pressed(keys: Res<KeyPresses>) -> bool {
+    keys.is_pressed(KEY_MOD_ALT)
+}
+
 fn enter_just_pressed(keys: Res<KeyPresses>) -> bool {
     keys.just_pressed(KEY_ENTER)
 }
 
-fn toggle_playing(
-    mut midi: EventWriter<MidiEnv>,
-    mut log: EventWriter<Log>,
-    // mut text_dis: Single<&mut TextComponent, With<PlayingMarker>>,
-    mut playing: ResMut<Playing>,
+/// the step that the cursor is on.
+fn cursor_step(location: &CursorLocation, display_start: &DisplayStart) -> usize {
+    (location.1 + display_start.0) % N_STEPS
+}
+
+fn transport_keys(
+    keys: Res<KeyPresses>,
+    location: Res<CursorLocation>,
+    display_start: Res<DisplayStart>,
+    mut transport: EventWriter<TransportCmd>,
+    mut preview: EventWriter<PreviewStep>,
+    mut fill: ResMut<Fill>,
+) {
+    let step = cursor_step(&location, &display_start);
+
+    // ctrl+f toggles fill mode.
+    if keys.is_pressed(KEY_MOD_CTRL) && keys.just_pressed(b'f') {
+        fill.0 = !fill.0;
+    }
+
+    // ctrl+p plays the step under the cursor, only while stopped.
+    if keys.is_pressed(KEY_MOD_CTRL) && keys.just_pressed(b'p') {
+        preview.write(PreviewStep {
+            track: track_from_cursor_col(location.0),
+            step,
+        });
+    }
+
+    if keys.just_pressed(KEY_F1) {
+        transport.write(TransportCmd::TogglePause);
+    } else if keys.just_pressed(KEY_F2) {
+        transport.write(TransportCmd::Stop);
+    } else if keys.just_pressed(KEY_F3) {
+        transport.write(TransportCmd::RestartFrom(step));
+    } else if keys.just_pressed(KEY_F4) && shift_held(&keys) {
+        transport.write(TransportCmd::ClearLoop);
+    } else if keys.just_pressed(KEY_F4) {
+        transport.write(TransportCmd::SetLoopStart(step));
+    } else if keys.just_pressed(KEY_F5) {
+        transport.write(TransportCmd::SetLoopEnd(step));
+    }
+}
+
+/// `shown` is the status last put in the status bar, so the text is only rebuilt when it changes.
+#[allow(clippy::too_many_arguments)]
+fn display_status_bar(
+    mut text: Single<&mut TextComponent, With<StatusBar>>,
+    mut shown: Local<Option<StatusLine>>,
+    transport: Res<Transport>,
+    tempo: Res<Tempo>,
+    pulse: Res<SyncPulse>,
+    bpq: Res<BPQ>,
+    clock: Res<ClockSource>,
+    location: Res<CursorLocation>,
+    tracks: Query<&TrackID>,
+    key: Res<Key>,
+    fill: Res<Fill>,
+) {
+    let track = track_from_cursor_col(location.0);
+    let armed = tracks.iter().any(|id| id.id == track && id.armed);
+
+    let mode = if armed && transport.state == TransportState::Playing {
+        EditMode::Record
+    } else if location.0 % CELLS_PER_TRACK == 0 && key.lock {
+        EditMode::Scale
+    } else if location.0 % CELLS_PER_TRACK == 0 {
+        EditMode::Note
+    } else {
+        EditMode::Cmd
+    };
+
+    let status = StatusLine {
+        tempo: tempo.0,
+        state: transport.state,
+        position: song_position(pulse.n_pulses, &bpq),
+        mode,
+        clock: *clock,
+        key: *key,
+        loop_range: transport.loop_range,
+        fill: fill.0,
+    };
+
+    // only touch the component when the status changes so render skips it otherwise.
+    if *shown != Some(status) {
+        text.set_text(status.to_string());
+        *shown = Some(status);
+    }
+}
+
+/// ctrl+r opens and closes the routing screen, ctrl+g the log screen and ctrl+o the midi monitor,
+/// esc closes any of them. the host's device list is asked for when the routing screen opens.
+fn screen_keys(
+    keys: Res<KeyPresses>,
+    mut screen: ResMut<Screen>,
+    link: Res<HostLink>,
+    mut devs: ResMut<HostDevs>,
+    mut to_host: EventWriter<FromTracker>,
+) {
+    let toggle = keys.is_pressed(KEY_MOD_CTRL) && keys.just_pressed(b'r');
+    let toggle_log = keys.is_pressed(KEY_MOD_CTRL) && keys.just_pressed(b'g');
+    let toggle_midi = keys.is_pressed(KEY_MOD_CTRL) && keys.just_pressed(b'o');
+
+    *screen = match *screen {
+        Screen::Tracker if toggle => {
+            if link.is_compatible() && link.has_capability(capability::ROUTING) {
+                request_devs(&mut devs, &mut to_host);
+            }
+
+            Screen::Routing
+        }
+        Screen::Tracker if toggle_log => Screen::Log,
+        Screen::Tracker if toggle_midi => Screen::Midi,
+        Screen::Routing if toggle || keys.just_pressed(KEY_ESC) => Screen::Tracker,
+        Screen::Log if toggle_log || keys.just_pressed(KEY_ESC) => Screen::Tracker,
+        Screen::Midi if toggle_midi || keys.just_pressed(KEY_ESC) => Screen::Tracker,
+        _ => return,
+    };
+}
+
+/// hides the text of the screen that was left, then shows the new screen's text on the next
+/// frame, so erasing the old text doesn't draw over the new.
+fn switch_screens(
+    screen: Res<Screen>,
+    mut shown: Local<Screen>,
+    mut hidden: Local<bool>,
+    texts: Query<(&mut Visible, &OnScreen, Has<CursorID>)>,
+) {
+    if *screen == *shown && !*hidden {
+        return;
+    }
+
+    for (ref mut vis, on, is_cursor) in texts {
+        if !*hidden && **on != *screen {
+            vis.set_visible(false);
+        } else if *hidden && **on == *screen && !is_cursor {
+            // display_cursor shows the cursor that's on screen.
+            vis.set_visible(true);
+        }
+    }
+
+    if *hidden {
+        *shown = *screen;
+    }
+
+    *hidden = !*hidden;
+}
+
+/// picks a device and device channel for each track, and whether its midi goes out over usb or
+/// through the host. a changed route is only sent to the host when enter is pressed.
+fn routing_keys(
+    keys: Res<KeyPresses>,
+    mut cursor: ResMut<RoutingCursor>,
+    mut tracks: Query<(
+        &TrackID,
+        &mut TrackRoute,
+        &mut TrackOutput,
+        &mut SoundingNotes,
+    )>,
+    mut devs: ResMut<HostDevs>,
+    mut midi_out: EventWriter<TrackMidi>,
+    mut to_host: EventWriter<FromTracker>,
 ) {
-    playing.0 = !playing.0;
-    // text_dis.set_text(format!("{}", playing.0).to_uppercase());
+    if keys.is_pressed(KEY_MOD_CTRL) {
+        if keys.just_pressed(b'd') {
+            request_devs(&mut devs, &mut to_host);
+        }
 
-    if playing.0 {
-        midi.write(MidiEnv::On { note: 48, vel: 120 });
-        log.write(Log::info("playing"));
+        return;
+    }
+
+    if keys.just_pressed(KEY_UP) {
+        cursor.0 = (cursor.0 + N_TRACKS - 1) % N_TRACKS;
+    } else if keys.just_pressed(KEY_DOWN) {
+        cursor.0 = (cursor.0 + 1) % N_TRACKS;
+    }
+
+    let Some((_, mut route, mut output, mut sounding)) =
+        tracks.iter_mut().find(|(id, ..)| id.id == cursor.0)
+    else {
+        return;
+    };
+
+    if keys.just_pressed(b'o') {
+        // the notes that are playing are released where they were sent.
+        release_notes(&mut sounding, &mut TrackOut::new(cursor.0, &mut midi_out));
+
+        *output = output.next();
+    }
+
+    let by = if keys.just_pressed(KEY_LEFT) || keys.just_pressed(b',') {
+        -1
+    } else if keys.just_pressed(KEY_RIGHT) || keys.just_pressed(b'.') {
+        1
     } else {
-        midi.write(MidiEnv::Off { note: 48 });
-        log.write(Log::info("not playing"));
-    }
-}
-
-// fn display_devs(
-//     mut devs: EventReader<FromHost>,
-//     mut text_comps: Single<(&mut TextComponent,), (With<DevDisplay>, Without<Shape>)>,
-// ) {
-//     for dev_ev in devs.read() {
-//         // match dev_ev {
-//         //     FromHost::MidiNoteOn
-//         // }
-//         if let FromHost::Devs { dev_names } = dev_ev {
-//             text_comps.0.set_text(format!("{dev_names:?}"));
-//         }
-//     }
-// }
+        0
+    };
+
+    if by != 0 && (keys.just_pressed(KEY_LEFT) || keys.just_pressed(KEY_RIGHT)) {
+        let dev_channel = route
+            .route
+            .as_ref()
+            .map(|route| route.dev_channel)
+            .unwrap_or(cursor.0 as u8);
+        route.route = devs
+            .cycle(route.route.as_ref().map(|route| route.dev.as_str()), by)
+            .map(|dev| Route { dev, dev_channel });
+        route.status = RouteStatus::Edited;
+    } else if by != 0 {
+        if let Some(ref mut to) = route.route {
+            to.adjust_channel(by);
+            route.status = RouteStatus::Edited;
+        }
+    } else if keys.just_pressed(KEY_ENTER) {
+        // the host plugin sends it, and resends it if the host reconnects.
+        route.status = RouteStatus::Unsent;
+    } else if keys.just_pressed(KEY_BACKSPACE) {
+        route.route = None;
+        route.status = RouteStatus::Unsent;
+    }
+}
+
+fn display_routing(
+    lines: Query<(&mut TextComponent, &RoutingLine)>,
+    tracks: Query<(&TrackID, &TrackRoute, &TrackOutput)>,
+    cursor: Res<RoutingCursor>,
+    link: Res<HostLink>,
+    devs: Res<HostDevs>,
+) {
+    let route_of = |track: usize| {
+        tracks
+            .iter()
+            .find(|(id, ..)| id.id == track)
+            .map(|(_, route, output)| (route, output))
+    };
+
+    for (ref mut text, line) in lines {
+        let new_text = match *line {
+            RoutingLine::Header => match link.protocol_version {
+                None => "Routing: no host".into(),
+                Some(version) if !link.is_compatible() => {
+                    format!("Routing: host protocol v{version} unsupported")
+                }
+                Some(_) if !link.has_capability(capability::ROUTING) => {
+                    "Routing: host can't route".into()
+                }
+                Some(_) => format!(
+                    "Routing: {} devices{}",
+                    devs.names.len(),
+                    if devs.waiting { " (listing)" } else { "" }
+                ),
+            },
+            RoutingLine::Row(track) => {
+                let (output, dev, dev_channel, status) = route_of(track)
+                    .map(|(route, output)| {
+                        (
+                            output.code(),
+                            route.route.as_ref().map(|to| to.dev.as_str()),
+                            route.route.as_ref().map(|to| to.dev_channel),
+                            route.status.code(),
+                        )
+                    })
+                    .unwrap_or((' ', None, None, ""));
+
+                format!(
+                    "{}Ch{:>2} {output} {:<20.20} {:>2} {status}",
+                    if track == cursor.0 { '>' } else { ' ' },
+                    track + 1,
+                    dev.unwrap_or("none"),
+                    dev_channel
+                        .map(|ch| format!("{}", ch + 1))
+                        .unwrap_or("--".into()),
+                )
+            }
+            RoutingLine::Detail => match route_of(cursor.0).map(|(route, _)| &route.status) {
+                Some(RouteStatus::Failed(e)) => format!("{e:.40}"),
+                _ => String::new(),
+            },
+        };
+
+        text.set_text(new_text);
+    }
+}
+
+/// up/down scroll through the log, and , and . make it less or more verbose.
+fn log_keys(
+    keys: Res<KeyPresses>,
+    buffer: Res<LogBuffer>,
+    mut scroll: ResMut<LogScroll>,
+    mut verbosity: ResMut<Verbosity>,
+) {
+    let max_scroll = buffer.entries().len().saturating_sub(LOG_ROWS);
+
+    if keys.just_pressed(KEY_UP) {
+        scroll.0 = (scroll.0 + 1).min(max_scroll);
+    } else if keys.just_pressed(KEY_DOWN) {
+        scroll.0 = scroll.0.saturating_sub(1);
+    }
+
+    let levels = [
+        LogLevel::Error,
+        LogLevel::Warn,
+        LogLevel::Info,
+        LogLevel::Debug,
+        LogLevel::Trace,
+    ];
+    let level_i = levels
+        .iter()
+        .position(|level| *level == verbosity.0)
+        .unwrap_or(2);
+
+    if keys.just_pressed(b',') {
+        verbosity.0 = levels[level_i.saturating_sub(1)];
+    } else if keys.just_pressed(b'.') {
+        verbosity.0 = levels[(level_i + 1).min(levels.len() - 1)];
+    }
+}
+
+fn display_log(
+    lines: Query<(&mut TextComponent, &LogLine)>,
+    buffer: Res<LogBuffer>,
+    scroll: Res<LogScroll>,
+    verbosity: Res<Verbosity>,
+    link: Res<HostLink>,
+) {
+    let entries = buffer.entries();
+    // the entry after the last one shown, the oldest shown is at the top.
+    let end = entries.len().saturating_sub(scroll.0);
+    let start = end.saturating_sub(LOG_ROWS);
+
+    for (ref mut text, line) in lines {
+        let (new_text, color) = match *line {
+            LogLine::Header => (
+                format!(
+                    "Log: {:?}{}",
+                    verbosity.0,
+                    if link.protocol_version.is_some() {
+                        ""
+                    } else {
+                        ", no host"
+                    }
+                ),
+                Some(Rgb565::CYAN),
+            ),
+            LogLine::Row(row) => match entries.get(start + row).filter(|_| start + row < end) {
+                Some(entry) => (
+                    format!("{:.40}", entry.to_string()),
+                    match entry.level {
+                        LogLevel::Error => Some(Rgb565::RED),
+                        LogLevel::Warn => Some(Rgb565::YELLOW),
+                        _ => None,
+                    },
+                ),
+                None => (String::new(), None),
+            },
+        };
+
+        // only touch the component when the line changes so render skips it otherwise.
+        if text.text != new_text || text.color != color {
+            text.color = color;
+            text.old = Some(text.text.clone());
+            text.text = new_text;
+        }
+    }
+}
+
+/// up/down scroll through the midi, left/right filter it to a channel and , and . to a type of
+/// message. u/h show only usb/host midi and >/< only sent/received, pressing one again shows
+/// everything. space pauses the monitor and backspace clears it.
+fn midi_keys(
+    keys: Res<KeyPresses>,
+    mut monitor: ResMut<MidiMonitor>,
+    mut scroll: ResMut<MidiScroll>,
+) {
+    if keys.just_pressed(KEY_LEFT) {
+        monitor.cycle_channel(-1);
+    } else if keys.just_pressed(KEY_RIGHT) {
+        monitor.cycle_channel(1);
+    } else if keys.just_pressed(b',') {
+        monitor.cycle_kind(-1);
+    } else if keys.just_pressed(b'.') {
+        monitor.cycle_kind(1);
+    } else if keys.just_pressed(b'u') {
+        monitor.toggle_link(MidiLink::Usb);
+    } else if keys.just_pressed(b'h') {
+        monitor.toggle_link(MidiLink::Host);
+    } else if keys.just_pressed(b'>') {
+        monitor.toggle_direction(MidiDirection::Sent);
+    } else if keys.just_pressed(b'<') {
+        monitor.toggle_direction(MidiDirection::Received);
+    } else if keys.just_pressed(b' ') {
+        monitor.paused = !monitor.paused;
+    } else if keys.just_pressed(KEY_BACKSPACE) {
+        monitor.clear();
+    }
+
+    let max_scroll = monitor.shown().count().saturating_sub(MIDI_ROWS);
+
+    if keys.just_pressed(KEY_UP) {
+        scroll.0 += 1;
+    } else if keys.just_pressed(KEY_DOWN) {
+        scroll.0 = scroll.0.saturating_sub(1);
+    }
+
+    // a filter can leave fewer messages to scroll through.
+    scroll.0 = scroll.0.min(max_scroll);
+}
+
+fn display_midi(
+    lines: Query<(&mut TextComponent, &MidiLine)>,
+    monitor: Res<MidiMonitor>,
+    scroll: Res<MidiScroll>,
+) {
+    let shown: Vec<_> = monitor.shown().collect();
+    // the message after the last one shown, the oldest shown is at the top.
+    let end = shown.len().saturating_sub(scroll.0);
+    let start = end.saturating_sub(MIDI_ROWS);
+
+    for (ref mut text, line) in lines {
+        let new_text = match *line {
+            MidiLine::Header => {
+                let ports = monitor.port_filter();
+
+                format!(
+                    "MIDI: ch {}, {}{}{}{}",
+                    monitor
+                        .channel
+                        .map(|channel| format!("{}", channel + 1))
+                        .unwrap_or("all".into()),
+                    monitor.kind.map(|kind| kind.name()).unwrap_or("all types"),
+                    if ports.is_empty() { "" } else { ", " },
+                    ports,
+                    if monitor.paused { ", paused" } else { "" }
+                )
+            }
+            MidiLine::Row(row) => shown[start..end]
+                .get(row)
+                .map(|entry| format!("{:.40}", entry.to_string()))
+                .unwrap_or_default(),
+        };
+
+        text.set_text(new_text);
+    }
+}
 
 /// changes the color of the step lable that is being played
 fn display_step(
@@ -0,0 +1,208 @@
+//! the host's message bus. steps publish the project's messages on it, and messages that arrive
+//! on it trigger the actions the project subscribed them to.
+use crate::{
+    N_STEPS, SetTrackFlag, Tempo, TrackFlag, TransportCmd, cond::Fill, host::HostLink,
+    logging::Log, remote::TEMPO_RANGE,
+};
+use bevy::prelude::*;
+use core::fmt::Display;
+use pico_tracker_types::{FromHost, FromTracker, capability};
+use serde::{Deserialize, Serialize};
+
+/// which of the project's bus messages a step publishes.
+#[derive(Clone, Copy, Default, Debug, PartialEq, Eq, PartialOrd, Hash, Serialize, Deserialize)]
+pub struct BusMsg(pub u8);
+
+impl BusMsg {
+    /// nudges the message index, 10 at a time vertically and 1 at a time horizontally.
+    pub fn adjust(&mut self, vertical: isize, horizontal: isize) {
+        self.0 = (self.0 as isize + vertical * 10 + horizontal).clamp(0, 127) as u8;
+    }
+}
+
+/// shown as `M` followed by the message index, e.g. `M003`.
+impl Display for BusMsg {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "M{:0>3}", self.0.min(127))
+    }
+}
+
+/// something the tracker does when a subscribed message arrives.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum BusAction {
+    /// e.g. `Transport(RestartFrom(16))` to launch the pattern from step 16.
+    Transport(TransportCmd),
+    Tempo(u16),
+    /// sets one of a track's flags, toggling it if `value` is `None`.
+    Flag {
+        track: usize,
+        flag: TrackFlag,
+        #[serde(default)]
+        value: Option<bool>,
+    },
+    /// sets fill mode, toggling it if `None`.
+    Fill(Option<bool>),
+}
+
+impl BusAction {
+    /// whether the action can be carried out. loop points past the end of the pattern and tempos
+    /// the remote can't set can't, the steps that are jumped to wrap around.
+    pub fn is_valid(&self) -> bool {
+        match self {
+            Self::Transport(TransportCmd::SetLoopStart(step) | TransportCmd::SetLoopEnd(step)) => {
+                *step < N_STEPS
+            }
+            Self::Tempo(bpm) => TEMPO_RANGE.contains(bpm),
+            _ => true,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct Subscription {
+    pub message: String,
+    pub action: BusAction,
+}
+
+/// the project's bus messages and subscriptions, saved with the project.
+#[derive(Resource, Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Bus {
+    /// the messages that bus step commands publish, by index.
+    #[serde(default)]
+    pub messages: Vec<String>,
+    #[serde(default)]
+    pub subscriptions: Vec<Subscription>,
+}
+
+impl Bus {
+    pub fn message(&self, msg: BusMsg) -> Option<&str> {
+        self.messages.get(msg.0 as usize).map(String::as_str)
+    }
+
+    /// the actions subscribed to `message`, in the order they were added.
+    pub fn actions<'a>(&'a self, message: &'a str) -> impl Iterator<Item = &'a BusAction> {
+        self.subscriptions
+            .iter()
+            .filter(move |sub| sub.message == message)
+            .map(|sub| &sub.action)
+    }
+
+    /// every message that's subscribed to, once each.
+    pub fn listen_for(&self) -> Vec<&str> {
+        let mut messages: Vec<&str> = Vec::new();
+
+        for sub in self.subscriptions.iter() {
+            if !messages.contains(&sub.message.as_str()) {
+                messages.push(&sub.message);
+            }
+        }
+
+        messages
+    }
+}
+
+/// sent by the sequencer when a step with a bus command plays.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Event)]
+pub struct PublishBus(pub BusMsg);
+
+fn bus_capable(link: &HostLink) -> bool {
+    link.is_compatible() && link.has_capability(capability::MESSAGE_BUS)
+}
+
+/// sends the messages that steps published to the host.
+pub fn publish_bus(
+    mut published: EventReader<PublishBus>,
+    bus: Res<Bus>,
+    link: Res<HostLink>,
+    mut to_host: EventWriter<FromTracker>,
+) {
+    for PublishBus(msg) in published.read() {
+        if let Some(message) = bus.message(*msg)
+            && bus_capable(&link)
+        {
+            to_host.write(FromTracker::MessageBus {
+                message: message.into(),
+            });
+        }
+    }
+}
+
+/// asks the host for the subscribed messages when it says hello or the subscriptions change, and
+/// carries out the actions subscribed to the messages that arrive. `listening` is what the host
+/// has been asked for, so a message that's no longer subscribed to can be taken back.
+#[allow(clippy::too_many_arguments)]
+pub fn bus_messages(
+    mut from_host: EventReader<FromHost>,
+    bus: Res<Bus>,
+    link: Res<HostLink>,
+    mut listening: Local<Vec<String>>,
+    mut to_host: EventWriter<FromTracker>,
+    mut transport: EventWriter<TransportCmd>,
+    mut flags: EventWriter<SetTrackFlag>,
+    mut tempo: ResMut<Tempo>,
+    mut fill: ResMut<Fill>,
+    mut log: EventWriter<Log>,
+) {
+    let mut said_hello = false;
+
+    for message in from_host.read() {
+        match message {
+            FromHost::Hello { .. } => said_hello = true,
+            FromHost::MessageBus { message } => {
+                for action in bus.actions(message) {
+                    if !action.is_valid() {
+                        log.write(Log::warn(format!(
+                            "{message:?} asks for {action:?}, which is out of range"
+                        )));
+                        continue;
+                    }
+
+                    match *action {
+                        BusAction::Transport(cmd) => {
+                            transport.write(cmd);
+                        }
+                        BusAction::Tempo(bpm) => {
+                            tempo.0 = bpm.clamp(*TEMPO_RANGE.start(), *TEMPO_RANGE.end())
+                        }
+                        BusAction::Flag { track, flag, value } => {
+                            flags.write(SetTrackFlag { track, flag, value });
+                        }
+                        BusAction::Fill(value) => fill.0 = value.unwrap_or(!fill.0),
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    // a host that just said hello hasn't been asked for anything yet.
+    if said_hello {
+        listening.clear();
+    }
+
+    if !(said_hello || bus.is_changed()) || !bus_capable(&link) {
+        return;
+    }
+
+    let subscribed = bus.listen_for();
+
+    if link.has_capability(capability::STOP_LISTENING) {
+        for message in listening.iter() {
+            if !subscribed.contains(&message.as_str()) {
+                to_host.write(FromTracker::StopListeningFor {
+                    message: message.clone(),
+                });
+            }
+        }
+    }
+
+    for message in subscribed.iter() {
+        if !listening.iter().any(|listening| listening == message) {
+            to_host.write(FromTracker::ListenFor {
+                message: (*message).into(),
+            });
+        }
+    }
+
+    *listening = subscribed.into_iter().map(String::from).collect();
+}
@@ -0,0 +1,245 @@
+use crate::MidiNote;
+use bevy::prelude::*;
+use core::fmt::Display;
+use serde::{Deserialize, Serialize};
+use strum_macros::Display;
+
+#[derive(
+    Clone, Copy, Default, Debug, PartialEq, Eq, PartialOrd, Hash, Display, Serialize, Deserialize,
+)]
+pub enum ChordQuality {
+    #[strum(to_string = "5")]
+    Power,
+    #[default]
+    #[strum(to_string = "M")]
+    Maj,
+    #[strum(to_string = "m")]
+    Min,
+    #[strum(to_string = "o")]
+    Dim,
+    #[strum(to_string = "+")]
+    Aug,
+    #[strum(to_string = "s2")]
+    Sus2,
+    #[strum(to_string = "s4")]
+    Sus4,
+    #[strum(to_string = "7")]
+    Dom7,
+    #[strum(to_string = "M7")]
+    Maj7,
+    #[strum(to_string = "m7")]
+    Min7,
+    /// minor seven flat five.
+    #[strum(to_string = "h7")]
+    HalfDim7,
+    #[strum(to_string = "o7")]
+    Dim7,
+    #[strum(to_string = "9")]
+    Dom9,
+    #[strum(to_string = "M9")]
+    Maj9,
+    #[strum(to_string = "m9")]
+    Min9,
+    #[strum(to_string = "11")]
+    Dom11,
+    #[strum(to_string = "m11")]
+    Min11,
+}
+
+impl ChordQuality {
+    /// every quality, in the order they are cycled through.
+    pub const ALL: [ChordQuality; 17] = [
+        Self::Power,
+        Self::Maj,
+        Self::Min,
+        Self::Dim,
+        Self::Aug,
+        Self::Sus2,
+        Self::Sus4,
+        Self::Dom7,
+        Self::Maj7,
+        Self::Min7,
+        Self::HalfDim7,
+        Self::Dim7,
+        Self::Dom9,
+        Self::Maj9,
+        Self::Min9,
+        Self::Dom11,
+        Self::Min11,
+    ];
+
+    /// semitones above the root of each note in the chord, lowest first.
+    pub fn intervals(&self) -> &'static [u8] {
+        match self {
+            Self::Power => &[0, 7],
+            Self::Maj => &[0, 4, 7],
+            Self::Min => &[0, 3, 7],
+            Self::Dim => &[0, 3, 6],
+            Self::Aug => &[0, 4, 8],
+            Self::Sus2 => &[0, 2, 7],
+            Self::Sus4 => &[0, 5, 7],
+            Self::Dom7 => &[0, 4, 7, 10],
+            Self::Maj7 => &[0, 4, 7, 11],
+            Self::Min7 => &[0, 3, 7, 10],
+            Self::HalfDim7 => &[0, 3, 6, 10],
+            Self::Dim7 => &[0, 3, 6, 9],
+            Self::Dom9 => &[0, 4, 7, 10, 14],
+            Self::Maj9 => &[0, 4, 7, 11, 14],
+            Self::Min9 => &[0, 3, 7, 10, 14],
+            Self::Dom11 => &[0, 4, 7, 10, 14, 17],
+            Self::Min11 => &[0, 3, 7, 10, 14, 17],
+        }
+    }
+
+    /// the quality made of exactly these intervals, if there is one.
+    pub fn from_intervals(intervals: &[u8]) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|quality| quality.intervals() == intervals)
+    }
+
+    /// the quality `by` places along from this one.
+    pub fn cycle(&self, by: isize) -> Self {
+        let i = Self::ALL
+            .iter()
+            .position(|quality| quality == self)
+            .unwrap_or(0) as isize;
+
+        Self::ALL[(i + by).rem_euclid(Self::ALL.len() as isize) as usize]
+    }
+
+    /// the name, shortened to 2 characters to leave room for an inversion. `m11` becomes `mB`,
+    /// 11 in hex.
+    pub fn short_code(&self) -> String {
+        match self {
+            Self::Min11 => "mB".into(),
+            quality => quality.to_string(),
+        }
+    }
+}
+
+#[derive(
+    Clone, Copy, Default, Debug, PartialEq, Eq, PartialOrd, Hash, Display, Serialize, Deserialize,
+)]
+pub enum Voicing {
+    /// every note within an octave of the lowest.
+    #[default]
+    Close,
+    /// every other note raised an octave.
+    Spread,
+    /// the second highest note dropped an octave.
+    Drop2,
+    /// the third highest note dropped an octave.
+    Drop3,
+}
+
+impl Voicing {
+    pub const ALL: [Voicing; 4] = [Self::Close, Self::Spread, Self::Drop2, Self::Drop3];
+
+    /// the character added to the chord's cell to show the voicing.
+    pub fn marker(&self) -> Option<char> {
+        match self {
+            Self::Close => None,
+            Self::Spread => Some('S'),
+            Self::Drop2 => Some('d'),
+            Self::Drop3 => Some('D'),
+        }
+    }
+
+    pub fn next(&self) -> Self {
+        let i = Self::ALL
+            .iter()
+            .position(|voicing| voicing == self)
+            .unwrap_or(0);
+
+        Self::ALL[(i + 1) % Self::ALL.len()]
+    }
+}
+
+#[derive(Clone, Copy, Default, Debug, PartialEq, Eq, PartialOrd, Hash, Serialize, Deserialize)]
+pub struct Chord {
+    pub quality: ChordQuality,
+    /// how many of the lowest notes are moved up an octave.
+    #[serde(default)]
+    pub inversion: u8,
+    #[serde(default)]
+    pub voicing: Voicing,
+    /// also play the lowest note an octave down.
+    #[serde(default)]
+    pub double: bool,
+}
+
+impl Chord {
+    pub fn new(quality: ChordQuality) -> Self {
+        Self {
+            quality,
+            ..Default::default()
+        }
+    }
+
+    /// the notes of the chord built on `root`, lowest first. notes outside of the midi range are
+    /// left out.
+    pub fn notes(&self, root: MidiNote) -> Vec<MidiNote> {
+        let mut notes: Vec<i16> = self
+            .quality
+            .intervals()
+            .iter()
+            .map(|interval| *interval as i16)
+            .collect();
+
+        for _ in 0..self.inversion as usize % notes.len() {
+            let lowest = notes.remove(0);
+            notes.push(lowest + 12);
+        }
+
+        let n_notes = notes.len();
+
+        match self.voicing {
+            Voicing::Close => {}
+            Voicing::Spread => notes
+                .iter_mut()
+                .skip(1)
+                .step_by(2)
+                .for_each(|note| *note += 12),
+            Voicing::Drop2 if n_notes >= 2 => notes[n_notes - 2] -= 12,
+            Voicing::Drop3 if n_notes >= 3 => notes[n_notes - 3] -= 12,
+            Voicing::Drop2 | Voicing::Drop3 => {}
+        }
+
+        notes.sort();
+
+        if self.double {
+            notes.insert(0, notes[0] - 12);
+        }
+
+        notes
+            .into_iter()
+            .map(|interval| root as i16 + interval)
+            .filter(|note| (0..128).contains(note))
+            .map(|note| note as MidiNote)
+            .collect()
+    }
+}
+
+/// fits the chord into a 4 character cell, e.g. `m7/1`. the inversion comes after the quality
+/// followed by the voicing and `^` for octave doubling, as far as they fit.
+impl Display for Chord {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        let mut code = if self.inversion > 0 {
+            format!("{}/{}", self.quality.short_code(), self.inversion)
+        } else {
+            self.quality.to_string()
+        };
+
+        for marker in [self.voicing.marker(), self.double.then_some('^')]
+            .into_iter()
+            .flatten()
+        {
+            if code.len() < 4 {
+                code.push(marker);
+            }
+        }
+
+        write!(f, "{code:-<4}")
+    }
+}
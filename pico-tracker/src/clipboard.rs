@@ -0,0 +1,258 @@
+use crate::{MidiCmd, Sf2Cmd, Step, Track, TrackerCmd};
+use bevy::prelude::*;
+use core::{
+    fmt::{Debug, Display},
+    ops::DerefMut,
+};
+use serde::{Deserialize, Serialize};
+
+/// a rectangle of steps, spanning one or more tracks. both ends are inclusive.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct Block {
+    pub first_track: usize,
+    pub last_track: usize,
+    pub first_step: usize,
+    pub last_step: usize,
+}
+
+impl Block {
+    /// the block between two (track, step) corners.
+    pub fn new(a: (usize, usize), b: (usize, usize)) -> Self {
+        Self {
+            first_track: a.0.min(b.0),
+            last_track: a.0.max(b.0),
+            first_step: a.1.min(b.1),
+            last_step: a.1.max(b.1),
+        }
+    }
+
+    pub fn contains(&self, track: usize, step: usize) -> bool {
+        (self.first_track..=self.last_track).contains(&track)
+            && (self.first_step..=self.last_step).contains(&step)
+    }
+
+    pub fn n_steps(&self) -> usize {
+        self.last_step - self.first_step + 1
+    }
+}
+
+/// the steps copied out of one track. also how steps are sent to and from the host.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum ClipTrack {
+    Midi(Vec<Step<MidiCmd>>),
+    SF2(Vec<Step<Sf2Cmd>>),
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Resource)]
+pub struct Clipboard {
+    /// one entry per copied track, left to right.
+    pub tracks: Vec<ClipTrack>,
+}
+
+impl<Cmd> TrackerCmd<Cmd>
+where
+    Cmd: Clone + Default + PartialEq + PartialOrd + ToString + Display,
+{
+    /// converts the command for use on another kind of track. `Custom` commands are converted
+    /// with `custom` and become `None` when it can't convert them.
+    pub fn convert<To>(&self, custom: impl Fn(&Cmd) -> Option<To>) -> TrackerCmd<To>
+    where
+        To: Clone + Default + PartialEq + PartialOrd + ToString + Display,
+    {
+        match self {
+            Self::None => TrackerCmd::None,
+            Self::Chord { chord } => TrackerCmd::Chord { chord: *chord },
+            Self::Roll { roll } => TrackerCmd::Roll { roll: *roll },
+            Self::Arp { arp } => TrackerCmd::Arp { arp: *arp },
+            Self::Cond { cond } => TrackerCmd::Cond { cond: *cond },
+            Self::Bend { bend } => TrackerCmd::Bend { bend: *bend },
+            Self::Porta { porta } => TrackerCmd::Porta { porta: *porta },
+            Self::Legato => TrackerCmd::Legato,
+            Self::Program { program } => TrackerCmd::Program { program: *program },
+            Self::Bus { msg } => TrackerCmd::Bus { msg: *msg },
+            Self::Swing { amt } => TrackerCmd::Swing { amt: *amt },
+            Self::HoldFor { notes } => TrackerCmd::HoldFor { notes: *notes },
+            Self::Panic => TrackerCmd::Panic,
+            Self::Custom(cmd) => custom(cmd).map(TrackerCmd::Custom).unwrap_or_default(),
+        }
+    }
+}
+
+/// midi volume CC
+const VOLUME_CC: u8 = 7;
+
+/// sf2 volume is the only sf2 command with a midi equivalent.
+pub fn sf2_to_midi(cmd: &Sf2Cmd) -> Option<MidiCmd> {
+    match cmd {
+        Sf2Cmd::Volume(vol) => Some(MidiCmd {
+            cc_param: VOLUME_CC,
+            arg_1: (vol.clamp(0.0, 1.0) * 127.0) as u8,
+            arg_2: 0,
+        }),
+        _ => None,
+    }
+}
+
+pub fn midi_to_sf2(cmd: &MidiCmd) -> Option<Sf2Cmd> {
+    (cmd.cc_param == VOLUME_CC).then(|| Sf2Cmd::Volume(cmd.arg_1 as f32 / 127.0))
+}
+
+fn convert_step<A, B>(step: &Step<A>, custom: impl Fn(&A) -> Option<B> + Copy) -> Step<B>
+where
+    A: Clone + Default + PartialEq + PartialOrd + Display + ToString + Debug,
+    B: Clone + Default + PartialEq + PartialOrd + Display + ToString + Debug,
+{
+    Step {
+        note: step.note,
+        cmds: (step.cmds.0.convert(custom), step.cmds.1.convert(custom)),
+        vel: step.vel,
+        delay: step.delay,
+    }
+}
+
+/// writes `src` over `dest`. when `mix` is set only the empty parts of `dest` are filled in.
+fn paste_step<Cmd>(dest: &mut Step<Cmd>, src: Step<Cmd>, mix: bool)
+where
+    Cmd: Clone + Default + PartialEq + PartialOrd + Display + ToString + Debug,
+{
+    if !mix {
+        *dest = src;
+        return;
+    }
+
+    if dest.note.is_none() {
+        dest.note = src.note;
+        dest.vel = src.vel;
+        dest.delay = src.delay;
+    }
+
+    if dest.cmds.0 == TrackerCmd::None {
+        dest.cmds.0 = src.cmds.0;
+    }
+
+    if dest.cmds.1 == TrackerCmd::None {
+        dest.cmds.1 = src.cmds.1;
+    }
+}
+
+fn paste_steps<Cmd>(dest: &mut [Step<Cmd>], at_step: usize, src: Vec<Step<Cmd>>, mix: bool)
+where
+    Cmd: Clone + Default + PartialEq + PartialOrd + Display + ToString + Debug,
+{
+    // anything pasted past the end of the track is dropped.
+    for (dest, src) in dest.iter_mut().skip(at_step).zip(src) {
+        paste_step(dest, src, mix);
+    }
+}
+
+impl ClipTrack {
+    pub fn copy(track: &Track, first_step: usize, n_steps: usize) -> Self {
+        match track {
+            Track::Midi { steps } => Self::Midi(
+                steps
+                    .iter()
+                    .skip(first_step)
+                    .take(n_steps)
+                    .cloned()
+                    .collect(),
+            ),
+            Track::SF2 { steps } => Self::SF2(
+                steps
+                    .iter()
+                    .skip(first_step)
+                    .take(n_steps)
+                    .cloned()
+                    .collect(),
+            ),
+        }
+    }
+
+    /// pastes into `track` starting at `at_step`, converting commands if the track is of a
+    /// different kind.
+    pub fn paste(&self, track: &mut Track, at_step: usize, mix: bool) {
+        match (self, track) {
+            (Self::Midi(src), Track::Midi { steps }) => {
+                paste_steps(steps, at_step, src.clone(), mix)
+            }
+            (Self::SF2(src), Track::SF2 { steps }) => paste_steps(steps, at_step, src.clone(), mix),
+            (Self::Midi(src), Track::SF2 { steps }) => {
+                let src = src
+                    .iter()
+                    .map(|step| convert_step(step, midi_to_sf2))
+                    .collect();
+                paste_steps(steps, at_step, src, mix)
+            }
+            (Self::SF2(src), Track::Midi { steps }) => {
+                let src = src
+                    .iter()
+                    .map(|step| convert_step(step, sf2_to_midi))
+                    .collect();
+                paste_steps(steps, at_step, src, mix)
+            }
+        }
+    }
+}
+
+/// resets every step in `first_step..first_step + n_steps`.
+pub fn clear_steps(track: &mut Track, first_step: usize, n_steps: usize) {
+    match track {
+        Track::Midi { steps } => steps
+            .iter_mut()
+            .skip(first_step)
+            .take(n_steps)
+            .for_each(|step| *step = Step::default()),
+        Track::SF2 { steps } => steps
+            .iter_mut()
+            .skip(first_step)
+            .take(n_steps)
+            .for_each(|step| *step = Step::default()),
+    }
+}
+
+impl Clipboard {
+    /// the block that pasting at `at_track`, `at_step` would write to.
+    pub fn block_at(&self, at_track: usize, at_step: usize) -> Block {
+        let n_steps = self
+            .tracks
+            .iter()
+            .map(|track| match track {
+                ClipTrack::Midi(steps) => steps.len(),
+                ClipTrack::SF2(steps) => steps.len(),
+            })
+            .max()
+            .unwrap_or(0);
+
+        Block {
+            first_track: at_track,
+            last_track: at_track + self.tracks.len().max(1) - 1,
+            first_step: at_step,
+            last_step: at_step + n_steps.max(1) - 1,
+        }
+    }
+
+    /// copies `block` out of `tracks`, which are indexed by track id.
+    pub fn copy(tracks: &[&Track], block: Block) -> Self {
+        Self {
+            tracks: tracks
+                .iter()
+                .skip(block.first_track)
+                .take(block.last_track - block.first_track + 1)
+                .map(|track| ClipTrack::copy(track, block.first_step, block.n_steps()))
+                .collect(),
+        }
+    }
+
+    /// pastes with the top left corner at `at_track`, `at_step`. tracks that fall past the last
+    /// track are dropped.
+    pub fn paste<T: DerefMut<Target = Track>>(
+        &self,
+        tracks: &mut [T],
+        at_track: usize,
+        at_step: usize,
+        mix: bool,
+    ) {
+        for (track, clip) in tracks.iter_mut().skip(at_track).zip(self.tracks.iter()) {
+            clip.paste(track, at_step, mix);
+        }
+    }
+}
@@ -0,0 +1,174 @@
+use bevy::prelude::*;
+use core::fmt::Display;
+use rand::{Rng, SeedableRng, rngs::SmallRng};
+use serde::{Deserialize, Serialize};
+
+/// seed for the conditions' random numbers. each roll mixes in the track, step and loop, so
+/// probabilities play out the same way every time playback restarts, and a track's rolls don't
+/// depend on what the other tracks play.
+pub const COND_SEED: u64 = 0x636f_6e64;
+
+/// whether fill mode is on. `Fill` conditions only play while it is.
+#[derive(Clone, Copy, Default, Debug, PartialEq, Eq, Hash, Resource, Deref, DerefMut)]
+pub struct Fill(pub bool);
+
+/// a condition on a step playing.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Hash, Serialize, Deserialize)]
+pub enum Cond {
+    /// plays this percentage of the time.
+    Prob(u8),
+    /// plays on the `a`th of every `b` loops, counting from 1.
+    Ratio {
+        a: u8,
+        b: u8,
+    },
+    Fill,
+    NotFill,
+    /// only on the first time through the pattern.
+    First,
+    NotFirst,
+    /// plays if the last condition on the track (other than `Pre` and `NotPre`) was true.
+    Pre,
+    NotPre,
+}
+
+impl Default for Cond {
+    fn default() -> Self {
+        Self::Prob(50)
+    }
+}
+
+/// what a condition is checked against.
+#[derive(Clone, Copy, Default, Debug, PartialEq, Eq, Hash)]
+pub struct CondContext {
+    /// how many times the pattern has looped since playback started.
+    pub loop_count: usize,
+    pub fill: bool,
+    /// the result of the last condition on the track.
+    pub prev: bool,
+}
+
+impl Cond {
+    /// every kind of condition, in the order they are cycled through.
+    pub const ALL: [Cond; 8] = [
+        Self::Prob(50),
+        Self::Ratio { a: 1, b: 2 },
+        Self::Fill,
+        Self::NotFill,
+        Self::First,
+        Self::NotFirst,
+        Self::Pre,
+        Self::NotPre,
+    ];
+
+    pub fn evaluate(&self, ctx: &CondContext, rng: &mut SmallRng) -> bool {
+        match *self {
+            Self::Prob(percent) => rng.random_range(0..100) < percent,
+            Self::Ratio { a, b } => ctx.loop_count % b.max(1) as usize == a.max(1) as usize - 1,
+            Self::Fill => ctx.fill,
+            Self::NotFill => !ctx.fill,
+            Self::First => ctx.loop_count == 0,
+            Self::NotFirst => ctx.loop_count != 0,
+            Self::Pre => ctx.prev,
+            Self::NotPre => !ctx.prev,
+        }
+    }
+
+    /// whether the result is what later `Pre` and `NotPre` conditions look at.
+    pub fn is_linkable(&self) -> bool {
+        !matches!(self, Self::Pre | Self::NotPre)
+    }
+
+    /// the next kind of condition.
+    pub fn next(&self) -> Self {
+        let i = Self::ALL
+            .iter()
+            .position(|cond| core::mem::discriminant(cond) == core::mem::discriminant(self))
+            .unwrap_or(0);
+
+        Self::ALL[(i + 1) % Self::ALL.len()]
+    }
+
+    /// nudges the condition's values, up/down being `vertical` and left/right `horizontal`.
+    /// probabilities move 10% at a time vertically and 1% horizontally, ratios change `a`
+    /// vertically and `b` horizontally.
+    pub fn adjust(&mut self, vertical: isize, horizontal: isize) {
+        match self {
+            Self::Prob(percent) => {
+                *percent = (*percent as isize + vertical * 10 + horizontal).clamp(0, 100) as u8
+            }
+            Self::Ratio { a, b } => {
+                *b = (*b as isize + horizontal).clamp(2, 8) as u8;
+                *a = (*a as isize + vertical).clamp(1, *b as isize) as u8;
+            }
+            _ => {}
+        }
+    }
+}
+
+/// fits the condition into a 4 character cell, e.g. `P50-` or `3:4-`.
+impl Display for Cond {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        let code = match self {
+            Self::Prob(percent) => format!("P{percent}"),
+            Self::Ratio { a, b } => format!("{a}:{b}"),
+            Self::Fill => "Fill".into(),
+            Self::NotFill => "!Fil".into(),
+            Self::First => "1st".into(),
+            Self::NotFirst => "!1st".into(),
+            Self::Pre => "Pre".into(),
+            Self::NotPre => "!Pre".into(),
+        };
+
+        write!(f, "{code:-<4}")
+    }
+}
+
+/// what the sequencer needs to evaluate conditions, reset whenever playback restarts.
+#[derive(Resource, Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct CondState {
+    pub loop_count: usize,
+    /// the last step that was played, to see when the pattern loops.
+    pub last_step: Option<usize>,
+}
+
+impl CondState {
+    /// call at the start of every step, counts a loop when playback jumps back.
+    pub fn next_step(&mut self, step: usize) {
+        if self.last_step.is_some_and(|last| step <= last) {
+            self.loop_count += 1;
+        }
+
+        self.last_step = Some(step);
+    }
+
+    /// whether `step` of `track`, with `cond`, should play. `prev` is the track's last condition
+    /// result, and is updated with this one.
+    pub fn check(
+        &self,
+        cond: Option<&Cond>,
+        track: usize,
+        step: usize,
+        fill: bool,
+        prev: &mut bool,
+    ) -> bool {
+        let Some(cond) = cond else {
+            return true;
+        };
+
+        let ctx = CondContext {
+            loop_count: self.loop_count,
+            fill,
+            prev: *prev,
+        };
+        let seed =
+            COND_SEED ^ ((track as u64) << 48) ^ ((step as u64) << 32) ^ self.loop_count as u64;
+        let res = cond.evaluate(&ctx, &mut SmallRng::seed_from_u64(seed));
+
+        if cond.is_linkable() {
+            *prev = res;
+        }
+
+        res
+    }
+}
@@ -1,10 +1,20 @@
 use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
 
 // TODO: Make a macro to build a "LessThan" type for any given numeric type
 
-#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Deref)]
+#[derive(
+    Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Deref, Serialize, Deserialize,
+)]
+#[serde(try_from = "usize", into = "usize")]
 pub struct UsizeLessThan<const LT: usize>(usize);
 
+impl<const LT: usize> From<UsizeLessThan<LT>> for usize {
+    fn from(value: UsizeLessThan<LT>) -> Self {
+        value.0
+    }
+}
+
 impl<const LT: usize> TryFrom<usize> for UsizeLessThan<LT> {
     type Error = String;
 
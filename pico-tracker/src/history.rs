@@ -0,0 +1,163 @@
+use crate::{
+    MidiCmd, Sf2Cmd, Step, Track,
+    clipboard::{Block, ClipTrack, Clipboard},
+};
+use alloc::collections::VecDeque;
+use bevy::prelude::*;
+use core::{mem::size_of, ops::DerefMut};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum EditKind {
+    Note,
+    Cmd,
+    Cut,
+    Paste,
+    Transform,
+    /// clearing a cell. never merged with the edit before it, so undo brings the cell back.
+    Delete,
+}
+
+/// one undoable edit, stored as the steps of `block` from before and after the edit.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Edit {
+    pub kind: EditKind,
+    pub block: Block,
+    pub before: Clipboard,
+    pub after: Clipboard,
+}
+
+impl Edit {
+    /// roughly how much heap the edit is holding on to.
+    pub fn n_bytes(&self) -> usize {
+        let clip_bytes = |clip: &Clipboard| -> usize {
+            clip.tracks
+                .iter()
+                .map(|track| match track {
+                    ClipTrack::Midi(steps) => steps.len() * size_of::<Step<MidiCmd>>(),
+                    ClipTrack::SF2(steps) => steps.len() * size_of::<Step<Sf2Cmd>>(),
+                })
+                .sum()
+        };
+
+        size_of::<Self>() + clip_bytes(&self.before) + clip_bytes(&self.after)
+    }
+
+    /// whether `other` is another edit of the same single cell, like holding a key down to
+    /// scroll through notes. those get merged in to one undo step.
+    fn merges_with(&self, other: &Edit) -> bool {
+        matches!(self.kind, EditKind::Note | EditKind::Cmd)
+            && self.kind == other.kind
+            && self.block == other.block
+            && self.block.n_steps() == 1
+            && self.block.first_track == self.block.last_track
+    }
+}
+
+/// how much heap the history may use, for edits made outside of the binary, which owns the heap.
+/// the binary keeps it up to date.
+#[derive(Resource, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct HistoryBudget(pub usize);
+
+#[derive(Clone, Debug, Default, PartialEq, Resource)]
+pub struct History {
+    undo: VecDeque<Edit>,
+    redo: Vec<Edit>,
+    n_bytes: usize,
+}
+
+impl History {
+    /// runs `edit` on `tracks` (indexed by track id) and records it. `max_bytes` is how much heap
+    /// the history may use, the oldest edits are forgotten to stay under it.
+    pub fn apply<T: DerefMut<Target = Track>>(
+        &mut self,
+        tracks: &mut [T],
+        kind: EditKind,
+        block: Block,
+        max_bytes: usize,
+        edit: impl FnOnce(&mut [T]),
+    ) {
+        let before = Self::snapshot(tracks, block);
+        edit(tracks);
+        let after = Self::snapshot(tracks, block);
+
+        if before == after {
+            return;
+        }
+
+        self.record(
+            Edit {
+                kind,
+                block,
+                before,
+                after,
+            },
+            max_bytes,
+        );
+    }
+
+    fn snapshot<T: DerefMut<Target = Track>>(tracks: &[T], block: Block) -> Clipboard {
+        let tracks: Vec<&Track> = tracks.iter().map(|track| &**track).collect();
+        Clipboard::copy(&tracks, block)
+    }
+
+    pub fn record(&mut self, edit: Edit, max_bytes: usize) {
+        self.redo.clear();
+
+        if let Some(last) = self.undo.back_mut()
+            && last.merges_with(&edit)
+        {
+            self.n_bytes -= last.n_bytes();
+            last.after = edit.after;
+            self.n_bytes += last.n_bytes();
+        } else {
+            self.n_bytes += edit.n_bytes();
+            self.undo.push_back(edit);
+        }
+
+        while self.n_bytes > max_bytes
+            && let Some(oldest) = self.undo.pop_front()
+        {
+            self.n_bytes -= oldest.n_bytes();
+        }
+    }
+
+    /// reverts the last edit. returns false if there was nothing to undo.
+    pub fn undo<T: DerefMut<Target = Track>>(&mut self, tracks: &mut [T]) -> bool {
+        let Some(edit) = self.undo.pop_back() else {
+            return false;
+        };
+
+        edit.before
+            .paste(tracks, edit.block.first_track, edit.block.first_step, false);
+        self.n_bytes -= edit.n_bytes();
+        self.redo.push(edit);
+
+        true
+    }
+
+    /// re-applies the last undone edit. returns false if there was nothing to redo.
+    pub fn redo<T: DerefMut<Target = Track>>(&mut self, tracks: &mut [T]) -> bool {
+        let Some(edit) = self.redo.pop() else {
+            return false;
+        };
+
+        edit.after
+            .paste(tracks, edit.block.first_track, edit.block.first_step, false);
+        self.n_bytes += edit.n_bytes();
+        self.undo.push_back(edit);
+
+        true
+    }
+
+    pub fn n_bytes(&self) -> usize {
+        self.n_bytes
+    }
+
+    pub fn can_undo(&self) -> bool {
+        !self.undo.is_empty()
+    }
+
+    pub fn can_redo(&self) -> bool {
+        !self.redo.is_empty()
+    }
+}
@@ -0,0 +1,237 @@
+//! the handshake with the host, and keeping track of the requests sent to it.
+use crate::{
+    bus::{Bus, PublishBus, bus_messages, publish_bus},
+    logging::Log,
+    remote::{Remote, remote_cmds, remote_messages},
+    routing::{HostDevs, close_routes, route_replies, routing_messages, send_routes},
+};
+use bevy::prelude::*;
+use pico_tracker_types::{FromHost, FromTracker, PROTOCOL_VERSION, capability, is_compatible};
+
+/// how many requests wait for a reply before the oldest is forgotten.
+pub const MAX_PENDING: usize = 16;
+
+pub struct HostPlugin;
+
+impl Plugin for HostPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<HostLink>()
+            .init_resource::<HostDevs>()
+            .init_resource::<Bus>()
+            .add_event::<FromHost>()
+            .add_event::<FromTracker>()
+            .add_event::<HostReply>()
+            .add_event::<HostClosed>()
+            .add_event::<PublishBus>()
+            .add_event::<Remote>()
+            .add_systems(
+                Update,
+                (
+                    host_closed,
+                    close_routes,
+                    host_messages,
+                    routing_messages,
+                    route_replies,
+                    send_routes,
+                    bus_messages,
+                    publish_bus,
+                    remote_messages,
+                    remote_cmds,
+                )
+                    .chain(),
+            );
+    }
+}
+
+/// the host's answer to a request sent with `HostLink::request`.
+#[derive(Clone, Debug, PartialEq, Eq, Event)]
+pub struct HostReply {
+    pub id: u32,
+    pub result: Result<(), String>,
+}
+
+/// sent when the host closes the serial port.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Event)]
+pub struct HostClosed;
+
+/// what the host said in its `Hello`, and the requests it hasn't answered yet.
+#[derive(Resource, Clone, Debug, Default, PartialEq, Eq)]
+pub struct HostLink {
+    /// `None` until the host says hello.
+    pub protocol_version: Option<u16>,
+    pub host_version: String,
+    pub capabilities: Vec<String>,
+    next_id: u32,
+    /// the id of each request waiting for an `Ack` or `Error`, with what it was for.
+    pending: Vec<(u32, String)>,
+}
+
+impl HostLink {
+    /// records the host's `Hello`. requests sent before it are dropped, the host won't answer them.
+    pub fn hello(
+        &mut self,
+        protocol_version: u16,
+        host_version: String,
+        capabilities: Vec<String>,
+    ) {
+        self.protocol_version = Some(protocol_version);
+        self.host_version = host_version;
+        self.capabilities = capabilities;
+        self.pending.clear();
+    }
+
+    /// forgets the host's `Hello` and its unanswered requests, once it's closed the serial port.
+    /// ids aren't reused, in case a late reply arrives.
+    pub fn close(&mut self) {
+        self.protocol_version = None;
+        self.host_version.clear();
+        self.capabilities.clear();
+        self.pending.clear();
+    }
+
+    /// whether the host said hello with a protocol version we can talk to.
+    pub fn is_compatible(&self) -> bool {
+        self.protocol_version.is_some_and(is_compatible)
+    }
+
+    pub fn has_capability(&self, capability: &str) -> bool {
+        self.capabilities.iter().any(|c| c == capability)
+    }
+
+    /// whether tracks set to the host output can send their notes to it.
+    pub fn takes_notes(&self) -> bool {
+        self.is_compatible() && self.has_capability(capability::NOTES_OUT)
+    }
+
+    /// the id to send with a new request, `what` is used to describe it if it fails.
+    pub fn request(&mut self, what: impl Into<String>) -> u32 {
+        let id = self.next_id;
+        self.next_id = self.next_id.wrapping_add(1);
+
+        if self.pending.len() >= MAX_PENDING {
+            self.pending.remove(0);
+        }
+
+        self.pending.push((id, what.into()));
+
+        id
+    }
+
+    /// marks the request as answered, returning what it was for.
+    pub fn answer(&mut self, id: u32) -> Option<String> {
+        let i = self
+            .pending
+            .iter()
+            .position(|(pending, _)| *pending == id)?;
+
+        Some(self.pending.remove(i).1)
+    }
+
+    /// how many requests are waiting for a reply.
+    pub fn pending(&self) -> usize {
+        self.pending.len()
+    }
+}
+
+/// the tracker's `Hello`, sent to the host when it opens the serial port.
+pub fn hello() -> FromTracker {
+    FromTracker::Hello {
+        protocol_version: PROTOCOL_VERSION,
+        firmware_version: env!("CARGO_PKG_VERSION").into(),
+        capabilities: alloc::vec![
+            capability::NOTES_IN.into(),
+            capability::ROUTING.into(),
+            capability::MESSAGE_BUS.into(),
+            capability::NOTES_OUT.into(),
+            capability::REMOTE.into(),
+            capability::PROJECT_SYNC.into(),
+            capability::LOG_LEVELS.into(),
+        ],
+    }
+}
+
+/// answers a request from the host: with the reply if there is one, otherwise with an `Ack` if the
+/// request had an id, or an `Error` if it failed.
+pub fn reply_to_host(
+    id: Option<u32>,
+    result: Result<Option<FromTracker>, String>,
+    to_host: &mut EventWriter<FromTracker>,
+) {
+    match result {
+        Ok(Some(reply)) => {
+            to_host.write(reply);
+        }
+        Ok(None) => {
+            if let Some(id) = id {
+                to_host.write(FromTracker::Ack { id });
+            }
+        }
+        Err(message) => {
+            to_host.write(FromTracker::Error { id, message });
+        }
+    }
+}
+
+fn host_closed(
+    mut closed: EventReader<HostClosed>,
+    mut link: ResMut<HostLink>,
+    mut log: EventWriter<Log>,
+) {
+    if closed.read().count() > 0 {
+        link.close();
+        log.write(Log::info("the host closed the serial port"));
+    }
+}
+
+fn host_messages(
+    mut link: ResMut<HostLink>,
+    mut from_host: EventReader<FromHost>,
+    mut to_host: EventWriter<FromTracker>,
+    mut replies: EventWriter<HostReply>,
+    mut log: EventWriter<Log>,
+) {
+    for message in from_host.read() {
+        match message {
+            FromHost::Hello {
+                protocol_version,
+                host_version,
+                capabilities,
+            } => {
+                link.hello(
+                    *protocol_version,
+                    host_version.clone(),
+                    capabilities.clone(),
+                );
+
+                if !link.is_compatible() {
+                    let message = format!(
+                        "protocol version {protocol_version} isn't supported, expected {PROTOCOL_VERSION}"
+                    );
+                    log.write(Log::error(message.clone()));
+                    to_host.write(FromTracker::Error { id: None, message });
+                }
+            }
+            FromHost::Ack { id } if link.answer(*id).is_some() => {
+                replies.write(HostReply {
+                    id: *id,
+                    result: Ok(()),
+                });
+            }
+            FromHost::Error { id, message } => {
+                match id.and_then(|id| Some((id, link.answer(id)?))) {
+                    Some((id, what)) => {
+                        log.write(Log::error(format!("{what} failed: {message}")));
+                        replies.write(HostReply {
+                            id,
+                            result: Err(message.clone()),
+                        });
+                    }
+                    None => {
+                        log.write(Log::error(format!("host error: {message}")));
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+}
@@ -7,9 +7,22 @@ use cortex_m_semihosting::debug;
 
 // use defmt_rtt as _; // global logger
 
-use crate::helpers::less_then::UsizeLessThan;
+use crate::{
+    arp::Arp,
+    bend::{Bend, Porta},
+    bus::BusMsg,
+    chord::Chord,
+    cond::Cond,
+    helpers::less_then::UsizeLessThan,
+    midi_plugin::{ArpState, BendState, LastCondition, PendingHits, SoundingNotes},
+    program::Program,
+    roll::{Hit, Roll},
+    routing::TrackRoute,
+    scale::Key,
+};
 use bevy::prelude::*;
 use core::{fmt::Display, ops::Index};
+use serde::{Deserialize, Serialize};
 use strum_macros::{Display, EnumString};
 
 use panic_probe as _;
@@ -17,14 +30,36 @@ use panic_probe as _;
 #[cfg(not(all(test, target_arch = "x86_64")))]
 pub use picocalc_bevy::hal;
 
+pub mod arp;
 #[cfg(not(all(test, target_arch = "x86_64")))]
 pub mod base_plugin;
+pub mod bend;
+pub mod bus;
+pub mod chord;
+pub mod clipboard;
+pub mod cond;
 #[cfg(not(all(test, target_arch = "x86_64")))]
 pub mod embedded;
 pub mod helpers;
+pub mod history;
+pub mod host;
+pub mod logging;
+pub mod midi_monitor;
 pub mod midi_plugin;
+pub mod program;
+#[cfg(not(all(test, target_arch = "x86_64")))]
+pub mod project;
+pub mod remote;
+pub mod roll;
+pub mod routing;
+pub mod scale;
+#[cfg(not(all(test, target_arch = "x86_64")))]
+pub mod sync;
+pub mod transform;
 
 pub type MidiNote = u8;
+/// the largest note, velocity or other data byte midi can send.
+pub const MIDI_MAX: u8 = 127;
 
 pub const SCREEN_W: usize = 320;
 pub const SCREEN_H: usize = 320;
@@ -35,6 +70,11 @@ pub const Y_OFFSET: i32 = 11;
 pub const COL_W: usize = 18;
 pub const CHAR_PIX_W: i32 = 8;
 pub const CHAR_PIX_H: i32 = 13;
+pub const N_TRACKS: usize = 8;
+/// how many tracks are drawn side by side.
+pub const TRACKS_ON_SCREEN: usize = 2;
+/// the note cell followed by the two command cells.
+pub const CELLS_PER_TRACK: usize = 3;
 
 // // same panicking *behavior* as `panic-probe` but doesn't print a panic message
 // // this prevents the panic message being printed *twice* when `defmt::panic` is invoked
@@ -79,13 +119,138 @@ pub struct CmdPallet(pub bool);
 #[derive(Clone, Copy, Default, Debug, States, PartialEq, Eq, Hash, Resource, Deref, DerefMut)]
 pub struct EdittingCell(pub bool);
 
-#[derive(Clone, Copy, Default, Debug, States, PartialEq, Eq, Hash, Resource, Deref, DerefMut)]
-pub struct Playing(pub bool);
+/// which screen is shown.
+#[derive(Clone, Copy, Default, Debug, PartialEq, Eq, Hash, Resource)]
+pub enum Screen {
+    #[default]
+    Tracker,
+    /// routing tracks to the host's midi devices.
+    Routing,
+    /// the recent log messages.
+    Log,
+    /// the midi sent and received.
+    Midi,
+}
+
+/// text that's only shown on one screen.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Component, Deref, DerefMut)]
+pub struct OnScreen(pub Screen);
+
+#[derive(Clone, Copy, Default, Debug, PartialEq, Eq, Hash)]
+pub enum TransportState {
+    #[default]
+    Stopped,
+    Playing,
+    Paused,
+}
+
+#[derive(Clone, Copy, Default, Debug, PartialEq, Eq, Hash, Resource)]
+pub struct Transport {
+    pub state: TransportState,
+    /// the first and last step (inclusive) that playback loops over. loops the whole pattern
+    /// when `None`.
+    pub loop_range: Option<(usize, usize)>,
+}
+
+/// where the sync pulses come from. only the internal timer is implemented.
+#[derive(Clone, Copy, Default, Debug, PartialEq, Eq, Hash, Resource, Display)]
+pub enum ClockSource {
+    #[default]
+    #[strum(to_string = "INT")]
+    Internal,
+}
+
+#[derive(Clone, Copy, Default, Debug, PartialEq, Eq, Hash, Display)]
+pub enum EditMode {
+    #[default]
+    #[strum(to_string = "NOTE")]
+    Note,
+    /// note entry locked to the key.
+    #[strum(to_string = "SCAL")]
+    Scale,
+    #[strum(to_string = "CMD")]
+    Cmd,
+    /// playing with a track armed.
+    #[strum(to_string = "REC")]
+    Record,
+}
+
+/// the values shown in the status bar, on the line between the titles and the tracks.
+#[derive(Clone, Copy, Default, Debug, PartialEq, Eq, Hash)]
+pub struct StatusLine {
+    pub tempo: u16,
+    pub state: TransportState,
+    /// bar, beat, and step within the beat. all counted from 1. only the last two digits of the
+    /// bar are shown.
+    pub position: (usize, usize, usize),
+    pub mode: EditMode,
+    pub clock: ClockSource,
+    pub key: Key,
+    pub loop_range: Option<(usize, usize)>,
+    /// whether fill mode is on.
+    pub fill: bool,
+}
+
+impl Display for StatusLine {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        let state = match (self.state, self.mode) {
+            (TransportState::Playing, EditMode::Record) => "REC ",
+            (TransportState::Playing, _) if self.fill => "FILL",
+            (TransportState::Playing, _) => "PLAY",
+            (TransportState::Paused, _) => "PAUS",
+            (TransportState::Stopped, _) => "STOP",
+        };
+        let (bar, beat, step) = self.position;
+
+        write!(
+            f,
+            "{:>3}BPM {state} {:>2}:{beat}:{step} {:<4} {} {}",
+            self.tempo,
+            bar % 100,
+            self.mode,
+            self.clock,
+            self.key
+        )?;
+
+        if let Some((start, end)) = self.loop_range {
+            write!(f, " L{start:0>2}-{end:0>2}")?;
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Event, Serialize, Deserialize)]
+pub enum TransportCmd {
+    /// start playing, or continue if paused.
+    Play,
+    /// stop playing and rewind to step 0.
+    Stop,
+    /// pause if playing, continue otherwise.
+    TogglePause,
+    /// start playing from the given step.
+    RestartFrom(usize),
+    SetLoopStart(usize),
+    SetLoopEnd(usize),
+    ClearLoop,
+    /// moves playback to the given step without starting or stopping it.
+    Locate(usize),
+}
 
 #[derive(Clone, Copy, Default, Debug, States, PartialEq, Eq, Hash, Resource, Deref, DerefMut)]
 pub struct Tempo(pub u16);
 
-#[derive(Clone, Debug, Component, PartialEq, PartialOrd)]
+#[derive(Clone, Debug, Component, PartialEq, PartialOrd, Serialize, Deserialize)]
+#[require(
+    TrackProgram,
+    TrackOutput,
+    TrackRoute,
+    SoundingNotes,
+    ArpState,
+    LastCondition,
+    PendingHits,
+    BendState
+)]
 pub enum Track {
     Midi { steps: Vec<Step<MidiCmd>> },
     SF2 { steps: Vec<Step<Sf2Cmd>> },
@@ -99,7 +264,26 @@ impl Default for Track {
     }
 }
 
-#[derive(Clone, Default, Debug, PartialEq, PartialOrd)]
+impl Track {
+    /// fixes up a track read from a file: `N_STEPS` steps, with notes and velocities midi can send.
+    pub fn sanitise(&mut self) {
+        match self {
+            Self::Midi { steps } => sanitise_steps(steps),
+            Self::SF2 { steps } => sanitise_steps(steps),
+        }
+    }
+}
+
+fn sanitise_steps<Cmd>(steps: &mut Vec<Step<Cmd>>)
+where
+    Cmd:
+        Clone + Default + PartialEq + PartialOrd + core::fmt::Display + ToString + core::fmt::Debug,
+{
+    steps.resize_with(N_STEPS, Step::default);
+    steps.iter_mut().for_each(Step::sanitise);
+}
+
+#[derive(Clone, Default, Debug, PartialEq, PartialOrd, Serialize, Deserialize)]
 pub struct Step<Cmd>
 where
     Cmd:
@@ -107,9 +291,156 @@ where
 {
     pub note: Option<MidiNote>,
     pub cmds: (TrackerCmd<Cmd>, TrackerCmd<Cmd>),
+    /// note on velocity, `None` uses the default velocity.
+    #[serde(default)]
+    pub vel: Option<u8>,
+    /// how many sync pulses after the start of the step the note is played.
+    #[serde(default)]
+    pub delay: u8,
+}
+
+impl<Cmd> Step<Cmd>
+where
+    Cmd:
+        Clone + Default + PartialEq + PartialOrd + core::fmt::Display + ToString + core::fmt::Debug,
+{
+    /// both of the step's commands.
+    fn each_cmd(&self) -> [&TrackerCmd<Cmd>; 2] {
+        [&self.cmds.0, &self.cmds.1]
+    }
+
+    /// the first thing `f` picks out of the step's commands.
+    pub fn find_cmd<T>(&self, f: impl Fn(&TrackerCmd<Cmd>) -> Option<&T>) -> Option<&T> {
+        self.each_cmd().into_iter().find_map(f)
+    }
+
+    /// the first chord in the step's commands.
+    pub fn chord(&self) -> Option<&Chord> {
+        self.find_cmd(|cmd| match cmd {
+            TrackerCmd::Chord { chord } => Some(chord),
+            _ => None,
+        })
+    }
+
+    /// the first arp in the step's commands.
+    pub fn arp(&self) -> Option<&Arp> {
+        self.find_cmd(|cmd| match cmd {
+            TrackerCmd::Arp { arp } => Some(arp),
+            _ => None,
+        })
+    }
+
+    /// the first condition in the step's commands.
+    pub fn cond(&self) -> Option<&Cond> {
+        self.find_cmd(|cmd| match cmd {
+            TrackerCmd::Cond { cond } => Some(cond),
+            _ => None,
+        })
+    }
+
+    /// the first roll in the step's commands.
+    pub fn roll(&self) -> Option<&Roll> {
+        self.find_cmd(|cmd| match cmd {
+            TrackerCmd::Roll { roll } => Some(roll),
+            _ => None,
+        })
+    }
+
+    /// the first pitch bend in the step's commands.
+    pub fn bend(&self) -> Option<&Bend> {
+        self.find_cmd(|cmd| match cmd {
+            TrackerCmd::Bend { bend } => Some(bend),
+            _ => None,
+        })
+    }
+
+    /// the first portamento command in the step's commands.
+    pub fn porta(&self) -> Option<&Porta> {
+        self.find_cmd(|cmd| match cmd {
+            TrackerCmd::Porta { porta } => Some(porta),
+            _ => None,
+        })
+    }
+
+    /// the first program change in the step's commands.
+    pub fn program(&self) -> Option<&Program> {
+        self.find_cmd(|cmd| match cmd {
+            TrackerCmd::Program { program } => Some(program),
+            _ => None,
+        })
+    }
+
+    /// the first bus message in the step's commands.
+    pub fn publish(&self) -> Option<&BusMsg> {
+        self.find_cmd(|cmd| match cmd {
+            TrackerCmd::Bus { msg } => Some(msg),
+            _ => None,
+        })
+    }
+
+    /// whether the step's notes are held until the next step's notes start.
+    pub fn legato(&self) -> bool {
+        self.each_cmd()
+            .into_iter()
+            .any(|cmd| matches!(cmd, TrackerCmd::Legato))
+    }
+
+    /// whether the note and velocity are ones midi can send, and any roll's hits fit in a cell.
+    pub fn is_valid(&self) -> bool {
+        self.note.is_none_or(|note| note <= MIDI_MAX)
+            && self.vel.is_none_or(|vel| vel <= MIDI_MAX)
+            && self.each_cmd().into_iter().all(|cmd| match cmd {
+                TrackerCmd::Roll { roll } => roll.times <= Roll::MAX_TIMES,
+                _ => true,
+            })
+    }
+
+    /// clamps the note and velocity to ones midi can send, and rolls to the hits that fit in a
+    /// cell.
+    pub fn sanitise(&mut self) {
+        self.note = self.note.map(|note| note.min(MIDI_MAX));
+        self.vel = self.vel.map(|vel| vel.min(MIDI_MAX));
+
+        for cmd in [&mut self.cmds.0, &mut self.cmds.1] {
+            if let TrackerCmd::Roll { roll } = cmd {
+                roll.times = roll.times.min(Roll::MAX_TIMES);
+            }
+        }
+    }
+
+    /// the notes to play for the step, the chord built on the step's note if it has one.
+    pub fn notes(&self) -> Vec<MidiNote> {
+        match (self.note, self.chord()) {
+            (Some(note), Some(chord)) => chord.notes(note),
+            (Some(note), None) => alloc::vec![note],
+            (None, _) => Vec::new(),
+        }
+    }
+
+    /// when the step's notes start over a step of `pps` pulses. one hit at the step's delay, or
+    /// the roll's hits spread over the rest of the step.
+    pub fn hits(&self, pps: usize, default_vel: u8) -> Vec<Hit> {
+        let notes = self.notes();
+
+        if notes.is_empty() {
+            return Vec::new();
+        }
+
+        let delay = (self.delay as usize).min(pps.max(1) - 1);
+        let vel = self.vel.unwrap_or(default_vel);
+
+        match self.roll() {
+            Some(roll) => roll.hits(&notes, vel, delay, pps.max(1) - delay),
+            None => alloc::vec![Hit {
+                pulse: delay,
+                notes,
+                vel,
+            }],
+        }
+    }
 }
 
-#[derive(Clone, Copy, Default, Debug, PartialEq, PartialOrd, Eq, Hash)]
+#[derive(Clone, Copy, Default, Debug, PartialEq, PartialOrd, Eq, Hash, Serialize, Deserialize)]
 pub enum Intervals {
     #[default]
     Root,
@@ -123,7 +454,36 @@ pub enum Intervals {
     SharpSeventh,
 }
 
-#[derive(Clone, Default, Debug, PartialEq, Eq, PartialOrd, Hash, EnumString, Display)]
+impl Intervals {
+    /// how many semitones above the root the interval is.
+    pub fn semitones(&self) -> u8 {
+        match self {
+            Self::Root => 0,
+            Self::MinThird => 3,
+            Self::MajThird => 4,
+            Self::FlatFifth => 6,
+            Self::Fifth => 7,
+            Self::SharpFifth => 8,
+            Self::FlatSeventh => 10,
+            Self::Seventh => 11,
+            Self::SharpSeventh => 12,
+        }
+    }
+}
+
+#[derive(
+    Clone,
+    Default,
+    Debug,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Hash,
+    EnumString,
+    Display,
+    Serialize,
+    Deserialize,
+)]
 pub enum TrackerCmd<Cmd>
 where
     Cmd: Clone + Default + PartialEq + PartialOrd + ToString + Display,
@@ -131,14 +491,31 @@ where
     #[default]
     #[strum(to_string = "----")]
     None,
-    #[strum(to_string = "Chrd")]
-    Chord { chord: Vec<Intervals> },
-    #[strum(to_string = "Roll")]
-    Roll {
-        /// how many extra times to "roll" what ever is being played. a value of 1 would produce
-        /// two 64th notes.
-        times: usize,
-    },
+    #[strum(to_string = "{chord}")]
+    Chord { chord: Chord },
+    /// retriggers the step's notes.
+    #[strum(to_string = "{roll}")]
+    Roll { roll: Roll },
+    /// arpeggiates the step's chord, or the notes held on the midi controller.
+    #[strum(to_string = "{arp}")]
+    Arp { arp: Arp },
+    /// only plays the step when the condition is met.
+    #[strum(to_string = "{cond}")]
+    Cond { cond: Cond },
+    #[strum(to_string = "{bend}")]
+    Bend { bend: Bend },
+    /// portamento on/off, and its time.
+    #[strum(to_string = "{porta}")]
+    Porta { porta: Porta },
+    /// holds the step's notes until the next step's notes have started.
+    #[strum(to_string = "Lgto")]
+    Legato,
+    /// changes the program, and the bank if it's set, before the step's notes play.
+    #[strum(to_string = "{program}")]
+    Program { program: Program },
+    /// publishes one of the project's messages on the host's message bus.
+    #[strum(to_string = "{msg}")]
+    Bus { msg: BusMsg },
     // NOTE: maybe remove Swing
     #[strum(to_string = "Swng")]
     Swing {
@@ -158,7 +535,7 @@ where
 
 // TODO: impl Display for TrackerCmd
 
-#[derive(Clone, Copy, Default, Debug, PartialEq, Eq, PartialOrd, Hash)]
+#[derive(Clone, Copy, Default, Debug, PartialEq, Eq, PartialOrd, Hash, Serialize, Deserialize)]
 pub struct MidiCmd {
     cc_param: u8,
     arg_1: u8,
@@ -171,7 +548,17 @@ impl Display for MidiCmd {
     }
 }
 
-#[derive(Clone, Copy, Debug, PartialEq, PartialOrd, EnumString, strum_macros::Display)]
+#[derive(
+    Clone,
+    Copy,
+    Debug,
+    PartialEq,
+    PartialOrd,
+    EnumString,
+    strum_macros::Display,
+    Serialize,
+    Deserialize,
+)]
 pub enum Sf2Cmd {
     #[strum(to_string = "Atk-")]
     Atk(usize),
@@ -193,10 +580,88 @@ impl Default for Sf2Cmd {
     }
 }
 
-#[derive(Clone, Copy, Default, Debug, States, PartialEq, Eq, Hash, Component)]
+#[derive(
+    Clone, Copy, Default, Debug, States, PartialEq, Eq, Hash, Component, Serialize, Deserialize,
+)]
 pub struct TrackID {
     pub id: usize,
-    pub playing: bool,
+    pub muted: bool,
+    pub solo: bool,
+    /// armed tracks are the ones that incoming notes get recorded to.
+    pub armed: bool,
+}
+
+impl TrackID {
+    /// whether the sequencer should play this track, `any_solo` being true if any track is soloed.
+    pub fn audible(&self, any_solo: bool) -> bool {
+        !self.muted && (!any_solo || self.solo)
+    }
+
+    /// the mute/solo/arm flags as shown in the track title.
+    pub fn flags_display(&self) -> String {
+        [
+            if self.muted { 'M' } else { '-' },
+            if self.solo { 'S' } else { '-' },
+            if self.armed { 'R' } else { '-' },
+        ]
+        .into_iter()
+        .collect()
+    }
+}
+
+/// the program (and bank) a track's instrument is set to when playback starts and when the
+/// project is loaded. `None` leaves the instrument as it is.
+#[derive(Clone, Copy, Default, Debug, PartialEq, Eq, Hash, Component, Deref, DerefMut)]
+pub struct TrackProgram(pub Option<Program>);
+
+/// where a track's midi is sent.
+#[derive(Clone, Copy, Default, Debug, PartialEq, Eq, Hash, Component, Serialize, Deserialize)]
+pub enum TrackOutput {
+    /// usb midi packets.
+    #[default]
+    Usb,
+    /// note events over the serial link, for the host to send on with its own routing. played over
+    /// usb midi while there's no host that takes them.
+    Host,
+}
+
+impl TrackOutput {
+    pub fn next(self) -> Self {
+        match self {
+            Self::Usb => Self::Host,
+            Self::Host => Self::Usb,
+        }
+    }
+
+    /// one character for the routing screen.
+    pub fn code(&self) -> char {
+        match self {
+            Self::Usb => 'U',
+            Self::Host => 'H',
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum TrackFlag {
+    Mute,
+    Solo,
+    Arm,
+}
+
+/// sets one of a tracks flags, toggling it if `value` is `None`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Event)]
+pub struct SetTrackFlag {
+    pub track: usize,
+    pub flag: TrackFlag,
+    pub value: Option<bool>,
+}
+
+/// plays a single step, including its rolls, while the transport is stopped.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Event)]
+pub struct PreviewStep {
+    pub track: usize,
+    pub step: usize,
 }
 
 #[derive(Clone, Copy, Default, Debug, States, PartialEq, Eq, Hash, Resource, Deref, DerefMut)]
@@ -210,6 +675,23 @@ pub fn x_from_col(col_i: usize) -> i32 {
     CHAR_PIX_W * col_i as i32
 }
 
+/// the id of the track that the cursor column `col` falls in.
+pub fn track_from_cursor_col(col: usize) -> usize {
+    col / CELLS_PER_TRACK
+}
+
+/// returns the first track to display so that `track` is on screen, scrolling as little as
+/// possible.
+pub fn scroll_view_to(first_view: usize, track: usize) -> usize {
+    if track < first_view {
+        track
+    } else if track >= first_view + TRACKS_ON_SCREEN {
+        track + 1 - TRACKS_ON_SCREEN
+    } else {
+        first_view
+    }
+}
+
 pub fn display_midi_note(midi_note: MidiNote) -> String {
     let note_name_i = midi_note % 12;
     let octave = midi_note / 12;
@@ -222,8 +704,8 @@ pub fn display_midi_note(midi_note: MidiNote) -> String {
     format!("{note_name}{octave:X}")
 }
 
-pub fn playing(am_playing: Res<Playing>) -> bool {
-    **am_playing
+pub fn playing(transport: Res<Transport>) -> bool {
+    transport.state == TransportState::Playing
 }
 
 // #[cfg(all(test, target_arch = "x86_64"))]
@@ -263,4 +745,1127 @@ mod test {
                 .as_str()
         )
     }
+
+    #[test]
+    fn track_view_scrolling() {
+        assert_eq!(track_from_cursor_col(0), 0);
+        assert_eq!(track_from_cursor_col(CELLS_PER_TRACK * 3 + 2), 3);
+
+        // already on screen
+        assert_eq!(scroll_view_to(2, 3), 2);
+        // scroll right just enough to show the track
+        assert_eq!(scroll_view_to(0, 5), 5 + 1 - TRACKS_ON_SCREEN);
+        // scroll left
+        assert_eq!(scroll_view_to(4, 1), 1);
+    }
+
+    #[test]
+    fn track_audibility() {
+        let plain = TrackID::default();
+        let muted = TrackID {
+            muted: true,
+            ..default()
+        };
+        let solo = TrackID {
+            solo: true,
+            ..default()
+        };
+
+        assert!(plain.audible(false));
+        assert!(!plain.audible(true));
+        assert!(!muted.audible(false));
+        assert!(solo.audible(true));
+        assert_eq!(solo.flags_display().as_str(), "-S-");
+    }
+
+    #[test]
+    fn loop_range_wrapping() {
+        use crate::midi_plugin::loop_pulse;
+
+        let pps = 6;
+
+        // no loop, or inside the loop, nothing changes
+        assert_eq!(loop_pulse(5 * pps, pps, None), 5 * pps);
+        assert_eq!(loop_pulse(5 * pps, pps, Some((4, 7))), 5 * pps);
+        // stepping past the end jumps to the next pass over the loop start
+        assert_eq!(loop_pulse(8 * pps, pps, Some((4, 7))), (N_STEPS + 4) * pps);
+        // before the start jumps forward to it
+        assert_eq!(loop_pulse(2 * pps, pps, Some((4, 7))), 4 * pps);
+        // only jumps on step boundaries
+        assert_eq!(loop_pulse(8 * pps + 1, pps, Some((4, 7))), 8 * pps + 1);
+    }
+
+    #[test]
+    fn status_line_display() {
+        let mut status = StatusLine {
+            tempo: 120,
+            state: TransportState::Playing,
+            position: (1, 2, 3),
+            mode: EditMode::Cmd,
+            clock: ClockSource::Internal,
+            key: Key::default(),
+            loop_range: None,
+            fill: false,
+        };
+
+        assert_eq!(
+            status.to_string().as_str(),
+            "120BPM PLAY  1:2:3 CMD  INT C-Maj"
+        );
+
+        status.fill = true;
+        assert_eq!(
+            status.to_string().as_str(),
+            "120BPM FILL  1:2:3 CMD  INT C-Maj"
+        );
+
+        status.mode = EditMode::Record;
+        status.key.root = 1;
+        status.key.scale = scale::Scale::Lydian;
+        status.loop_range = Some((4, 7));
+        assert_eq!(
+            status.to_string().as_str(),
+            "120BPM REC   1:2:3 REC  INT C#Lyd L04-07"
+        );
+        assert!(status.to_string().len() <= CHAR_W);
+
+        status.position = (123, 4, 4);
+        assert_eq!(
+            status.to_string().as_str(),
+            "120BPM REC  23:4:4 REC  INT C#Lyd L04-07"
+        );
+        assert!(status.to_string().len() <= CHAR_W);
+    }
+
+    #[test]
+    fn clipboard_copy_paste() {
+        use crate::clipboard::{Block, Clipboard};
+
+        let mut src = Track::default();
+        let mut dest = Track::SF2 {
+            steps: (0..N_STEPS).map(|_| Step::default()).collect(),
+        };
+
+        if let Track::Midi { steps } = &mut src {
+            steps[1].note = Some(60);
+            steps[2].note = Some(62);
+            steps[2].cmds.0 = TrackerCmd::Panic;
+        }
+
+        if let Track::SF2 { steps } = &mut dest {
+            steps[5].note = Some(40);
+        }
+
+        let clip = Clipboard::copy(&[&src], Block::new((0, 2), (0, 1)));
+        assert_eq!(clip.tracks.len(), 1);
+
+        // paste-mix keeps the existing note at step 5
+        clip.paste(&mut [&mut dest], 0, 4, true);
+
+        let Track::SF2 { steps } = &dest else {
+            panic!("track kind changed");
+        };
+        assert_eq!(steps[4].note, Some(60));
+        assert_eq!(steps[5].note, Some(40));
+        assert!(steps[5].cmds.0 == TrackerCmd::Panic);
+
+        // a plain paste overwrites
+        clip.paste(&mut [&mut dest], 0, 4, false);
+
+        let Track::SF2 { steps } = &dest else {
+            panic!("track kind changed");
+        };
+        assert_eq!(steps[5].note, Some(62));
+    }
+
+    #[test]
+    fn undo_redo_history() {
+        use crate::{
+            clipboard::{Block, Clipboard},
+            history::{EditKind, History},
+        };
+
+        let note_at = |track: &Track, step: usize| match track {
+            Track::Midi { steps } => steps[step].note,
+            Track::SF2 { steps } => steps[step].note,
+        };
+        let set_note = |tracks: &mut [&mut Track], track: usize, step: usize, note: u8| {
+            if let Track::Midi { steps } = &mut *tracks[track] {
+                steps[step].note = Some(note);
+            }
+        };
+
+        let mut a = Track::default();
+        let mut b = Track::default();
+        let mut history = History::default();
+        let cell = Block::new((0, 3), (0, 3));
+
+        // scrolling through notes on one cell is one undo step
+        for note in 60..64 {
+            history.apply(
+                &mut [&mut a, &mut b],
+                EditKind::Note,
+                cell,
+                usize::MAX,
+                |tracks| set_note(tracks, 0, 3, note),
+            );
+        }
+
+        // then paste that cell on to the second track
+        let clip = Clipboard::copy(&[&a], cell);
+        let paste_block = clip.block_at(1, 5);
+        history.apply(
+            &mut [&mut a, &mut b],
+            EditKind::Paste,
+            paste_block,
+            usize::MAX,
+            |tracks| clip.paste(tracks, 1, 5, false),
+        );
+
+        assert_eq!(note_at(&a, 3), Some(63));
+        assert_eq!(note_at(&b, 5), Some(63));
+
+        assert!(history.undo(&mut [&mut a, &mut b]));
+        assert_eq!(note_at(&b, 5), None);
+        assert_eq!(note_at(&a, 3), Some(63));
+
+        assert!(history.undo(&mut [&mut a, &mut b]));
+        assert_eq!(note_at(&a, 3), None);
+        assert!(!history.undo(&mut [&mut a, &mut b]));
+
+        assert!(history.redo(&mut [&mut a, &mut b]));
+        assert_eq!(note_at(&a, 3), Some(63));
+
+        // deleting right after an edit of the same cell is its own undo step
+        history.apply(
+            &mut [&mut a, &mut b],
+            EditKind::Note,
+            cell,
+            usize::MAX,
+            |tracks| set_note(tracks, 0, 3, 64),
+        );
+        history.apply(
+            &mut [&mut a, &mut b],
+            EditKind::Delete,
+            cell,
+            usize::MAX,
+            |tracks| {
+                if let Track::Midi { steps } = &mut *tracks[0] {
+                    steps[3].note = None;
+                }
+            },
+        );
+        assert!(history.undo(&mut [&mut a, &mut b]));
+        assert_eq!(note_at(&a, 3), Some(64));
+
+        // a new edit drops the redo stack
+        history.apply(
+            &mut [&mut a, &mut b],
+            EditKind::Note,
+            cell,
+            usize::MAX,
+            |tracks| set_note(tracks, 0, 3, 10),
+        );
+        assert!(!history.can_redo());
+
+        // a tiny budget only keeps what fits
+        history.apply(
+            &mut [&mut a, &mut b],
+            EditKind::Note,
+            Block::new((0, 0), (0, 0)),
+            0,
+            |tracks| set_note(tracks, 0, 0, 1),
+        );
+        assert!(!history.can_undo());
+        assert_eq!(history.n_bytes(), 0);
+    }
+
+    #[test]
+    fn block_transforms() {
+        use crate::{midi_plugin::DEFAULT_VEL, transform::Transform};
+        use rand::{SeedableRng, rngs::SmallRng};
+
+        let mut rng = SmallRng::seed_from_u64(0);
+        let mut track = Track::default();
+        let notes = |track: &Track| -> Vec<Option<u8>> {
+            match track {
+                Track::Midi { steps } => steps[..4].iter().map(|step| step.note).collect(),
+                Track::SF2 { steps } => steps[..4].iter().map(|step| step.note).collect(),
+            }
+        };
+
+        if let Track::Midi { steps } = &mut track {
+            steps[0].note = Some(60);
+            steps[1].note = Some(127);
+            steps[3].note = Some(66);
+        }
+
+        Transform::Transpose(1).apply(&mut track, 0, 4, &mut rng);
+        // the top note can't go any higher
+        assert!(notes(&track) == [Some(61), Some(127), None, Some(67)]);
+
+        Transform::Transpose(-1).apply(&mut track, 0, 4, &mut rng);
+        Transform::Reverse.apply(&mut track, 0, 4, &mut rng);
+        assert!(notes(&track) == [Some(66), None, Some(126), Some(60)]);
+
+        Transform::Rotate(-1).apply(&mut track, 0, 4, &mut rng);
+        assert!(notes(&track) == [None, Some(126), Some(60), Some(66)]);
+        Transform::Rotate(1).apply(&mut track, 0, 4, &mut rng);
+
+        Transform::FillEvery(2).apply(&mut track, 0, 4, &mut rng);
+        assert!(notes(&track) == [Some(66), None, Some(66), Some(60)]);
+
+        // F# isn't in C major, it rounds down to F
+        let c_major = Key::default();
+        assert!(!c_major.contains(66));
+        Transform::Quantise(c_major).apply(&mut track, 0, 4, &mut rng);
+        assert!(notes(&track) == [Some(65), None, Some(65), Some(60)]);
+
+        Transform::Humanise { vel: 10, timing: 5 }.apply(&mut track, 0, 4, &mut rng);
+
+        if let Track::Midi { steps } = &track {
+            for step in steps[..4].iter().filter(|step| step.note.is_some()) {
+                let vel = step.vel.unwrap();
+                assert!((DEFAULT_VEL - 10..=DEFAULT_VEL + 10).contains(&vel));
+                assert!(step.delay <= 5);
+            }
+
+            assert_eq!(steps[1].vel, None);
+        }
+
+        // a programmed accent and delay are jittered around, not replaced
+        if let Track::Midi { steps } = &mut track {
+            steps[0].vel = Some(127);
+            steps[0].delay = 3;
+        }
+
+        Transform::Humanise { vel: 10, timing: 2 }.apply(&mut track, 0, 1, &mut rng);
+
+        if let Track::Midi { steps } = &track {
+            assert!((117..=127).contains(&steps[0].vel.unwrap()));
+            assert!((1..=5).contains(&steps[0].delay));
+        }
+    }
+
+    #[test]
+    fn keys_and_scales() {
+        use crate::{chord::ChordQuality, scale::Scale};
+
+        let d_dorian = Key {
+            root: 2,
+            scale: Scale::Dorian,
+            lock: true,
+        };
+
+        // D dorian has the same notes as C major
+        assert!((0..128).all(|note| d_dorian.contains(note) == Key::default().contains(note)));
+
+        // stepping through the scale from E: F, G, then back down
+        assert_eq!(d_dorian.step(64, 1), 65);
+        assert_eq!(d_dorian.step(64, 2), 67);
+        assert_eq!(d_dorian.step(64, -3), 59);
+        // out of key notes land on the next note in the key
+        assert_eq!(d_dorian.step(61, 1), 62);
+        // there's nowhere higher to go
+        assert_eq!(d_dorian.step(127, 1), 127);
+        assert_eq!(d_dorian.step(1, -1), 0);
+        assert_eq!(d_dorian.step(0, -1), 0);
+
+        let a_minor_pent = Key {
+            root: 9,
+            scale: Scale::MinorPentatonic,
+            lock: false,
+        };
+        assert_eq!(a_minor_pent.step(57, 1), 60);
+        assert_eq!(a_minor_pent.quantise(56), 55);
+
+        let custom = Scale::custom(0, [64, 67, 76]);
+        assert!(custom == Scale::Custom(0b1001_0001));
+        assert!(Scale::MinorPentatonic.cycle(1) == Scale::Major);
+        assert!(custom.cycle(1) == Scale::Major);
+        assert!(Scale::Major.cycle(-1) == Scale::MinorPentatonic);
+
+        // diatonic chords in C major
+        let c_major = Key::default();
+        let quality = |key: Key, note, seventh| key.chord(note, seventh).map(|chord| chord.quality);
+        assert!(quality(c_major, 60, false) == Some(ChordQuality::Maj));
+        assert!(quality(c_major, 62, true) == Some(ChordQuality::Min7));
+        assert!(quality(c_major, 67, true) == Some(ChordQuality::Dom7));
+        assert!(quality(c_major, 71, false) == Some(ChordQuality::Dim));
+        assert!(quality(c_major, 71, true) == Some(ChordQuality::HalfDim7));
+        // the pentatonic scale has no third on D
+        let c_major_pent = Key {
+            root: 0,
+            scale: Scale::MajorPentatonic,
+            lock: false,
+        };
+        assert!(quality(c_major_pent, 62, false) == Some(ChordQuality::Power));
+    }
+
+    #[test]
+    fn chord_voicings() {
+        use crate::chord::{Chord, ChordQuality, Voicing};
+
+        let mut chord = Chord::new(ChordQuality::Min7);
+        assert!(chord.notes(60) == [60, 63, 67, 70]);
+        assert_eq!(chord.to_string().as_str(), "m7--");
+
+        chord.inversion = 1;
+        assert!(chord.notes(60) == [63, 67, 70, 72]);
+        assert_eq!(chord.to_string().as_str(), "m7/1");
+
+        chord.inversion = 0;
+        chord.voicing = Voicing::Drop2;
+        assert!(chord.notes(60) == [55, 60, 63, 70]);
+
+        chord.voicing = Voicing::Spread;
+        chord.double = true;
+        assert!(chord.notes(60) == [48, 60, 67, 75, 82]);
+        assert_eq!(chord.to_string().as_str(), "m7S^");
+
+        // notes past the top of the midi range are dropped
+        assert!(Chord::new(ChordQuality::Maj).notes(124) == [124]);
+        assert_eq!(Chord::new(ChordQuality::Min11).to_string().as_str(), "m11-");
+        let m11 = Chord {
+            inversion: 1,
+            ..Chord::new(ChordQuality::Min11)
+        };
+        assert_eq!(m11.to_string().as_str(), "mB/1");
+        // a new chord cycles from the start, so up makes it major
+        assert!(ChordQuality::ALL[0].cycle(1) == ChordQuality::Maj);
+
+        let mut step = Step::<MidiCmd> {
+            note: Some(60),
+            ..Default::default()
+        };
+        assert!(step.notes() == [60]);
+
+        step.cmds.1 = TrackerCmd::Chord {
+            chord: Chord::new(ChordQuality::Sus4),
+        };
+        assert!(step.notes() == [60, 65, 67]);
+        assert_eq!(step.cmds.1.to_string().as_str(), "s4--");
+    }
+
+    #[test]
+    fn arp_patterns() {
+        use crate::arp::{Arp, ArpMode};
+        use rand::{SeedableRng, rngs::SmallRng};
+
+        let mut rng = SmallRng::seed_from_u64(0);
+        let notes = [67, 60, 64];
+        let mut arp = Arp::default();
+        let mut pattern = |arp: &Arp, n: usize| -> Vec<Option<u8>> {
+            (0..n).map(|i| arp.note_at(&notes, i, &mut rng)).collect()
+        };
+
+        assert!(pattern(&arp, 4) == [Some(60), Some(64), Some(67), Some(60)]);
+
+        arp.mode = ArpMode::Down;
+        assert!(pattern(&arp, 4) == [Some(67), Some(64), Some(60), Some(67)]);
+
+        arp.mode = ArpMode::UpDown;
+        assert!(pattern(&arp, 5) == [Some(60), Some(64), Some(67), Some(64), Some(60)]);
+
+        arp.mode = ArpMode::AsPlayed;
+        arp.octaves = 2;
+        assert!(pattern(&arp, 6) == [Some(67), Some(60), Some(64), Some(79), Some(72), Some(76)]);
+
+        arp.mode = ArpMode::Random;
+        assert!(
+            pattern(&arp, 16)
+                .into_iter()
+                .all(|note| note.is_some_and(|note| [60, 64, 67, 72, 76, 79].contains(&note)))
+        );
+        assert_eq!(arp.note_at(&[], 0, &mut rng), None);
+
+        assert_eq!(arp.to_string().as_str(), "ArpR");
+        assert_eq!(
+            TrackerCmd::<MidiCmd>::Arp { arp }.to_string().as_str(),
+            "ArpR"
+        );
+
+        // rate 3, gate 50%
+        assert_eq!(Arp::default().gate_pulses(), Some(1));
+        arp.gate = 100;
+        assert_eq!(arp.gate_pulses(), None);
+    }
+
+    #[test]
+    fn conditional_trigs() {
+        use crate::cond::{Cond, CondState};
+
+        // whether `cond` plays on loop number `loops`.
+        let plays_on = |cond: Cond, loops: usize, fill: bool| -> bool {
+            let state = CondState {
+                loop_count: loops,
+                ..Default::default()
+            };
+
+            state.check(Some(&cond), 0, 0, fill, &mut false)
+        };
+
+        assert!(plays_on(Cond::Ratio { a: 1, b: 2 }, 0, false));
+        assert!(!plays_on(Cond::Ratio { a: 1, b: 2 }, 1, false));
+        assert!(plays_on(Cond::Ratio { a: 3, b: 4 }, 6, false));
+        assert!(!plays_on(Cond::Ratio { a: 3, b: 4 }, 7, false));
+        assert!(plays_on(Cond::Fill, 0, true));
+        assert!(!plays_on(Cond::Fill, 0, false));
+        assert!(plays_on(Cond::NotFill, 0, false));
+        assert!(plays_on(Cond::First, 0, false));
+        assert!(!plays_on(Cond::NotFirst, 0, false));
+        assert!(plays_on(Cond::NotFirst, 3, false));
+        assert!(plays_on(Cond::Prob(100), 0, false));
+        assert!(!plays_on(Cond::Prob(0), 0, false));
+
+        // the pattern loops when playback goes back to an earlier step
+        let mut state = CondState::default();
+        for step in [0, 1, 2, 0, 1, 1] {
+            state.next_step(step);
+        }
+        assert_eq!(state.loop_count, 2);
+
+        // probabilities come out the same each time playback restarts, and each track rolls
+        // its own
+        let rolls = |track: usize| -> Vec<bool> {
+            let state = CondState::default();
+            (0..32)
+                .map(|step| state.check(Some(&Cond::Prob(50)), track, step, false, &mut false))
+                .collect()
+        };
+        assert!(rolls(0) == rolls(0));
+        assert!(rolls(0).contains(&true) && rolls(0).contains(&false));
+        assert_ne!(rolls(0), rolls(1));
+
+        // pre follows the last condition that wasn't a pre
+        let state = CondState::default();
+        let mut prev = false;
+        assert!(state.check(Some(&Cond::First), 0, 0, false, &mut prev));
+        assert!(state.check(Some(&Cond::Pre), 0, 1, false, &mut prev));
+        assert!(!state.check(Some(&Cond::NotPre), 0, 2, false, &mut prev));
+        assert!(state.check(Some(&Cond::Pre), 0, 3, false, &mut prev));
+        assert!(state.check(None, 0, 4, false, &mut prev));
+
+        let mut cond = Cond::Ratio { a: 4, b: 4 };
+        cond.adjust(0, -2);
+        assert!(cond == Cond::Ratio { a: 2, b: 2 });
+        assert_eq!(cond.to_string().as_str(), "2:2-");
+        assert_eq!(Cond::Prob(5).to_string().as_str(), "P5--");
+        assert!(Cond::NotPre.next() == Cond::Prob(50));
+        assert_eq!(
+            TrackerCmd::<MidiCmd>::Cond { cond: Cond::First }
+                .to_string()
+                .as_str(),
+            "1st-"
+        );
+    }
+
+    #[test]
+    fn roll_hits() {
+        use crate::roll::{Hit, Roll};
+
+        let mut step: Step<MidiCmd> = Step {
+            note: Some(60),
+            vel: Some(100),
+            ..Default::default()
+        };
+
+        // without a roll the notes play once, on the delay
+        step.delay = 2;
+        assert!(
+            step.hits(6, 111)
+                == alloc::vec![Hit {
+                    pulse: 2,
+                    notes: alloc::vec![60],
+                    vel: 100,
+                }]
+        );
+
+        // hits are spread over the rest of the step
+        step.delay = 0;
+        step.cmds.0 = TrackerCmd::Roll {
+            roll: Roll {
+                times: 2,
+                vel_ramp: -20,
+                pitch_step: 12,
+            },
+        };
+        let hits = step.hits(6, 111);
+        assert!(hits.iter().map(|hit| hit.pulse).collect::<Vec<_>>() == alloc::vec![0, 2, 4]);
+        assert!(hits.iter().map(|hit| hit.vel).collect::<Vec<_>>() == alloc::vec![100, 80, 60]);
+        assert!(hits.iter().map(|hit| hit.notes[0]).collect::<Vec<_>>() == alloc::vec![60, 72, 84]);
+
+        // never more than one hit per pulse
+        let roll = Roll {
+            times: 8,
+            ..Default::default()
+        };
+        let hits = roll.hits(&[60], 100, 4, 2);
+        assert!(hits.iter().map(|hit| hit.pulse).collect::<Vec<_>>() == alloc::vec![4, 5]);
+
+        // velocities stay in range
+        let roll = Roll {
+            times: 3,
+            vel_ramp: 64,
+            pitch_step: 0,
+        };
+        assert!(
+            roll.hits(&[60], 100, 0, 6)
+                .iter()
+                .map(|hit| hit.vel)
+                .collect::<Vec<_>>()
+                == alloc::vec![100, 127, 127, 127]
+        );
+
+        assert_eq!(roll.to_string().as_str(), "R4<-");
+        assert_eq!(
+            TrackerCmd::<MidiCmd>::Roll {
+                roll: Roll {
+                    pitch_step: -1,
+                    ..Default::default()
+                }
+            }
+            .to_string()
+            .as_str(),
+            "R2-v"
+        );
+
+        // the editor stops at the hits that fit in a step
+        let mut roll = Roll::default();
+        roll.adjust(20, 0, 6);
+        assert_eq!(roll.times, 5);
+        assert_eq!(roll.hits(&[60], 100, 0, 6).len(), 6);
+        roll.adjust(20, 0, 48);
+        assert_eq!(roll.times, 8);
+        roll.adjust(-20, 0, 1);
+        assert_eq!(roll.times, 1);
+
+        // rolls from a file are clamped to what fits in a cell
+        step.cmds.1 = TrackerCmd::Roll {
+            roll: Roll {
+                times: 255,
+                ..Default::default()
+            },
+        };
+        assert_eq!(step.cmds.1.to_string().as_str(), "R255--");
+        assert!(!step.is_valid());
+        step.sanitise();
+        assert!(
+            step.cmds.1
+                == TrackerCmd::Roll {
+                    roll: Roll {
+                        times: 8,
+                        ..Default::default()
+                    }
+                }
+        );
+        assert!(step.is_valid());
+    }
+
+    #[test]
+    fn bends_and_porta() {
+        use crate::bend::{BEND_CENTER, Bend, BendRamp, PORTA_CC, PORTA_TIME_CC, Porta};
+
+        assert_eq!(Bend::default().value(), BEND_CENTER);
+        assert_eq!(
+            Bend {
+                amount: -64,
+                ramp: false
+            }
+            .value(),
+            0
+        );
+        assert_eq!(
+            Bend {
+                amount: 63,
+                ramp: false
+            }
+            .value(),
+            16256
+        );
+
+        let mut bend = Bend::default();
+        bend.adjust(10, 0);
+        assert_eq!(bend.amount, 63);
+        bend.adjust(-2, -3);
+        assert_eq!(bend.amount, 44);
+        assert_eq!(bend.to_string().as_str(), "B+44");
+        bend.ramp = true;
+        bend.amount = -5;
+        assert_eq!(bend.to_string().as_str(), "~-5-");
+
+        // ramps glide evenly and hold at the end
+        let ramp = BendRamp {
+            from: BEND_CENTER,
+            to: 0,
+            start_pulse: 10,
+            pulses: 4,
+        };
+        assert_eq!(ramp.value_at(10), BEND_CENTER);
+        assert_eq!(ramp.value_at(12), BEND_CENTER / 2);
+        assert_eq!(ramp.value_at(20), 0);
+        assert!(!ramp.is_done(13));
+        assert!(ramp.is_done(14));
+
+        assert!(Porta { time: 0 }.ccs() == alloc::vec![(PORTA_CC, 0)]);
+        assert!(Porta { time: 40 }.ccs() == alloc::vec![(PORTA_TIME_CC, 40), (PORTA_CC, 127)]);
+        assert_eq!(Porta { time: 0 }.to_string().as_str(), "GOff");
+        assert_eq!(Porta { time: 7 }.to_string().as_str(), "G007");
+
+        let step: Step<MidiCmd> = Step {
+            cmds: (TrackerCmd::None, TrackerCmd::Legato),
+            ..Default::default()
+        };
+        assert!(step.legato());
+        assert_eq!(step.cmds.1.to_string().as_str(), "Lgto");
+
+        use crate::midi_plugin::held_over;
+
+        let next: Step<MidiCmd> = Step {
+            note: Some(60),
+            ..Default::default()
+        };
+        let track = Track::Midi {
+            steps: alloc::vec![step, next],
+        };
+        let played = PendingHits {
+            step: Some(0),
+            step_start: 0,
+            ..Default::default()
+        };
+        assert!(held_over(&track, &played, Some(0), 1, 6, 6));
+        // the legato step didn't play the last time round, it was muted or its condition failed.
+        assert!(!held_over(&track, &played, Some(0), 1, 12, 6));
+        assert!(!held_over(
+            &track,
+            &PendingHits::default(),
+            Some(0),
+            1,
+            6,
+            6
+        ));
+    }
+
+    #[test]
+    fn program_changes() {
+        use crate::program::{BANK_LSB_CC, BANK_MSB_CC, Program};
+
+        assert!(Program::default().bank_ccs().is_empty());
+        assert!(
+            Program {
+                program: 5,
+                bank: Some(300),
+            }
+            .bank_ccs()
+                == alloc::vec![(BANK_MSB_CC, 2), (BANK_LSB_CC, 44)]
+        );
+
+        let mut program = Program::default();
+        program.adjust(-1, 0);
+        assert_eq!(program.program, 0);
+        program.adjust(13, 2);
+        assert_eq!(program.program, 127);
+        program.adjust(-12, 0);
+        assert_eq!(program.to_string().as_str(), "#007");
+
+        let step: Step<MidiCmd> = Step {
+            cmds: (TrackerCmd::Program { program }, TrackerCmd::None),
+            ..Default::default()
+        };
+        assert!(step.program() == Some(&program));
+    }
+
+    #[test]
+    fn host_requests() {
+        use crate::host::{HostLink, MAX_PENDING};
+
+        let mut link = HostLink::default();
+        assert!(!link.is_compatible());
+
+        let first = link.request("connect 1");
+        let second = link.request("connect 2");
+        assert_ne!(first, second);
+        assert!(link.answer(second) == Some("connect 2".into()));
+        assert!(link.answer(second).is_none());
+
+        // the oldest request is forgotten once too many are waiting
+        for _ in 0..MAX_PENDING {
+            link.request("devs");
+        }
+        assert_eq!(link.pending(), MAX_PENDING);
+        assert!(link.answer(first).is_none());
+
+        // a new hello drops requests from before it
+        link.hello(
+            pico_tracker_types::PROTOCOL_VERSION,
+            "1.0".into(),
+            alloc::vec!["routing".into()],
+        );
+        assert!(link.is_compatible());
+        assert!(link.has_capability("routing"));
+        assert!(!link.has_capability("message-bus"));
+        assert_eq!(link.pending(), 0);
+
+        link.hello(
+            pico_tracker_types::PROTOCOL_VERSION + 1,
+            "2.0".into(),
+            Vec::new(),
+        );
+        assert!(!link.is_compatible());
+
+        // closing the port forgets the host, but not which ids were used
+        link.hello(
+            pico_tracker_types::PROTOCOL_VERSION,
+            "1.0".into(),
+            alloc::vec!["notes-out".into()],
+        );
+        let before = link.request("devs");
+        assert!(link.takes_notes());
+        link.close();
+        assert!(!link.is_compatible());
+        assert!(!link.takes_notes());
+        assert_eq!(link.pending(), 0);
+        assert_ne!(link.request("devs"), before);
+    }
+
+    #[test]
+    fn route_picking() {
+        use crate::routing::{HostDevs, Route};
+
+        let devs = HostDevs {
+            names: alloc::vec!["synth".into(), "drums".into()],
+            waiting: false,
+        };
+
+        // no route sits between the last and first devices
+        assert!(devs.cycle(None, 1) == Some("synth".into()));
+        assert!(devs.cycle(Some("synth"), 1) == Some("drums".into()));
+        assert!(devs.cycle(Some("drums"), 1).is_none());
+        assert!(devs.cycle(None, -1) == Some("drums".into()));
+        // a device the host no longer lists starts over
+        assert!(devs.cycle(Some("gone"), 1) == Some("synth".into()));
+        assert!(HostDevs::default().cycle(None, 1).is_none());
+
+        let mut route = Route {
+            dev: "synth".into(),
+            dev_channel: 15,
+        };
+        route.adjust_channel(1);
+        assert_eq!(route.dev_channel, 0);
+        route.adjust_channel(-1);
+        assert_eq!(route.dev_channel, 15);
+    }
+
+    #[test]
+    fn note_backends() {
+        use crate::{
+            base_plugin::MidiEnv,
+            midi_plugin::{MidiBackend, SentNotes},
+        };
+
+        let mut sent = SentNotes::default();
+        let on = MidiEnv::On { note: 60, vel: 100 };
+        let off = MidiEnv::Off { note: 60 };
+
+        assert!(MidiBackend::of(TrackOutput::Host, false) == MidiBackend::Usb);
+        assert!(MidiBackend::of(TrackOutput::Host, true) == MidiBackend::Host);
+
+        // a note off goes where its note on went, even after the host goes away
+        assert!(sent.route(0, on, MidiBackend::Host) == Some(MidiBackend::Host));
+        assert!(sent.route(0, off, MidiBackend::Usb) == Some(MidiBackend::Host));
+        // and is dropped once the note has been released
+        assert!(sent.route(0, off, MidiBackend::Usb).is_none());
+        // other messages go wherever the track goes now
+        let cc = MidiEnv::Cc { cc: 1, value: 2 };
+        assert!(sent.route(0, cc, MidiBackend::Usb) == Some(MidiBackend::Usb));
+
+        // notes sounding where the track no longer goes are handed back to be released
+        sent.route(0, on, MidiBackend::Host);
+        sent.route(1, on, MidiBackend::Host);
+        assert!(sent.moved(0, MidiBackend::Host).is_empty());
+        assert!(sent.moved(0, MidiBackend::Usb) == [(60, MidiBackend::Host)]);
+        assert!(sent.route(0, off, MidiBackend::Usb).is_none());
+        assert!(sent.route(1, off, MidiBackend::Usb) == Some(MidiBackend::Host));
+    }
+
+    #[test]
+    fn bus_subscriptions() {
+        use crate::bus::{Bus, BusAction, BusMsg, Subscription};
+
+        let bus = Bus {
+            messages: alloc::vec!["lights".into(), "scene 2".into()],
+            subscriptions: alloc::vec![
+                Subscription {
+                    message: "drop".into(),
+                    action: BusAction::Transport(TransportCmd::RestartFrom(16)),
+                },
+                Subscription {
+                    message: "slow".into(),
+                    action: BusAction::Tempo(90),
+                },
+                Subscription {
+                    message: "drop".into(),
+                    action: BusAction::Fill(Some(true)),
+                },
+            ],
+        };
+
+        assert_eq!(bus.message(BusMsg(1)), Some("scene 2"));
+        assert_eq!(bus.message(BusMsg(2)), None);
+        assert!(
+            bus.actions("drop").copied().collect::<Vec<_>>()
+                == alloc::vec![
+                    BusAction::Transport(TransportCmd::RestartFrom(16)),
+                    BusAction::Fill(Some(true))
+                ]
+        );
+        assert_eq!(bus.actions("nope").count(), 0);
+        assert!(bus.listen_for() == alloc::vec!["drop", "slow"]);
+        assert!(BusAction::Transport(TransportCmd::SetLoopEnd(N_STEPS - 1)).is_valid());
+        assert!(!BusAction::Transport(TransportCmd::SetLoopStart(N_STEPS)).is_valid());
+        assert!(BusAction::Transport(TransportCmd::Locate(N_STEPS)).is_valid());
+        assert!(BusAction::Tempo(999).is_valid());
+        assert!(!BusAction::Tempo(0).is_valid());
+        assert!(!BusAction::Tempo(1000).is_valid());
+
+        let mut msg = BusMsg::default();
+        msg.adjust(1, 3);
+        assert_eq!(msg.to_string().as_str(), "M013");
+        msg.adjust(-2, 0);
+        assert!(msg == BusMsg(0));
+
+        let step = Step::<MidiCmd> {
+            cmds: (TrackerCmd::Legato, TrackerCmd::Bus { msg: BusMsg(1) }),
+            ..Default::default()
+        };
+        assert!(step.publish() == Some(&BusMsg(1)));
+    }
+
+    #[test]
+    fn remote_commands() {
+        use crate::{
+            clipboard::ClipTrack,
+            remote::{Remote, RemoteCmd, read_steps, write_steps},
+        };
+        use pico_tracker_types::FromHost;
+
+        assert!(
+            Remote::from_host(&FromHost::Locate {
+                id: Some(3),
+                step: 8
+            }) == Some(Remote {
+                id: Some(3),
+                cmd: RemoteCmd::Transport(TransportCmd::Locate(8)),
+            })
+        );
+        assert!(
+            Remote::from_host(&FromHost::Solo {
+                id: None,
+                track: 1,
+                value: None
+            }) == Some(Remote {
+                id: None,
+                cmd: RemoteCmd::Flag {
+                    track: 1,
+                    flag: TrackFlag::Solo,
+                    value: None,
+                },
+            })
+        );
+        assert!(
+            Remote::from_host(&FromHost::MessageBus {
+                message: "drop".into()
+            })
+            .is_none()
+        );
+
+        let mut src = Track::default();
+        if let Track::Midi { steps } = &mut src {
+            steps[2].note = Some(60);
+            steps[3].cmds.0 = TrackerCmd::Legato;
+        }
+
+        // steps read from one track write the same steps to another
+        let steps = read_steps(&src, 2, 2).unwrap();
+        let mut dest = Track::default();
+        write_steps(&mut dest, 10, &steps).unwrap();
+        if let Track::Midi { steps } = &dest {
+            assert_eq!(steps[10].note, Some(60));
+            assert!(steps[11].cmds.0 == TrackerCmd::Legato);
+            assert!(steps[12] == Step::default());
+        }
+
+        // reads stop at the end of the track
+        let tail = read_steps(&src, N_STEPS - 1, 4).unwrap();
+        write_steps(&mut dest, 0, &tail).unwrap();
+        if let Track::Midi { steps } = &dest {
+            assert!(steps[1] == Step::default());
+        }
+
+        // failed writes leave the track as it was
+        let before = dest.clone();
+        assert!(read_steps(&src, N_STEPS, 1).is_err());
+        assert!(write_steps(&mut dest, N_STEPS, &steps).is_err());
+        assert!(write_steps(&mut dest, 0, "Midi([(note: 60)])").is_err());
+        let loud = ClipTrack::Midi(vec![Step {
+            note: Some(200),
+            ..Step::default()
+        }]);
+        let loud = pico_tracker_types::ron::to_string(&loud).unwrap();
+        assert!(write_steps(&mut dest, 0, &loud).is_err());
+        assert!(dest == before);
+    }
+
+    #[test]
+    fn log_entries() {
+        use crate::logging::{LOG_LEN, Log, LogBuffer, LogEntry, Verbosity, module_of};
+        use pico_tracker_types::LogLevel;
+
+        assert_eq!(module_of("src/host.rs"), "host");
+        assert_eq!(
+            module_of("/home/me/pico-tracker/src/midi_plugin/mod.rs"),
+            "midi_plugin"
+        );
+        assert_eq!(module_of("src/helpers/less_then.rs"), "helpers/less_then");
+        assert_eq!(Log::warn("x").module, "lib");
+
+        let verbosity = Verbosity::default();
+        assert!(verbosity.logs(LogLevel::Error));
+        assert!(verbosity.logs(LogLevel::Info));
+        assert!(!verbosity.logs(LogLevel::Debug));
+
+        let entry = |i: usize| LogEntry {
+            level: LogLevel::Info,
+            module: "sync",
+            message: format!("{i}"),
+            millis: 61_500,
+        };
+        assert_eq!(entry(0).to_string().as_str(), "01:01 I sync: 0");
+
+        // only the newest entries are kept
+        let mut buffer = LogBuffer::default();
+        for i in 0..LOG_LEN + 2 {
+            buffer.push(entry(i));
+        }
+        assert_eq!(buffer.entries().len(), LOG_LEN);
+        assert!(buffer.entries()[0] == entry(2));
+        assert!(buffer.entries()[LOG_LEN - 1] == entry(LOG_LEN + 1));
+    }
+
+    #[test]
+    fn midi_monitor() {
+        use crate::base_plugin::MidiEnv;
+        use crate::midi_monitor::{
+            MONITOR_LEN, MidiDirection, MidiKind, MidiLink, MidiMonitor, MidiPort, MonitorEntry,
+            UsbMidiIn,
+        };
+
+        assert!(
+            UsbMidiIn::from_packet(&[0x09, 0x92, 60, 100])
+                == Some(UsbMidiIn {
+                    channel: 2,
+                    msg: MidiEnv::On { note: 60, vel: 100 },
+                })
+        );
+        assert!(
+            UsbMidiIn::from_packet(&[0x0e, 0xe0, 0, 0x40])
+                == Some(UsbMidiIn {
+                    channel: 0,
+                    msg: MidiEnv::PitchBend { value: 8192 },
+                })
+        );
+        // clock and short packets aren't shown
+        assert!(UsbMidiIn::from_packet(&[0x0f, 0xf8, 0, 0]).is_none());
+        assert!(UsbMidiIn::from_packet(&[0x09, 0x90]).is_none());
+
+        let entry = |channel: u8, msg: MidiEnv| MonitorEntry {
+            millis: 61_005,
+            port: MidiPort::UsbOut,
+            channel,
+            msg,
+        };
+        assert_eq!(
+            entry(0, MidiEnv::On { note: 60, vel: 100 })
+                .to_string()
+                .as_str(),
+            "01:01.005 U>  1 on   C-5 100"
+        );
+        assert_eq!(
+            entry(9, MidiEnv::PitchBend { value: 8000 })
+                .to_string()
+                .as_str(),
+            "01:01.005 U> 10 bend -192"
+        );
+
+        let mut monitor = MidiMonitor::default();
+        for note in 0..MONITOR_LEN as u8 + 2 {
+            monitor.push(entry(note % 2, MidiEnv::Off { note }));
+        }
+        monitor.push(entry(1, MidiEnv::Cc { cc: 1, value: 64 }));
+        assert_eq!(monitor.shown().count(), MONITOR_LEN);
+        assert!(monitor.shown().next() == Some(&entry(1, MidiEnv::Off { note: 3 })));
+
+        // filters only hide entries
+        monitor.cycle_channel(2);
+        assert_eq!(monitor.channel, Some(1));
+        monitor.cycle_kind(-3);
+        assert!(monitor.kind == Some(MidiKind::Cc));
+        assert_eq!(monitor.shown().count(), 1);
+        monitor.cycle_channel(-2);
+        monitor.cycle_kind(3);
+        assert!((monitor.channel, monitor.kind) == (None, None));
+        assert_eq!(monitor.shown().count(), MONITOR_LEN);
+
+        // nothing's kept while paused
+        monitor.paused = true;
+        monitor.push(entry(0, MidiEnv::Program { program: 4 }));
+        assert!(monitor.shown().last() == Some(&entry(1, MidiEnv::Cc { cc: 1, value: 64 })));
+
+        // so do the port filters, and toggling one again shows everything
+        monitor.paused = false;
+        monitor.push(MonitorEntry {
+            port: MidiPort::HostIn,
+            ..entry(0, MidiEnv::Program { program: 4 })
+        });
+        monitor.toggle_link(MidiLink::Host);
+        assert_eq!(monitor.shown().count(), 1);
+        assert_eq!(monitor.port_filter().as_str(), "H");
+        monitor.toggle_link(MidiLink::Host);
+        monitor.toggle_direction(MidiDirection::Sent);
+        assert_eq!(monitor.shown().count(), MONITOR_LEN - 1);
+        monitor.toggle_direction(MidiDirection::Received);
+        assert_eq!(monitor.shown().count(), 1);
+        assert_eq!(monitor.port_filter().as_str(), "<");
+        monitor.toggle_direction(MidiDirection::Received);
+        assert_eq!(monitor.shown().count(), MONITOR_LEN);
+    }
+
+    #[test]
+    fn track_sanitising() {
+        let mut track = Track::Midi {
+            steps: alloc::vec![Step {
+                note: Some(200),
+                vel: Some(128),
+                ..Default::default()
+            }],
+        };
+        assert!(!track_steps(&track)[0].is_valid());
+
+        // a short track from a file is padded, and what midi can't send is clamped
+        track.sanitise();
+        let steps = track_steps(&track);
+        assert_eq!(steps.len(), N_STEPS);
+        assert_eq!(
+            (steps[0].note, steps[0].vel),
+            (Some(MIDI_MAX), Some(MIDI_MAX))
+        );
+        assert!(steps.iter().all(Step::is_valid));
+
+        fn track_steps(track: &Track) -> &[Step<MidiCmd>] {
+            match track {
+                Track::Midi { steps } => steps,
+                Track::SF2 { .. } => &[],
+            }
+        }
+    }
 }
@@ -0,0 +1,202 @@
+//! log messages with a level and the module that logged them, sent to the host and kept for the
+//! log screen.
+use crate::host::reply_to_host;
+use alloc::collections::VecDeque;
+use bevy::prelude::*;
+use core::{fmt, panic::Location};
+use pico_tracker_types::{FromHost, FromTracker, LogLevel};
+use picocalc_bevy::{LoggingEnv, PicoTimer};
+
+/// how many log entries the log screen keeps.
+pub const LOG_LEN: usize = 64;
+
+pub struct LoggingPlugin;
+
+impl Plugin for LoggingPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<Uptime>()
+            .init_resource::<LogBuffer>()
+            .init_resource::<Verbosity>()
+            .add_event::<Log>()
+            .add_event::<LoggingEnv>()
+            .add_systems(PreUpdate, tick_uptime)
+            .add_systems(Update, (picocalc_logs, log_level_messages))
+            .add_systems(PostUpdate, collect_logs);
+    }
+}
+
+/// a message to log. the constructors fill in the module they're called from.
+#[derive(Clone, Debug, PartialEq, Eq, Event)]
+pub struct Log {
+    pub level: LogLevel,
+    pub module: &'static str,
+    pub message: String,
+}
+
+impl Log {
+    #[track_caller]
+    pub fn new(level: LogLevel, message: impl Into<String>) -> Self {
+        Self {
+            level,
+            module: module_of(Location::caller().file()),
+            message: message.into(),
+        }
+    }
+
+    #[track_caller]
+    pub fn error(message: impl Into<String>) -> Self {
+        Self::new(LogLevel::Error, message)
+    }
+
+    #[track_caller]
+    pub fn warn(message: impl Into<String>) -> Self {
+        Self::new(LogLevel::Warn, message)
+    }
+
+    #[track_caller]
+    pub fn info(message: impl Into<String>) -> Self {
+        Self::new(LogLevel::Info, message)
+    }
+
+    #[track_caller]
+    pub fn debug(message: impl Into<String>) -> Self {
+        Self::new(LogLevel::Debug, message)
+    }
+
+    #[track_caller]
+    pub fn trace(message: impl Into<String>) -> Self {
+        Self::new(LogLevel::Trace, message)
+    }
+}
+
+/// the module a source file holds, e.g. `midi_plugin` for `.../src/midi_plugin/mod.rs`.
+pub fn module_of(file: &'static str) -> &'static str {
+    let file = file.rsplit_once("src/").map_or(file, |(_, file)| file);
+    let file = file.strip_suffix(".rs").unwrap_or(file);
+
+    file.strip_suffix("/mod").unwrap_or(file)
+}
+
+/// a logged message, with when it was logged.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LogEntry {
+    pub level: LogLevel,
+    pub module: &'static str,
+    pub message: String,
+    /// milliseconds since the tracker started.
+    pub millis: u64,
+}
+
+impl LogEntry {
+    pub fn to_host(&self) -> FromTracker {
+        FromTracker::Log {
+            message: self.message.clone(),
+            level: self.level,
+            millis: self.millis,
+            module: self.module.into(),
+        }
+    }
+}
+
+/// `mm:ss L module: message`, as shown on the log screen.
+impl fmt::Display for LogEntry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let secs = self.millis / 1000;
+
+        write!(
+            f,
+            "{:02}:{:02} {} {}: {}",
+            (secs / 60) % 100,
+            secs % 60,
+            self.level.code(),
+            self.module,
+            self.message
+        )
+    }
+}
+
+/// milliseconds since the tracker started.
+#[derive(Resource, Clone, Copy, Debug, Default, PartialEq, Eq, Deref)]
+pub struct Uptime(pub u64);
+
+/// the last `LOG_LEN` log entries, oldest first.
+#[derive(Resource, Clone, Debug, Default, PartialEq, Eq)]
+pub struct LogBuffer(VecDeque<LogEntry>);
+
+impl LogBuffer {
+    /// adds an entry, forgetting the oldest one if the buffer is full.
+    pub fn push(&mut self, entry: LogEntry) {
+        if self.0.len() == LOG_LEN {
+            self.0.pop_front();
+        }
+
+        self.0.push_back(entry);
+    }
+
+    pub fn entries(&self) -> &VecDeque<LogEntry> {
+        &self.0
+    }
+}
+
+/// the least important level that's still logged.
+#[derive(Resource, Clone, Copy, Debug, Default, PartialEq, Eq, Deref, DerefMut)]
+pub struct Verbosity(pub LogLevel);
+
+impl Verbosity {
+    pub fn logs(&self, level: LogLevel) -> bool {
+        level <= self.0
+    }
+}
+
+fn tick_uptime(mut uptime: ResMut<Uptime>, time: NonSend<PicoTimer>) {
+    uptime.0 += time.delta_millis();
+}
+
+/// picocalc_bevy's own log messages. `LoggingEnv` only gives us the message, not the level it was
+/// logged at, so they're all info.
+fn picocalc_logs(mut events: EventReader<LoggingEnv>, mut log: EventWriter<Log>) {
+    for event in events.read() {
+        log.write(Log {
+            level: LogLevel::Info,
+            module: "picocalc_bevy",
+            message: event.msg.clone(),
+        });
+    }
+}
+
+fn log_level_messages(
+    mut from_host: EventReader<FromHost>,
+    mut verbosity: ResMut<Verbosity>,
+    mut to_host: EventWriter<FromTracker>,
+) {
+    for message in from_host.read() {
+        if let FromHost::SetLogLevel { id, level } = message {
+            verbosity.0 = *level;
+            reply_to_host(*id, Ok(None), &mut to_host);
+        }
+    }
+}
+
+/// sends the logs verbose enough to the host, and keeps them for the log screen.
+fn collect_logs(
+    mut logs: EventReader<Log>,
+    verbosity: Res<Verbosity>,
+    uptime: Res<Uptime>,
+    mut buffer: ResMut<LogBuffer>,
+    mut to_host: EventWriter<FromTracker>,
+) {
+    for log in logs.read() {
+        if !verbosity.logs(log.level) {
+            continue;
+        }
+
+        let entry = LogEntry {
+            level: log.level,
+            module: log.module,
+            message: log.message.clone(),
+            millis: uptime.0,
+        };
+        to_host.write(entry.to_host());
+        buffer.push(entry);
+    }
+}
@@ -0,0 +1,357 @@
+//! the midi the tracker sends and receives, over usb and through the host, kept for the midi
+//! monitor screen.
+use crate::{
+    base_plugin::{MidiEnv, UsbMidiOut},
+    display_midi_note,
+    logging::Uptime,
+};
+use alloc::collections::VecDeque;
+use bevy::prelude::*;
+use core::fmt;
+use pico_tracker_types::{FromHost, FromTracker};
+
+/// how many midi messages the monitor keeps.
+pub const MONITOR_LEN: usize = 64;
+
+pub struct MidiMonitorPlugin;
+
+impl Plugin for MidiMonitorPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<MidiMonitor>()
+            .add_event::<UsbMidiIn>()
+            .add_systems(PostUpdate, monitor_midi);
+    }
+}
+
+/// a midi message read from usb.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Event)]
+pub struct UsbMidiIn {
+    pub channel: u8,
+    pub msg: MidiEnv,
+}
+
+impl UsbMidiIn {
+    /// the message in a 4 byte usb midi event packet, `None` if it isn't one the tracker sends
+    /// too.
+    pub fn from_packet(packet: &[u8]) -> Option<Self> {
+        let [_, status, data_1, data_2] = *packet else {
+            return None;
+        };
+        let (data_1, data_2) = (data_1 & 0x7f, data_2 & 0x7f);
+
+        let msg = match status & 0xf0 {
+            0x80 => MidiEnv::Off { note: data_1 },
+            0x90 => MidiEnv::On {
+                note: data_1,
+                vel: data_2,
+            },
+            0xb0 => MidiEnv::Cc {
+                cc: data_1,
+                value: data_2,
+            },
+            0xc0 => MidiEnv::Program { program: data_1 },
+            0xe0 => MidiEnv::PitchBend {
+                value: data_1 as u16 | (data_2 as u16) << 7,
+            },
+            _ => return None,
+        };
+
+        Some(Self {
+            channel: status & 0x0f,
+            msg,
+        })
+    }
+}
+
+/// where a midi message went, or came from.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum MidiPort {
+    UsbOut,
+    HostOut,
+    UsbIn,
+    HostIn,
+}
+
+impl MidiPort {
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::UsbOut => "U>",
+            Self::HostOut => "H>",
+            Self::UsbIn => "U<",
+            Self::HostIn => "H<",
+        }
+    }
+
+    pub fn link(&self) -> MidiLink {
+        match self {
+            Self::UsbOut | Self::UsbIn => MidiLink::Usb,
+            Self::HostOut | Self::HostIn => MidiLink::Host,
+        }
+    }
+
+    pub fn direction(&self) -> MidiDirection {
+        match self {
+            Self::UsbOut | Self::HostOut => MidiDirection::Sent,
+            Self::UsbIn | Self::HostIn => MidiDirection::Received,
+        }
+    }
+}
+
+/// which way a message went over usb or through the host, for the monitor's filters.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum MidiLink {
+    Usb,
+    Host,
+}
+
+impl MidiLink {
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::Usb => "U",
+            Self::Host => "H",
+        }
+    }
+}
+
+/// whether a message was sent by the tracker or received by it, for the monitor's filters.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum MidiDirection {
+    Sent,
+    Received,
+}
+
+impl MidiDirection {
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::Sent => ">",
+            Self::Received => "<",
+        }
+    }
+}
+
+/// the kinds of message the monitor can be filtered to. note ons and offs are both notes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum MidiKind {
+    Note,
+    Cc,
+    PitchBend,
+    Program,
+}
+
+impl MidiKind {
+    pub const ALL: [Self; 4] = [Self::Note, Self::Cc, Self::PitchBend, Self::Program];
+
+    pub fn of(msg: &MidiEnv) -> Self {
+        match msg {
+            MidiEnv::On { .. } | MidiEnv::Off { .. } => Self::Note,
+            MidiEnv::Cc { .. } => Self::Cc,
+            MidiEnv::PitchBend { .. } => Self::PitchBend,
+            MidiEnv::Program { .. } => Self::Program,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Note => "notes",
+            Self::Cc => "cc",
+            Self::PitchBend => "bend",
+            Self::Program => "program",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MonitorEntry {
+    /// milliseconds since the tracker started.
+    pub millis: u64,
+    pub port: MidiPort,
+    /// 0 based.
+    pub channel: u8,
+    pub msg: MidiEnv,
+}
+
+/// `mm:ss.mmm port channel type values`, as shown on the midi monitor screen.
+impl fmt::Display for MonitorEntry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let secs = self.millis / 1000;
+
+        write!(
+            f,
+            "{:02}:{:02}.{:03} {} {:>2} ",
+            (secs / 60) % 100,
+            secs % 60,
+            self.millis % 1000,
+            self.port.code(),
+            self.channel + 1,
+        )?;
+
+        match self.msg {
+            MidiEnv::On { note, vel } => write!(f, "on   {} {vel:>3}", display_midi_note(note)),
+            MidiEnv::Off { note } => write!(f, "off  {}", display_midi_note(note)),
+            MidiEnv::Cc { cc, value } => write!(f, "cc   {cc:>3} {value:>3}"),
+            MidiEnv::PitchBend { value } => write!(f, "bend {:+}", value as i32 - 8192),
+            MidiEnv::Program { program } => write!(f, "prog {program:>3}"),
+        }
+    }
+}
+
+/// the last `MONITOR_LEN` midi messages, oldest first, and which of them are shown.
+#[derive(Resource, Clone, Debug, Default, PartialEq, Eq)]
+pub struct MidiMonitor {
+    entries: VecDeque<MonitorEntry>,
+    /// no new messages are kept while paused.
+    pub paused: bool,
+    /// only show this channel, if set.
+    pub channel: Option<u8>,
+    /// only show this kind of message, if set.
+    pub kind: Option<MidiKind>,
+    /// only show messages that went over usb, or through the host, if set.
+    pub link: Option<MidiLink>,
+    /// only show sent, or received, messages if set.
+    pub direction: Option<MidiDirection>,
+}
+
+impl MidiMonitor {
+    /// keeps an entry, forgetting the oldest one if the monitor is full. does nothing while
+    /// paused.
+    pub fn push(&mut self, entry: MonitorEntry) {
+        if self.paused {
+            return;
+        }
+
+        if self.entries.len() == MONITOR_LEN {
+            self.entries.pop_front();
+        }
+
+        self.entries.push_back(entry);
+    }
+
+    pub fn shows(&self, entry: &MonitorEntry) -> bool {
+        self.channel.is_none_or(|channel| channel == entry.channel)
+            && self
+                .kind
+                .is_none_or(|kind| kind == MidiKind::of(&entry.msg))
+            && self.link.is_none_or(|link| link == entry.port.link())
+            && self
+                .direction
+                .is_none_or(|direction| direction == entry.port.direction())
+    }
+
+    /// the entries that pass the filters, oldest first.
+    pub fn shown(&self) -> impl Iterator<Item = &MonitorEntry> {
+        self.entries.iter().filter(|entry| self.shows(entry))
+    }
+
+    /// steps the channel filter through all channels, then each channel in turn.
+    pub fn cycle_channel(&mut self, by: i8) {
+        // 0 is all channels.
+        let i = self.channel.map_or(0, |channel| channel as i8 + 1);
+        let i = (i + by).rem_euclid(17);
+
+        self.channel = (i > 0).then(|| i as u8 - 1);
+    }
+
+    /// steps the kind filter through all kinds, then each kind in turn.
+    pub fn cycle_kind(&mut self, by: i8) {
+        let n = MidiKind::ALL.len() as i8 + 1;
+        let i = self.kind.map_or(0, |kind| {
+            MidiKind::ALL.iter().position(|k| *k == kind).unwrap_or(0) as i8 + 1
+        });
+        let i = (i + by).rem_euclid(n);
+
+        self.kind = (i > 0).then(|| MidiKind::ALL[i as usize - 1]);
+    }
+
+    /// only shows messages over `link`, or shows every link again if it already was.
+    pub fn toggle_link(&mut self, link: MidiLink) {
+        self.link = (self.link != Some(link)).then_some(link);
+    }
+
+    /// only shows messages going `direction`, or shows both ways again if it already was.
+    pub fn toggle_direction(&mut self, direction: MidiDirection) {
+        self.direction = (self.direction != Some(direction)).then_some(direction);
+    }
+
+    /// the port filters as in a port's code, e.g. `H<`, or `U` for everything over usb. empty
+    /// without them.
+    pub fn port_filter(&self) -> String {
+        let link = self.link.map_or("", |link| link.code());
+        let direction = self.direction.map_or("", |direction| direction.code());
+
+        format!("{link}{direction}")
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+}
+
+/// the midi in a message to the host, and the track's channel it's on.
+fn to_host_midi(message: &FromTracker) -> Option<(u8, MidiEnv)> {
+    Some(match *message {
+        FromTracker::NoteOn { channel, note, vel } => (channel, MidiEnv::On { note, vel }),
+        FromTracker::NoteOff { channel, note } => (channel, MidiEnv::Off { note }),
+        FromTracker::Cc {
+            channel,
+            control,
+            value,
+        } => (channel, MidiEnv::Cc { cc: control, value }),
+        FromTracker::PitchBend { channel, value } => (channel, MidiEnv::PitchBend { value }),
+        FromTracker::ProgramChange { channel, program } => (channel, MidiEnv::Program { program }),
+        _ => return None,
+    })
+}
+
+/// the midi in a message from the host's controllers.
+fn from_host_midi(message: &FromHost) -> Option<(u8, MidiEnv)> {
+    Some(match *message {
+        FromHost::MidiNoteOn { note, vel, channel } => (channel, MidiEnv::On { note, vel }),
+        FromHost::MidiNoteOff { note, channel } => (channel, MidiEnv::Off { note }),
+        FromHost::MidiCC {
+            control,
+            param,
+            channel,
+        } => (
+            channel,
+            MidiEnv::Cc {
+                cc: control,
+                value: param,
+            },
+        ),
+        _ => return None,
+    })
+}
+
+/// keeps the midi sent and received this frame.
+fn monitor_midi(
+    mut usb_out: EventReader<UsbMidiOut>,
+    mut to_host: EventReader<FromTracker>,
+    mut usb_in: EventReader<UsbMidiIn>,
+    mut from_host: EventReader<FromHost>,
+    uptime: Res<Uptime>,
+    mut monitor: ResMut<MidiMonitor>,
+) {
+    let usb_out = usb_out
+        .read()
+        .map(|midi| (MidiPort::UsbOut, midi.channel, midi.msg));
+    let host_out = to_host
+        .read()
+        .filter_map(to_host_midi)
+        .map(|(channel, msg)| (MidiPort::HostOut, channel, msg));
+    let usb_in = usb_in
+        .read()
+        .map(|midi| (MidiPort::UsbIn, midi.channel, midi.msg));
+    let host_in = from_host
+        .read()
+        .filter_map(from_host_midi)
+        .map(|(channel, msg)| (MidiPort::HostIn, channel, msg));
+
+    for (port, channel, msg) in usb_out.chain(host_out).chain(usb_in).chain(host_in) {
+        monitor.push(MonitorEntry {
+            millis: uptime.0,
+            port,
+            channel,
+            msg,
+        });
+    }
+}
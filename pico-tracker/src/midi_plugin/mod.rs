@@ -1,10 +1,24 @@
 use crate::{
-    N_STEPS, Playing, Tempo, Track, TrackID, base_plugin::MidiEnv, hal::timer::Instant, playing,
+    ClockSource, MidiNote, N_STEPS, PreviewStep, SetTrackFlag, Step, Tempo, Track, TrackFlag,
+    TrackID, TrackOutput, TrackProgram, TrackerCmd, Transport, TransportCmd, TransportState,
+    arp::Arp,
+    base_plugin::{MidiEnv, UsbMidiOut},
+    bend::{BEND_CENTER, Bend, BendRamp},
+    bus::PublishBus,
+    cond::{CondState, Fill},
+    hal::timer::Instant,
+    host::HostLink,
+    logging::Log,
+    playing,
+    program::Program,
+    roll::Hit,
 };
 use bevy::prelude::*;
 use core::time::Duration;
 use defmt::*;
-use picocalc_bevy::{LoggingEnv as Log, PicoTimer};
+use pico_tracker_types::{FromHost, FromTracker};
+use picocalc_bevy::PicoTimer;
+use rand::{SeedableRng, rngs::SmallRng};
 
 #[derive(Resource, Clone, Debug, Copy, Eq, PartialEq)]
 pub struct SyncPulse {
@@ -18,6 +32,112 @@ pub struct SyncTimer(Timer);
 #[derive(Component, Clone, Debug, Copy, Eq, Hash, PartialEq)]
 pub struct PlayingTrack(pub usize, pub usize, pub Option<usize>); // track index, step index,
 
+/// midi from one of the tracks, sent on to the track's output by `send_track_midi`.
+#[derive(Event, Clone, Copy, PartialEq, Eq)]
+pub struct TrackMidi {
+    pub track: usize,
+    pub msg: MidiEnv,
+}
+
+/// writes the midi of one track.
+pub struct TrackOut<'a, 'w> {
+    pub track: usize,
+    events: &'a mut EventWriter<'w, TrackMidi>,
+}
+
+impl<'a, 'w> TrackOut<'a, 'w> {
+    pub fn new(track: usize, events: &'a mut EventWriter<'w, TrackMidi>) -> Self {
+        Self { track, events }
+    }
+
+    pub fn write(&mut self, msg: MidiEnv) {
+        self.events.write(TrackMidi {
+            track: self.track,
+            msg,
+        });
+    }
+}
+
+/// the notes a track has turned on and not yet released.
+#[derive(Component, Clone, Debug, Default, Eq, Hash, PartialEq, Deref, DerefMut)]
+pub struct SoundingNotes(pub Vec<MidiNote>);
+
+/// the arp a track is playing, if any.
+#[derive(Component, Clone, Debug, Default, PartialEq, Deref, DerefMut)]
+pub struct ArpState(pub Option<PlayingArp>);
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct PlayingArp {
+    pub arp: Arp,
+    /// the sync pulse the arp started on.
+    pub start_pulse: usize,
+    /// the notes of the step that started the arp.
+    pub notes: Vec<MidiNote>,
+    /// arpeggiate the notes held on the midi controller instead of `notes`, while there are any.
+    pub use_held: bool,
+    pub vel: u8,
+    /// the note the arp is currently playing.
+    pub note: Option<MidiNote>,
+}
+
+/// the result of the last condition on a track, for `Pre` conditions.
+#[derive(Component, Clone, Copy, Debug, Default, Eq, Hash, PartialEq, Deref, DerefMut)]
+pub struct LastCondition(pub bool);
+
+/// the hits of the step a track triggered that are still to be played.
+#[derive(Component, Clone, Debug, Default, PartialEq)]
+pub struct PendingHits {
+    /// the step the hits are from, `None` before the track plays one.
+    pub step: Option<usize>,
+    /// the sync pulse the step started on.
+    pub step_start: usize,
+    pub hits: Vec<Hit>,
+    /// the first hit starts before the notes held over from a legato step are released. notes
+    /// in both are held rather than played again.
+    pub legato: bool,
+}
+
+/// the pitch bend a track last sent, and the ramp it's gliding along.
+#[derive(Component, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct BendState {
+    pub value: u16,
+    pub ramp: Option<BendRamp>,
+}
+
+impl Default for BendState {
+    fn default() -> Self {
+        Self {
+            value: BEND_CENTER,
+            ramp: None,
+        }
+    }
+}
+
+/// the step being auditioned while the transport is stopped.
+#[derive(Resource, Clone, Debug, Default)]
+pub struct Preview {
+    /// the id of the track the step is on, `None` when nothing is being previewed.
+    pub track: Option<usize>,
+    /// sync pulses since the preview started.
+    pub pulse: usize,
+    pub hits: PendingHits,
+    timer: Timer,
+}
+
+/// the notes held down on the midi controller, in the order they were played.
+#[derive(Resource, Clone, Debug, Default, Eq, Hash, PartialEq, Deref, DerefMut)]
+pub struct HeldNotes(pub Vec<MidiNote>);
+
+/// random numbers for the random arp mode.
+#[derive(Resource, Clone, Debug)]
+pub struct ArpRng(pub SmallRng);
+
+impl Default for ArpRng {
+    fn default() -> Self {
+        Self(SmallRng::seed_from_u64(0x6172_7000))
+    }
+}
+
 #[derive(Resource, Clone, Debug, Eq, PartialEq)]
 pub struct ControllerName(String);
 
@@ -27,6 +147,9 @@ pub struct LastPlayedPulse(Option<usize>);
 #[derive(Resource, Clone, Debug, Copy, Eq, Hash, PartialEq)]
 pub struct BPQ(pub usize);
 
+/// velocity of steps that don't set their own.
+pub const DEFAULT_VEL: u8 = 111;
+
 #[derive(Resource, Clone, Debug, Copy, Eq, Hash, PartialEq, Deref, DerefMut)]
 pub struct PlayingSyncPulse(pub bool);
 
@@ -53,21 +176,34 @@ impl Plugin for MidiOutPlugin {
             Duration::from_secs_f64(60.0 / 120.0 / 48.0),
             TimerMode::Repeating,
         )))
-        .insert_resource(Playing(true))
+        .init_resource::<Transport>()
+        .init_resource::<ClockSource>()
+        .init_resource::<HeldNotes>()
+        .init_resource::<ArpRng>()
+        .init_resource::<CondState>()
+        .init_resource::<Fill>()
+        .init_resource::<Preview>()
         .insert_resource(BPQ(48))
         // .insert_resource(LastPlayedPulse(None))
         .insert_resource(PlayingSyncPulse(true))
+        .add_event::<SetTrackFlag>()
+        .add_event::<TransportCmd>()
+        .add_event::<PreviewStep>()
+        .add_event::<TrackMidi>()
+        .add_event::<PublishBus>()
         .add_systems(Startup, setup)
-        .add_systems(Update, sync.run_if(sync_pulsing))
+        // after everything that plays notes has run.
+        .add_systems(PostUpdate, send_track_midi)
+        .add_systems(Update, sync.run_if(sync_pulsing).run_if(playing))
+        .add_systems(Update, (set_track_flags, transport_cmds, track_held_notes))
         .add_systems(
             Update,
             (
-                send_notes.run_if(playing),
+                send_notes.run_if(playing).run_if(not_played_yet),
+                preview_steps.run_if(not(playing)),
                 // note_notif.run_if(playing),
                 // update_front_end.run_if(sync_pulsing)
-            )
-                // .chain()
-                .run_if(on_thirtysecond_note),
+            ),
         );
     }
 }
@@ -119,6 +255,7 @@ fn sync(
     // mut state_updated: EventWriter<StateUpdated>,
     // output: Res<MidiOutput>,
     bpq: Res<BPQ>,
+    transport: Res<Transport>,
     mut log: EventWriter<Log>,
 ) {
     // time.tick();
@@ -152,6 +289,7 @@ fn sync(
 
         pulse.n_pulses += 1;
         pulse.n_pulses %= usize::MAX;
+        pulse.n_pulses = loop_pulse(pulse.n_pulses, pulses_per_step(&bpq), transport.loop_range);
         // log.write(Log::info("pulse"));
 
         // set last sync pulse time
@@ -192,53 +330,804 @@ fn note_notif(mut log: EventWriter<Log>) {
     log.write(Log::error("note"));
 }
 
+#[allow(clippy::too_many_arguments)]
 fn send_notes(
     // output: Res<MidiOutput>,
     // mut playing: Query<&mut PlayingTrack, Without<PlayingQueued>>,
     // phrases: Res<AllPhrases>,
-    tracks: Query<(&Track, &TrackID)>,
+    mut tracks: Query<(
+        &Track,
+        &TrackID,
+        &mut SoundingNotes,
+        &mut ArpState,
+        &mut LastCondition,
+        &mut PendingHits,
+        &mut BendState,
+    )>,
     // mut state_updated: EventWriter<StateUpdated>,
-    // mut last_played: ResMut<LastPlayedPulse>,
+    mut last_played: ResMut<LastPlayedPulse>,
     pulse: Res<SyncPulse>,
     bpq: Res<BPQ>,
-    mut midi_out: EventWriter<MidiEnv>,
+    held: Res<HeldNotes>,
+    mut rng: ResMut<ArpRng>,
+    mut cond_state: ResMut<CondState>,
+    fill: Res<Fill>,
+    mut midi_out: EventWriter<TrackMidi>,
+    mut published: EventWriter<PublishBus>,
     mut log: EventWriter<Log>,
 ) {
     // let step_i = pulse.n_pulses % (bpq.0 / 8);
     // let step_i = (pulse.n_pulses / (bpq.0 / 8)) % N_STEPS;
     let step_i = get_step_num(&pulse, &bpq);
-    let last_step_i = if step_i > 0 { step_i - 1 } else { N_STEPS - 1 };
-
-    for (ref track, id) in tracks.iter() {
-        if id.playing {
-            match track {
-                Track::Midi { steps } => {
-                    // log.write(Log::error(format!(
-                    //     "step: {step_i}, last step: {last_step_i}"
-                    // )));
-
-                    if let Some(Some(note)) = steps.get(last_step_i).map(|step| step.note) {
-                        midi_out.write(MidiEnv::Off { note });
-                        // log.write(Log::error(format!("stopping: {note}")));
+    let pps = pulses_per_step(&bpq);
+    let pulse_in_step = pulse.n_pulses % pps;
+    let any_solo = tracks.iter().any(|(_, id, ..)| id.solo);
+    let prev_step = cond_state.last_step;
+
+    if pulse_in_step == 0 {
+        cond_state.next_step(step_i);
+    }
+
+    for (ref track, id, mut sounding, mut arp, mut last_cond, mut pending, mut bend) in
+        tracks.iter_mut()
+    {
+        let mut out = TrackOut::new(id.id, &mut midi_out);
+
+        // notes last until the next step, or until the next step's notes start after a legato
+        // step. the arp's note lasts for its own gate, which can run on past the step.
+        if pulse_in_step == 0 && !held_over(track, &pending, prev_step, step_i, pulse.n_pulses, pps)
+        {
+            release_step_notes(&mut sounding, &arp, &mut out);
+        }
+
+        if !id.audible(any_solo) {
+            if arp.is_some() {
+                release_arp_note(&mut arp, &mut sounding, &mut out);
+                arp.0 = None;
+            }
+
+            pending.hits.clear();
+            bend.ramp = None;
+
+            continue;
+        }
+
+        match track {
+            Track::Midi { steps } => {
+                // log.write(Log::error(format!("step: {step_i}")));
+
+                if let Some(step) = steps.get(step_i)
+                    && (step.delay as usize).min(pps - 1) == pulse_in_step
+                    && cond_state.check(step.cond(), id.id, step_i, fill.0, &mut last_cond)
+                {
+                    if let Some(step_bend) = step.bend() {
+                        start_bend(
+                            &mut bend,
+                            step_bend,
+                            pulse.n_pulses,
+                            pps - pulse_in_step,
+                            &mut out,
+                        );
+                    }
+
+                    for (cc, value) in step.porta().map(|porta| porta.ccs()).unwrap_or_default() {
+                        out.write(MidiEnv::Cc { cc, value });
+                    }
+
+                    if let Some(program) = step.program() {
+                        send_program(program, &mut out);
+                    }
+
+                    if let Some(msg) = step.publish() {
+                        published.write(PublishBus(*msg));
                     }
 
-                    if let Some(Some(note)) = steps.get(step_i).map(|step| step.note) {
-                        midi_out.write(MidiEnv::On { note, vel: 111 });
-                        // log.write(Log::error(format!("playing: {note}")));
+                    if let Some(step_arp) = step.arp() {
+                        // the arp plays the step's notes instead of them all sounding at once.
+                        release_arp_note(&mut arp, &mut sounding, &mut out);
+                        arp.0 = Some(PlayingArp {
+                            arp: *step_arp,
+                            start_pulse: pulse.n_pulses,
+                            notes: step.notes(),
+                            use_held: step.chord().is_none(),
+                            vel: step.vel.unwrap_or(DEFAULT_VEL),
+                            note: None,
+                        });
+                    } else {
+                        // a chord command plays the whole chord built on the note, a roll
+                        // retriggers it over the rest of the step.
+                        *pending = PendingHits {
+                            step: Some(step_i),
+                            step_start: pulse.n_pulses - pulse_in_step,
+                            hits: step.hits(pps, DEFAULT_VEL),
+                            legato: !sounding.is_empty(),
+                        };
                     }
                 }
-                Track::SF2 { steps: _ } => {
-                    // defmt::todo!("write SF2");
+            }
+            Track::SF2 { steps: _ } => {
+                // defmt::todo!("write SF2");
+            }
+        }
+
+        play_hits(&mut pending, &mut sounding, pulse.n_pulses, &mut out);
+        play_bend(&mut bend, pulse.n_pulses, &mut out);
+
+        if arp.is_some() {
+            play_arp(
+                &mut arp,
+                &mut sounding,
+                pulse.n_pulses,
+                pps,
+                &held,
+                &mut rng.0,
+                &mut out,
+            );
+        }
+    }
+
+    _ = last_played.0.insert(pulse.n_pulses);
+}
+
+/// plays the hits that are due on `n_pulses`. each hit releases the notes of the one before it.
+fn play_hits(
+    pending: &mut PendingHits,
+    sounding: &mut SoundingNotes,
+    n_pulses: usize,
+    midi_out: &mut TrackOut<'_, '_>,
+) {
+    // playback jumped back to before the step started.
+    if n_pulses < pending.step_start {
+        pending.hits.clear();
+    }
+
+    let t = n_pulses.saturating_sub(pending.step_start);
+
+    while let Some(hit) = pending.hits.first()
+        && hit.pulse <= t
+    {
+        let hit = pending.hits.remove(0);
+
+        let legato = core::mem::take(&mut pending.legato);
+
+        // hits that were skipped over aren't played late.
+        if hit.pulse < t {
+            continue;
+        }
+
+        if legato {
+            let held = core::mem::take(&mut sounding.0);
+
+            for note in hit.notes.iter().copied() {
+                if !held.contains(&note) {
+                    midi_out.write(MidiEnv::On { note, vel: hit.vel });
                 }
+
+                sounding.push(note);
+            }
+
+            for note in held.into_iter().filter(|note| !hit.notes.contains(note)) {
+                midi_out.write(MidiEnv::Off { note });
+            }
+        } else {
+            release_notes(sounding, midi_out);
+
+            for note in hit.notes {
+                midi_out.write(MidiEnv::On { note, vel: hit.vel });
+                sounding.push(note);
+            }
+        }
+    }
+}
+
+/// whether the notes of the step before `step` are held in to it, which they are when that step
+/// played, it's legato and `step` has a note to move on to. `pending` is from the last step the
+/// track played, so a muted step or one whose condition failed doesn't hold anything.
+pub fn held_over(
+    track: &Track,
+    pending: &PendingHits,
+    prev_step: Option<usize>,
+    step: usize,
+    n_pulses: usize,
+    pps: usize,
+) -> bool {
+    let Some(prev_step) = prev_step else {
+        return false;
+    };
+
+    if pending.step != Some(prev_step) || pending.step_start + pps != n_pulses {
+        return false;
+    }
+
+    match track {
+        Track::Midi { steps } => legato_into(steps, prev_step, step),
+        Track::SF2 { steps } => legato_into(steps, prev_step, step),
+    }
+}
+
+fn legato_into<Cmd>(steps: &[Step<Cmd>], prev_step: usize, step: usize) -> bool
+where
+    Cmd:
+        Clone + Default + PartialEq + PartialOrd + core::fmt::Display + ToString + core::fmt::Debug,
+{
+    steps.get(prev_step).is_some_and(|prev| prev.legato())
+        && steps.get(step).is_some_and(|step| step.note.is_some())
+}
+
+/// sends a bend straight away, or starts ramping to it over `pulses`.
+fn start_bend(
+    state: &mut BendState,
+    bend: &Bend,
+    n_pulses: usize,
+    pulses: usize,
+    midi_out: &mut TrackOut<'_, '_>,
+) {
+    if bend.ramp {
+        state.ramp = Some(BendRamp {
+            from: state.value,
+            to: bend.value(),
+            start_pulse: n_pulses,
+            pulses,
+        });
+    } else {
+        state.ramp = None;
+        state.value = bend.value();
+        midi_out.write(MidiEnv::PitchBend { value: state.value });
+    }
+}
+
+/// moves a track's bend along its ramp, sending the new value when it changes.
+fn play_bend(state: &mut BendState, n_pulses: usize, midi_out: &mut TrackOut<'_, '_>) {
+    let Some(ramp) = state.ramp else {
+        return;
+    };
+
+    // playback jumped back to before the ramp started.
+    if n_pulses < ramp.start_pulse {
+        state.ramp = None;
+        return;
+    }
+
+    let value = ramp.value_at(n_pulses);
+
+    if value != state.value {
+        state.value = value;
+        midi_out.write(MidiEnv::PitchBend { value });
+    }
+
+    if ramp.is_done(n_pulses) {
+        state.ramp = None;
+    }
+}
+
+/// plays the step asked for by a `PreviewStep` over the length of a step, at the current tempo.
+fn preview_steps(
+    mut events: EventReader<PreviewStep>,
+    mut preview: ResMut<Preview>,
+    time: NonSend<PicoTimer>,
+    tempo: Res<Tempo>,
+    bpq: Res<BPQ>,
+    mut tracks: Query<(&Track, &TrackID, &mut SoundingNotes)>,
+    mut midi_out: EventWriter<TrackMidi>,
+) {
+    let pps = pulses_per_step(&bpq);
+    let started = !events.is_empty();
+
+    for event in events.read() {
+        for (_, id, mut sounding) in tracks.iter_mut() {
+            if id.id == event.track || preview.track == Some(id.id) {
+                release_notes(&mut sounding, &mut TrackOut::new(id.id, &mut midi_out));
+            }
+        }
+
+        let hits = tracks
+            .iter()
+            .find(|(_, id, _)| id.id == event.track)
+            .and_then(|(track, _, _)| match track {
+                Track::Midi { steps } => steps.get(event.step),
+                Track::SF2 { .. } => None,
+            })
+            .map(|step| step.hits(pps, DEFAULT_VEL))
+            .unwrap_or_default();
+
+        *preview = Preview {
+            track: Some(event.track),
+            pulse: 0,
+            hits: PendingHits {
+                step: Some(event.step),
+                step_start: 0,
+                hits,
+                legato: false,
+            },
+            timer: Timer::new(
+                Duration::from_secs_f64(60.0 / tempo.0 as f64 / bpq.0 as f64),
+                TimerMode::Repeating,
+            ),
+        };
+    }
+
+    let Preview {
+        track,
+        pulse,
+        hits,
+        timer,
+    } = &mut *preview;
+    let Some((id, mut sounding)) = tracks
+        .iter_mut()
+        .find_map(|(_, id, sounding)| (Some(id.id) == *track).then_some((id.id, sounding)))
+    else {
+        *track = None;
+        return;
+    };
+    let mut out = TrackOut::new(id, &mut midi_out);
+
+    if started {
+        play_hits(hits, &mut sounding, 0, &mut out);
+        return;
+    }
+
+    timer.tick(Duration::from_millis(time.delta_millis()));
+
+    for _ in 0..timer.times_finished_this_tick() {
+        *pulse += 1;
+
+        if *pulse >= pps {
+            release_notes(&mut sounding, &mut out);
+            *track = None;
+            return;
+        }
+
+        play_hits(hits, &mut sounding, *pulse, &mut out);
+    }
+}
+
+/// plays the next note of a track's arp when it's due, and releases it when its gate is up.
+fn play_arp(
+    arp: &mut ArpState,
+    sounding: &mut SoundingNotes,
+    n_pulses: usize,
+    pps: usize,
+    held: &HeldNotes,
+    rng: &mut SmallRng,
+    midi_out: &mut TrackOut<'_, '_>,
+) {
+    let Some(playing) = arp.0.as_ref() else {
+        return;
+    };
+    let start = playing.start_pulse;
+    let length = playing.arp.steps.max(1) as usize * pps;
+
+    // the arp has finished, or playback jumped back to before it started.
+    if n_pulses < start || n_pulses >= start + length {
+        release_arp_note(arp, sounding, midi_out);
+        arp.0 = None;
+        return;
+    }
+
+    let t = n_pulses - start;
+    let rate = playing.arp.rate.max(1) as usize;
+
+    if t % rate == 0 {
+        let notes = if playing.use_held && !held.is_empty() {
+            &held.0
+        } else {
+            &playing.notes
+        };
+        let note = playing.arp.note_at(notes, t / rate, rng);
+        let vel = playing.vel;
+
+        release_arp_note(arp, sounding, midi_out);
+
+        if let Some(note) = note
+            && let Some(playing) = arp.0.as_mut()
+        {
+            midi_out.write(MidiEnv::On { note, vel });
+            sounding.push(note);
+            playing.note = Some(note);
+        }
+    } else if playing.arp.gate_pulses() == Some(t % rate) {
+        release_arp_note(arp, sounding, midi_out);
+    }
+}
+
+/// releases the note the arp is playing, if it hasn't been released already.
+fn release_arp_note(
+    arp: &mut ArpState,
+    sounding: &mut SoundingNotes,
+    midi_out: &mut TrackOut<'_, '_>,
+) {
+    if let Some(playing) = arp.0.as_mut()
+        && let Some(note) = playing.note.take()
+        && let Some(i) = sounding.iter().position(|sounding| *sounding == note)
+    {
+        sounding.remove(i);
+        midi_out.write(MidiEnv::Off { note });
+    }
+}
+
+/// releases the sounding notes, other than the one the arp is playing.
+fn release_step_notes(
+    sounding: &mut SoundingNotes,
+    arp: &ArpState,
+    midi_out: &mut TrackOut<'_, '_>,
+) {
+    let arp_note = arp.as_ref().and_then(|playing| playing.note);
+
+    sounding.retain(|note| {
+        let keep = Some(*note) == arp_note;
+
+        if !keep {
+            midi_out.write(MidiEnv::Off { note: *note });
+        }
+
+        keep
+    });
+}
+
+/// keeps track of the notes held down on the midi controller.
+fn track_held_notes(mut events: EventReader<FromHost>, mut held: ResMut<HeldNotes>) {
+    for event in events.read() {
+        match event {
+            FromHost::MidiNoteOn { note, vel, .. } if *vel > 0 => {
+                held.retain(|held| held != note);
+                held.push(*note);
+            }
+            // a note on with no velocity is a note off.
+            FromHost::MidiNoteOn { note, .. } | FromHost::MidiNoteOff { note, .. } => {
+                held.retain(|held| held != note)
+            }
+            _ => {}
+        }
+    }
+}
+
+/// the host's version of a midi message from a track. the channel is the track, the same as in
+/// `FromTracker::Connect`.
+pub fn host_midi(track: usize, msg: MidiEnv) -> FromTracker {
+    let channel = track as u8;
+
+    match msg {
+        MidiEnv::On { note, vel } => FromTracker::NoteOn { channel, note, vel },
+        MidiEnv::Off { note } => FromTracker::NoteOff { channel, note },
+        MidiEnv::PitchBend { value } => FromTracker::PitchBend { channel, value },
+        MidiEnv::Cc { cc, value } => FromTracker::Cc {
+            channel,
+            control: cc,
+            value,
+        },
+        MidiEnv::Program { program } => FromTracker::ProgramChange { channel, program },
+    }
+}
+
+/// where a track's midi goes out.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum MidiBackend {
+    Usb,
+    Host,
+}
+
+impl MidiBackend {
+    /// the track's output, or usb if the output is the host and `host_takes_notes` is false.
+    pub fn of(output: TrackOutput, host_takes_notes: bool) -> Self {
+        if output == TrackOutput::Host && host_takes_notes {
+            Self::Host
+        } else {
+            Self::Usb
+        }
+    }
+
+    pub fn send(
+        self,
+        track: usize,
+        msg: MidiEnv,
+        usb: &mut EventWriter<UsbMidiOut>,
+        to_host: &mut EventWriter<FromTracker>,
+    ) {
+        match self {
+            Self::Usb => {
+                usb.write(UsbMidiOut {
+                    channel: track as u8,
+                    msg,
+                });
+            }
+            Self::Host => {
+                to_host.write(host_midi(track, msg));
+            }
+        }
+    }
+}
+
+/// the notes each track has sent and not released yet, and where they went, so they're released
+/// where they started even if the track's output or the host changes while they sound.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SentNotes(Vec<(usize, MidiNote, MidiBackend)>);
+
+impl SentNotes {
+    /// where `msg` from `track` goes, given where the track's midi goes now. note offs go where
+    /// their note on went, and are dropped if the note was already released.
+    pub fn route(
+        &mut self,
+        track: usize,
+        msg: MidiEnv,
+        backend: MidiBackend,
+    ) -> Option<MidiBackend> {
+        let sent =
+            |(t, n, _): &(usize, MidiNote, MidiBackend), note: MidiNote| *t == track && *n == note;
+
+        match msg {
+            MidiEnv::On { note, vel } if vel > 0 => {
+                self.0.retain(|entry| !sent(entry, note));
+                self.0.push((track, note, backend));
+
+                Some(backend)
+            }
+            MidiEnv::On { note, .. } | MidiEnv::Off { note } => {
+                let i = self.0.iter().position(|entry| sent(entry, note))?;
+
+                Some(self.0.remove(i).2)
+            }
+            _ => Some(backend),
+        }
+    }
+
+    /// forgets the notes `track` has sounding anywhere but `backend`, returning them so they can
+    /// be released.
+    pub fn moved(&mut self, track: usize, backend: MidiBackend) -> Vec<(MidiNote, MidiBackend)> {
+        let mut moved = Vec::new();
+
+        self.0.retain(|(t, note, sent_to)| {
+            let keep = *t != track || *sent_to == backend;
+
+            if !keep {
+                moved.push((*note, *sent_to));
+            }
+
+            keep
+        });
+
+        moved
+    }
+}
+
+/// sends the tracks' midi to their outputs.
+fn send_track_midi(
+    mut events: EventReader<TrackMidi>,
+    tracks: Query<(&TrackID, &TrackOutput)>,
+    link: Option<Res<HostLink>>,
+    mut sent: Local<SentNotes>,
+    mut usb: EventWriter<UsbMidiOut>,
+    mut to_host: EventWriter<FromTracker>,
+) {
+    let host_takes_notes = link.is_some_and(|link| link.takes_notes());
+    let backend = |track: usize| {
+        let output = tracks
+            .iter()
+            .find_map(|(id, output)| (id.id == track).then_some(*output))
+            .unwrap_or_default();
+
+        MidiBackend::of(output, host_takes_notes)
+    };
+
+    // the output or the host changed, release the notes where they were sent or they'd hang.
+    for (id, _) in tracks.iter() {
+        for (note, sent_to) in sent.moved(id.id, backend(id.id)) {
+            sent_to.send(id.id, MidiEnv::Off { note }, &mut usb, &mut to_host);
+        }
+    }
+
+    for TrackMidi { track, msg } in events.read() {
+        if let Some(backend) = sent.route(*track, *msg, backend(*track)) {
+            backend.send(*track, *msg, &mut usb, &mut to_host);
+        }
+    }
+}
+
+/// sends the bank select, if the bank is set, then the program change.
+pub fn send_program(program: &Program, midi_out: &mut TrackOut<'_, '_>) {
+    for (cc, value) in program.bank_ccs() {
+        midi_out.write(MidiEnv::Cc { cc, value });
+    }
+
+    midi_out.write(MidiEnv::Program {
+        program: program.program.min(127),
+    });
+}
+
+/// sends a note off for every note that a track is still holding.
+pub fn release_notes(sounding: &mut SoundingNotes, midi_out: &mut TrackOut<'_, '_>) {
+    for note in sounding.drain(..) {
+        midi_out.write(MidiEnv::Off { note });
+    }
+}
+
+fn set_track_flags(
+    mut flag_events: EventReader<SetTrackFlag>,
+    mut tracks: Query<(&mut TrackID, &mut SoundingNotes)>,
+    mut midi_out: EventWriter<TrackMidi>,
+) {
+    if flag_events.is_empty() {
+        return;
+    }
+
+    for event in flag_events.read() {
+        for (mut id, _) in tracks.iter_mut() {
+            if id.id != event.track {
+                continue;
             }
+
+            let flag = match event.flag {
+                TrackFlag::Mute => &mut id.muted,
+                TrackFlag::Solo => &mut id.solo,
+                TrackFlag::Arm => &mut id.armed,
+            };
+            *flag = event.value.unwrap_or(!*flag);
         }
     }
 
-    // _ = last_played.0.insert(pulse.n_pulses);
+    // muting or soloing can silence tracks mid note.
+    let any_solo = tracks.iter().any(|(id, _)| id.solo);
+
+    for (id, mut sounding) in tracks.iter_mut() {
+        if !id.audible(any_solo) {
+            release_notes(&mut sounding, &mut TrackOut::new(id.id, &mut midi_out));
+        }
+    }
 }
 
 pub fn get_step_num(pulse: &Res<SyncPulse>, bpq: &Res<BPQ>) -> usize {
-    (pulse.n_pulses / (bpq.0 / 8)) % N_STEPS
+    (pulse.n_pulses / pulses_per_step(bpq)) % N_STEPS
+}
+
+/// each step is a 32nd note.
+pub fn pulses_per_step(bpq: &BPQ) -> usize {
+    bpq.0 / 8
+}
+
+/// the bar, beat, and step within the beat of a pulse, all counted from 1. a pattern is one bar.
+pub fn song_position(n_pulses: usize, bpq: &BPQ) -> (usize, usize, usize) {
+    let steps = n_pulses / pulses_per_step(bpq);
+    let steps_per_beat = N_STEPS / 4;
+
+    (
+        steps / N_STEPS + 1,
+        (steps % N_STEPS) / steps_per_beat + 1,
+        steps % steps_per_beat + 1,
+    )
+}
+
+/// keeps playback inside of the loop range. when a new step starts outside of the range this
+/// jumps forward to the next time the loop's first step would come around, so `n_pulses` never
+/// goes backwards.
+pub fn loop_pulse(
+    n_pulses: usize,
+    pulses_per_step: usize,
+    loop_range: Option<(usize, usize)>,
+) -> usize {
+    let Some((start, end)) = loop_range else {
+        return n_pulses;
+    };
+
+    if n_pulses % pulses_per_step != 0 {
+        return n_pulses;
+    }
+
+    let step = (n_pulses / pulses_per_step) % N_STEPS;
+
+    if (start..=end).contains(&step) {
+        n_pulses
+    } else {
+        n_pulses + ((start + N_STEPS - step) % N_STEPS) * pulses_per_step
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn transport_cmds(
+    mut cmds: EventReader<TransportCmd>,
+    mut transport: ResMut<Transport>,
+    mut pulse: ResMut<SyncPulse>,
+    mut last_played: ResMut<LastPlayedPulse>,
+    mut sync_timer: ResMut<SyncTimer>,
+    mut cond_state: ResMut<CondState>,
+    mut preview: ResMut<Preview>,
+    mut tracks: Query<(
+        &TrackID,
+        &mut SoundingNotes,
+        &mut LastCondition,
+        &mut PendingHits,
+        &mut BendState,
+        &TrackProgram,
+    )>,
+    mut midi_out: EventWriter<TrackMidi>,
+    bpq: Res<BPQ>,
+) {
+    for cmd in cmds.read() {
+        let was_playing = transport.state == TransportState::Playing;
+        let was_stopped = transport.state == TransportState::Stopped;
+
+        match *cmd {
+            TransportCmd::Play => transport.state = TransportState::Playing,
+            TransportCmd::Stop => {
+                transport.state = TransportState::Stopped;
+                pulse.n_pulses = 0;
+            }
+            TransportCmd::TogglePause => {
+                transport.state = if was_playing {
+                    TransportState::Paused
+                } else {
+                    TransportState::Playing
+                };
+            }
+            TransportCmd::RestartFrom(step) => {
+                transport.state = TransportState::Playing;
+                pulse.n_pulses = (step % N_STEPS) * pulses_per_step(&bpq);
+            }
+            TransportCmd::SetLoopStart(step) => {
+                let end = transport
+                    .loop_range
+                    .map(|(_, end)| end)
+                    .unwrap_or(N_STEPS - 1);
+                transport.loop_range = Some((step.min(end), step.max(end)));
+            }
+            TransportCmd::SetLoopEnd(step) => {
+                let start = transport.loop_range.map(|(start, _)| start).unwrap_or(0);
+                transport.loop_range = Some((start.min(step), start.max(step)));
+            }
+            TransportCmd::ClearLoop => transport.loop_range = None,
+            TransportCmd::Locate(step) => {
+                pulse.n_pulses = (step % N_STEPS) * pulses_per_step(&bpq);
+            }
+        }
+
+        let jumped = matches!(
+            cmd,
+            TransportCmd::Stop | TransportCmd::RestartFrom(_) | TransportCmd::Locate(_)
+        );
+
+        if jumped {
+            // so the step at the new location gets played, and gets its full length.
+            last_played.0 = None;
+            sync_timer.0.reset();
+            // conditions play out the same way each time playback restarts.
+            *cond_state = CondState::default();
+
+            for (id, _, mut last_cond, mut pending, mut bend, _) in tracks.iter_mut() {
+                last_cond.0 = false;
+                pending.hits.clear();
+
+                // bends don't carry over to where playback restarts.
+                if *bend != BendState::default() {
+                    if bend.value != BEND_CENTER {
+                        TrackOut::new(id.id, &mut midi_out)
+                            .write(MidiEnv::PitchBend { value: BEND_CENTER });
+                    }
+
+                    *bend = BendState::default();
+                }
+            }
+        }
+
+        if was_playing && (jumped || transport.state != TransportState::Playing) {
+            for (id, mut sounding, ..) in tracks.iter_mut() {
+                release_notes(&mut sounding, &mut TrackOut::new(id.id, &mut midi_out));
+            }
+        }
+
+        // instruments start out on their track's program.
+        if was_stopped && transport.state == TransportState::Playing {
+            for (id, .., program) in tracks.iter() {
+                if let Some(program) = program.0 {
+                    send_program(&program, &mut TrackOut::new(id.id, &mut midi_out));
+                }
+            }
+        }
+
+        // a preview is cut off when playback starts.
+        if transport.state == TransportState::Playing && preview.track.take().is_some() {
+            for (id, mut sounding, ..) in tracks.iter_mut() {
+                release_notes(&mut sounding, &mut TrackOut::new(id.id, &mut midi_out));
+            }
+        }
+    }
 }
 
 // fn toggle_playing(
@@ -0,0 +1,44 @@
+use bevy::prelude::*;
+use core::fmt::Display;
+use serde::{Deserialize, Serialize};
+
+/// midi bank select CC, most significant 7 bits.
+pub const BANK_MSB_CC: u8 = 0;
+/// midi bank select CC, least significant 7 bits.
+pub const BANK_LSB_CC: u8 = 32;
+
+/// a program change, with an optional bank select sent before it.
+#[derive(Clone, Copy, Default, Debug, PartialEq, Eq, PartialOrd, Hash, Serialize, Deserialize)]
+pub struct Program {
+    /// 0-127.
+    pub program: u8,
+    /// 14 bit bank number, `None` leaves the bank as it is.
+    #[serde(default)]
+    pub bank: Option<u16>,
+}
+
+impl Program {
+    /// the bank select CCs to send before the program change, as `(cc, value)` pairs.
+    pub fn bank_ccs(&self) -> Vec<(u8, u8)> {
+        self.bank
+            .map(|bank| {
+                alloc::vec![
+                    (BANK_MSB_CC, (bank >> 7 & 0x7f) as u8),
+                    (BANK_LSB_CC, (bank & 0x7f) as u8),
+                ]
+            })
+            .unwrap_or_default()
+    }
+
+    /// nudges the program number, 10 at a time vertically and 1 at a time horizontally.
+    pub fn adjust(&mut self, vertical: isize, horizontal: isize) {
+        self.program = (self.program as isize + vertical * 10 + horizontal).clamp(0, 127) as u8;
+    }
+}
+
+/// shown as `#` followed by the program number, e.g. `#012`.
+impl Display for Program {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "#{:0>3}", self.program.min(127))
+    }
+}
@@ -0,0 +1,416 @@
+use crate::{
+    Tempo, Track, TrackID, TrackOutput, TrackProgram,
+    base_plugin::MidiEnv,
+    bend::BEND_CENTER,
+    bus::Bus,
+    cond::CondState,
+    history::History,
+    logging::Log,
+    midi_plugin::{
+        ArpState, BendState, LastCondition, PendingHits, Preview, SoundingNotes, TrackMidi,
+        TrackOut, release_notes, send_program,
+    },
+    program::Program,
+    remote::TEMPO_RANGE,
+    routing::{Route, RouteStatus, TrackRoute},
+    scale::Key,
+    sync::{Push, sync_files},
+};
+use bevy::prelude::*;
+use embedded_sdmmc::{Mode, VolumeIdx};
+use pico_tracker_types::{FileInfo, ron, sync::Crc32};
+use picocalc_bevy::FileSystemStruct;
+use serde::{Deserialize, Serialize};
+
+/// 8.3 file name that the project is saved to on the SD card.
+pub const PROJECT_FILE: &str = "PROJECT.RON";
+
+#[derive(Event, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ProjectEvent {
+    Save,
+    Load,
+}
+
+/// everything that gets saved to the SD card.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Project {
+    pub tempo: u16,
+    #[serde(default)]
+    pub key: Key,
+    pub tracks: Vec<ProjectTrack>,
+    /// the messages bus commands publish and the messages the project reacts to.
+    #[serde(default)]
+    pub bus: Bus,
+}
+
+impl Project {
+    /// fixes up what the tracker can't play, so a hand edited or pushed project can't crash it.
+    pub fn sanitise(&mut self) {
+        self.tempo = self.tempo.clamp(*TEMPO_RANGE.start(), *TEMPO_RANGE.end());
+
+        for saved in self.tracks.iter_mut() {
+            saved.track.sanitise();
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ProjectTrack {
+    pub id: TrackID,
+    pub track: Track,
+    /// sent to the instrument when the project is loaded and when playback starts.
+    #[serde(default)]
+    pub program: Option<Program>,
+    /// sent to the host when the project is loaded and whenever the host connects.
+    #[serde(default)]
+    pub route: Option<Route>,
+    #[serde(default)]
+    pub output: TrackOutput,
+}
+
+impl ProjectTrack {
+    /// track `id` with no steps and nothing set, for the tracks a project file leaves out.
+    pub fn empty(id: usize) -> Self {
+        Self {
+            id: TrackID { id, ..default() },
+            track: Track::default(),
+            program: None,
+            route: None,
+            output: TrackOutput::default(),
+        }
+    }
+}
+
+pub struct ProjectPlugin;
+
+impl Plugin for ProjectPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<ProjectEvent>()
+            .init_resource::<Key>()
+            .init_resource::<Bus>()
+            .init_resource::<Push>()
+            .add_systems(Update, (save_project, load_project, sync_files));
+    }
+}
+
+/// writes `bytes` to `name` in the root directory of the SD card, replacing the file if it exists.
+pub fn write_file(fs: &mut FileSystemStruct, name: &str, bytes: &[u8]) -> Result<(), String> {
+    let volume_mgr = &fs.0;
+    let volume = volume_mgr
+        .open_volume(VolumeIdx(0))
+        .map_err(|e| format!("{e:?}"))?;
+    let root = volume.open_root_dir().map_err(|e| format!("{e:?}"))?;
+    let file = root
+        .open_file_in_dir(name, Mode::ReadWriteCreateOrTruncate)
+        .map_err(|e| format!("{e:?}"))?;
+
+    file.write(bytes).map_err(|e| format!("{e:?}"))
+}
+
+/// reads all of `name` from the root directory of the SD card.
+pub fn read_file(fs: &mut FileSystemStruct, name: &str) -> Result<Vec<u8>, String> {
+    let volume_mgr = &fs.0;
+    let volume = volume_mgr
+        .open_volume(VolumeIdx(0))
+        .map_err(|e| format!("{e:?}"))?;
+    let root = volume.open_root_dir().map_err(|e| format!("{e:?}"))?;
+    let file = root
+        .open_file_in_dir(name, Mode::ReadOnly)
+        .map_err(|e| format!("{e:?}"))?;
+
+    let mut bytes = Vec::new();
+    let mut buf = [0u8; 512];
+
+    while !file.is_eof() {
+        let n = file.read(&mut buf).map_err(|e| format!("{e:?}"))?;
+        bytes.extend_from_slice(&buf[..n]);
+    }
+
+    Ok(bytes)
+}
+
+/// up to `len` bytes of `name` from `offset` on, and the length of the whole file.
+pub fn read_file_at(
+    fs: &mut FileSystemStruct,
+    name: &str,
+    offset: u32,
+    len: usize,
+) -> Result<(Vec<u8>, u32), String> {
+    let volume_mgr = &fs.0;
+    let volume = volume_mgr
+        .open_volume(VolumeIdx(0))
+        .map_err(|e| format!("{e:?}"))?;
+    let root = volume.open_root_dir().map_err(|e| format!("{e:?}"))?;
+    let file = root
+        .open_file_in_dir(name, Mode::ReadOnly)
+        .map_err(|e| format!("{e:?}"))?;
+    let file_len = file.length();
+
+    if offset > file_len {
+        return Err(format!("{name} is only {file_len} bytes long"));
+    }
+
+    file.seek_from_start(offset).map_err(|e| format!("{e:?}"))?;
+
+    let mut bytes = vec![0u8; len.min((file_len - offset) as usize)];
+    let mut n_read = 0;
+
+    while n_read < bytes.len() && !file.is_eof() {
+        n_read += file
+            .read(&mut bytes[n_read..])
+            .map_err(|e| format!("{e:?}"))?;
+    }
+
+    bytes.truncate(n_read);
+
+    Ok((bytes, file_len))
+}
+
+/// the length and CRC-32 of `name`, read a block at a time.
+pub fn file_crc(fs: &mut FileSystemStruct, name: &str) -> Result<(u32, u32), String> {
+    let volume_mgr = &fs.0;
+    let volume = volume_mgr
+        .open_volume(VolumeIdx(0))
+        .map_err(|e| format!("{e:?}"))?;
+    let root = volume.open_root_dir().map_err(|e| format!("{e:?}"))?;
+    let file = root
+        .open_file_in_dir(name, Mode::ReadOnly)
+        .map_err(|e| format!("{e:?}"))?;
+
+    let mut crc = Crc32::default();
+    let mut buf = [0u8; 512];
+
+    while !file.is_eof() {
+        let n = file.read(&mut buf).map_err(|e| format!("{e:?}"))?;
+        crc.update(&buf[..n]);
+    }
+
+    Ok((file.length(), crc.finish()))
+}
+
+/// adds `bytes` to the end of `name` in the root directory of the SD card, creating the file if
+/// it doesn't exist. returns the file's new length.
+pub fn append_file(fs: &mut FileSystemStruct, name: &str, bytes: &[u8]) -> Result<u32, String> {
+    let volume_mgr = &fs.0;
+    let volume = volume_mgr
+        .open_volume(VolumeIdx(0))
+        .map_err(|e| format!("{e:?}"))?;
+    let root = volume.open_root_dir().map_err(|e| format!("{e:?}"))?;
+    let file = root
+        .open_file_in_dir(name, Mode::ReadWriteCreateOrAppend)
+        .map_err(|e| format!("{e:?}"))?;
+
+    file.write(bytes).map_err(|e| format!("{e:?}"))?;
+
+    Ok(file.length())
+}
+
+/// copies `from` over `to` in the root directory of the SD card a block at a time, so big files
+/// don't have to fit in the heap.
+pub fn copy_file(fs: &mut FileSystemStruct, from: &str, to: &str) -> Result<(), String> {
+    let volume_mgr = &fs.0;
+    let volume = volume_mgr
+        .open_volume(VolumeIdx(0))
+        .map_err(|e| format!("{e:?}"))?;
+    let root = volume.open_root_dir().map_err(|e| format!("{e:?}"))?;
+    let src = root
+        .open_file_in_dir(from, Mode::ReadOnly)
+        .map_err(|e| format!("{e:?}"))?;
+    let dst = root
+        .open_file_in_dir(to, Mode::ReadWriteCreateOrTruncate)
+        .map_err(|e| format!("{e:?}"))?;
+    let mut buf = [0u8; 512];
+
+    while !src.is_eof() {
+        let n = src.read(&mut buf).map_err(|e| format!("{e:?}"))?;
+        dst.write(&buf[..n]).map_err(|e| format!("{e:?}"))?;
+    }
+
+    Ok(())
+}
+
+/// deletes `name` from the root directory of the SD card.
+pub fn delete_file(fs: &mut FileSystemStruct, name: &str) -> Result<(), String> {
+    let volume_mgr = &fs.0;
+    let volume = volume_mgr
+        .open_volume(VolumeIdx(0))
+        .map_err(|e| format!("{e:?}"))?;
+    let root = volume.open_root_dir().map_err(|e| format!("{e:?}"))?;
+
+    root.delete_file_in_dir(name).map_err(|e| format!("{e:?}"))
+}
+
+/// the files in the root directory of the SD card, leaving out directories.
+pub fn list_files(fs: &mut FileSystemStruct) -> Result<Vec<FileInfo>, String> {
+    let volume_mgr = &fs.0;
+    let volume = volume_mgr
+        .open_volume(VolumeIdx(0))
+        .map_err(|e| format!("{e:?}"))?;
+    let root = volume.open_root_dir().map_err(|e| format!("{e:?}"))?;
+    let mut files = Vec::new();
+
+    root.iterate_dir(|entry| {
+        if !entry.attributes.is_directory() && !entry.attributes.is_volume() {
+            files.push(FileInfo {
+                name: format!("{}", entry.name),
+                len: entry.size,
+            });
+        }
+    })
+    .map_err(|e| format!("{e:?}"))?;
+
+    Ok(files)
+}
+
+fn save_project(
+    mut events: EventReader<ProjectEvent>,
+    mut fs: NonSendMut<FileSystemStruct>,
+    tracks: Query<(&Track, &TrackID, &TrackProgram, &TrackRoute, &TrackOutput)>,
+    tempo: Res<Tempo>,
+    key: Res<Key>,
+    bus: Res<Bus>,
+    mut log: EventWriter<Log>,
+) {
+    if !events.read().any(|event| *event == ProjectEvent::Save) {
+        return;
+    }
+
+    let mut tracks: Vec<ProjectTrack> = tracks
+        .iter()
+        .map(|(track, id, program, route, output)| ProjectTrack {
+            id: *id,
+            track: track.clone(),
+            program: program.0,
+            route: route.route.clone(),
+            output: *output,
+        })
+        .collect();
+    tracks.sort_by_key(|track| track.id.id);
+
+    let project = Project {
+        tempo: tempo.0,
+        key: *key,
+        tracks,
+        bus: bus.clone(),
+    };
+
+    let res = ron::to_string(&project)
+        .map_err(|e| format!("{e:?}"))
+        .and_then(|text| write_file(&mut fs, PROJECT_FILE, text.as_bytes()));
+
+    match res {
+        Ok(_) => log.write(Log::info("project saved")),
+        Err(e) => log.write(Log::error(format!("failed to save project: {e}"))),
+    };
+}
+
+#[allow(clippy::too_many_arguments, clippy::type_complexity)]
+fn load_project(
+    mut events: EventReader<ProjectEvent>,
+    mut fs: NonSendMut<FileSystemStruct>,
+    mut tracks: Query<(
+        &mut Track,
+        &mut TrackID,
+        &mut TrackProgram,
+        &mut TrackRoute,
+        &mut TrackOutput,
+        &mut SoundingNotes,
+        &mut PendingHits,
+        &mut ArpState,
+        &mut BendState,
+        &mut LastCondition,
+    )>,
+    mut tempo: ResMut<Tempo>,
+    mut key: ResMut<Key>,
+    mut bus: ResMut<Bus>,
+    mut cond_state: ResMut<CondState>,
+    mut preview: ResMut<Preview>,
+    history: Option<ResMut<History>>,
+    mut midi_out: EventWriter<TrackMidi>,
+    mut log: EventWriter<Log>,
+) {
+    if !events.read().any(|event| *event == ProjectEvent::Load) {
+        return;
+    }
+
+    let project = read_file(&mut fs, PROJECT_FILE).and_then(|bytes| {
+        let text = core::str::from_utf8(&bytes).map_err(|e| format!("{e:?}"))?;
+        ron::from_str::<Project>(text).map_err(|e| format!("{e:?}"))
+    });
+
+    let project = match project {
+        Ok(mut project) => {
+            project.sanitise();
+            project
+        }
+        Err(e) => {
+            log.write(Log::error(format!("failed to load project: {e}")));
+            return;
+        }
+    };
+
+    tempo.0 = project.tempo;
+    *key = project.key;
+    // the host is asked for the new subscriptions by the host plugin.
+    *bus = project.bus;
+
+    // nothing of the old project is left to play, or to count conditions from.
+    *cond_state = CondState::default();
+    preview.track = None;
+
+    for (
+        mut track,
+        mut id,
+        mut program,
+        mut route,
+        mut output,
+        mut sounding,
+        mut pending,
+        mut arp,
+        mut bend,
+        mut last_cond,
+    ) in tracks.iter_mut()
+    {
+        let mut track_out = TrackOut::new(id.id, &mut midi_out);
+        // released on the output they were played on, before it changes.
+        release_notes(&mut sounding, &mut track_out);
+
+        if bend.value != BEND_CENTER {
+            track_out.write(MidiEnv::PitchBend { value: BEND_CENTER });
+        }
+
+        *pending = PendingHits::default();
+        *arp = ArpState::default();
+        *bend = BendState::default();
+        last_cond.0 = false;
+
+        // tracks the file leaves out are emptied, not left with the old project's steps.
+        let saved = project
+            .tracks
+            .iter()
+            .find(|saved| saved.id.id == id.id)
+            .cloned()
+            .unwrap_or_else(|| ProjectTrack::empty(id.id));
+
+        *track = saved.track;
+        *id = saved.id;
+        program.0 = saved.program;
+        *output = saved.output;
+        // sent by the host plugin once there's a host to send it to.
+        *route = TrackRoute {
+            route: saved.route,
+            status: RouteStatus::Unsent,
+        };
+
+        if let Some(program) = saved.program {
+            send_program(&program, &mut track_out);
+        }
+    }
+
+    // the edits were made to the old tracks.
+    if let Some(mut history) = history {
+        *history = History::default();
+    }
+
+    log.write(Log::info("project loaded"));
+}
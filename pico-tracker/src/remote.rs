@@ -0,0 +1,271 @@
+//! commands from the host that drive the tracker: transport, tempo, mute/solo, and reading and
+//! writing steps.
+use crate::{
+    N_TRACKS, SetTrackFlag, Tempo, Track, TrackFlag, TrackID, TransportCmd,
+    clipboard::{ClipTrack, Clipboard},
+    history::{EditKind, History, HistoryBudget},
+    host::reply_to_host,
+};
+use bevy::prelude::*;
+use core::ops::RangeInclusive;
+use pico_tracker_types::{FromHost, FromTracker, codec::encode, ron};
+
+/// the tempos the host can set, the most that fit in the status line.
+pub const TEMPO_RANGE: RangeInclusive<u16> = 1..=999;
+
+/// a command from the host, and the id to answer it with.
+#[derive(Clone, Debug, PartialEq, Event)]
+pub struct Remote {
+    pub id: Option<u32>,
+    pub cmd: RemoteCmd,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum RemoteCmd {
+    Transport(TransportCmd),
+    Tempo(u16),
+    /// sets one of a track's flags, toggling it if `value` is `None`.
+    Flag {
+        track: usize,
+        flag: TrackFlag,
+        value: Option<bool>,
+    },
+    ReadSteps {
+        track: usize,
+        from: usize,
+        len: usize,
+    },
+    /// `steps` is a `ClipTrack` as RON.
+    WriteSteps {
+        track: usize,
+        from: usize,
+        steps: String,
+    },
+}
+
+impl Remote {
+    /// the command in a message from the host, `None` if the message isn't one.
+    pub fn from_host(message: &FromHost) -> Option<Self> {
+        let (id, cmd) = match message {
+            FromHost::Play { id } => (id, RemoteCmd::Transport(TransportCmd::Play)),
+            FromHost::Stop { id } => (id, RemoteCmd::Transport(TransportCmd::Stop)),
+            FromHost::Locate { id, step } => (
+                id,
+                RemoteCmd::Transport(TransportCmd::Locate(*step as usize)),
+            ),
+            FromHost::Launch { id, step } => (
+                id,
+                RemoteCmd::Transport(TransportCmd::RestartFrom(*step as usize)),
+            ),
+            FromHost::SetTempo { id, bpm } => (id, RemoteCmd::Tempo(*bpm)),
+            FromHost::Mute { id, track, value } => (
+                id,
+                RemoteCmd::Flag {
+                    track: *track as usize,
+                    flag: TrackFlag::Mute,
+                    value: *value,
+                },
+            ),
+            FromHost::Solo { id, track, value } => (
+                id,
+                RemoteCmd::Flag {
+                    track: *track as usize,
+                    flag: TrackFlag::Solo,
+                    value: *value,
+                },
+            ),
+            FromHost::ReadSteps {
+                id,
+                track,
+                from,
+                len,
+            } => (
+                id,
+                RemoteCmd::ReadSteps {
+                    track: *track as usize,
+                    from: *from as usize,
+                    len: *len as usize,
+                },
+            ),
+            FromHost::WriteSteps {
+                id,
+                track,
+                from,
+                steps,
+            } => (
+                id,
+                RemoteCmd::WriteSteps {
+                    track: *track as usize,
+                    from: *from as usize,
+                    steps: steps.clone(),
+                },
+            ),
+            _ => return None,
+        };
+
+        Some(Self { id: *id, cmd })
+    }
+}
+
+fn n_steps(track: &Track) -> usize {
+    match track {
+        Track::Midi { steps } => steps.len(),
+        Track::SF2 { steps } => steps.len(),
+    }
+}
+
+/// `len` of the track's steps from `from` on, as RON. fewer are returned if the track ends first.
+pub fn read_steps(track: &Track, from: usize, len: usize) -> Result<String, String> {
+    if from >= n_steps(track) {
+        return Err(format!("step {from} is past the end of the track"));
+    }
+
+    ron::to_string(&ClipTrack::copy(track, from, len)).map_err(|e| format!("{e}"))
+}
+
+/// the RON from `read_steps`, refusing steps with notes or velocities midi can't send, or rolls
+/// with more hits than fit in a cell.
+pub fn parse_steps(steps: &str) -> Result<ClipTrack, String> {
+    let steps: ClipTrack =
+        ron::from_str(steps).map_err(|e| format!("failed to parse steps: {e}"))?;
+    let invalid = match &steps {
+        ClipTrack::Midi(steps) => steps.iter().position(|step| !step.is_valid()),
+        ClipTrack::SF2(steps) => steps.iter().position(|step| !step.is_valid()),
+    };
+
+    match invalid {
+        Some(i) => Err(format!(
+            "step {i} has a note or velocity over 127, or a roll of over 9 hits"
+        )),
+        None => Ok(steps),
+    }
+}
+
+/// writes the RON from `read_steps` over the track from `from` on, converting commands if the
+/// steps are from another kind of track.
+pub fn write_steps(track: &mut Track, from: usize, steps: &str) -> Result<(), String> {
+    if from >= n_steps(track) {
+        return Err(format!("step {from} is past the end of the track"));
+    }
+
+    parse_steps(steps)?.paste(track, from, false);
+
+    Ok(())
+}
+
+/// `write_steps` on the track with id `track`, recorded in the undo history if there is one.
+fn write_remote_steps(
+    tracks: &mut Query<(&TrackID, &mut Track)>,
+    track: usize,
+    from: usize,
+    steps: &str,
+    history: Option<&mut History>,
+    budget: usize,
+) -> Result<(), String> {
+    let mut sorted: Vec<(&TrackID, Mut<Track>)> = tracks.iter_mut().collect();
+    sorted.sort_by_key(|(id, _)| id.id);
+    let mut sorted: Vec<Mut<Track>> = sorted.into_iter().map(|(_, track)| track).collect();
+
+    let Some(to) = sorted.get(track) else {
+        return Err(format!("there's no track {}", track + 1));
+    };
+
+    if from >= n_steps(to) {
+        return Err(format!("step {from} is past the end of the track"));
+    }
+
+    let clip = Clipboard {
+        tracks: vec![parse_steps(steps)?],
+    };
+    let block = clip.block_at(track, from);
+    let paste = |tracks: &mut [Mut<Track>]| clip.paste(tracks, track, from, false);
+
+    match history {
+        Some(history) => history.apply(&mut sorted, EditKind::Paste, block, budget, paste),
+        None => paste(&mut sorted),
+    }
+
+    Ok(())
+}
+
+/// turns the host's remote commands in to `Remote` events.
+pub fn remote_messages(mut from_host: EventReader<FromHost>, mut remote: EventWriter<Remote>) {
+    for message in from_host.read() {
+        if let Some(cmd) = Remote::from_host(message) {
+            remote.write(cmd);
+        }
+    }
+}
+
+/// carries out remote commands, answering the ones with an id with `Ack` or `Error`. written
+/// steps can be undone like any other paste.
+#[allow(clippy::too_many_arguments)]
+pub fn remote_cmds(
+    mut cmds: EventReader<Remote>,
+    mut tracks: Query<(&TrackID, &mut Track)>,
+    mut tempo: ResMut<Tempo>,
+    mut transport: EventWriter<TransportCmd>,
+    mut flags: EventWriter<SetTrackFlag>,
+    mut to_host: EventWriter<FromTracker>,
+    mut history: Option<ResMut<History>>,
+    budget: Option<Res<HistoryBudget>>,
+) {
+    for Remote { id, cmd } in cmds.read() {
+        let no_track = |track: usize| format!("there's no track {}", track + 1);
+
+        let reply = match cmd {
+            RemoteCmd::Transport(cmd) => {
+                transport.write(*cmd);
+                Ok(None)
+            }
+            RemoteCmd::Tempo(bpm) if TEMPO_RANGE.contains(bpm) => {
+                tempo.0 = *bpm;
+                Ok(None)
+            }
+            RemoteCmd::Tempo(bpm) => Err(format!(
+                "tempo {bpm} is outside of {}-{}",
+                TEMPO_RANGE.start(),
+                TEMPO_RANGE.end()
+            )),
+            RemoteCmd::Flag { track, .. } if *track >= N_TRACKS => Err(no_track(*track)),
+            RemoteCmd::Flag { track, flag, value } => {
+                flags.write(SetTrackFlag {
+                    track: *track,
+                    flag: *flag,
+                    value: *value,
+                });
+                Ok(None)
+            }
+            RemoteCmd::ReadSteps { track, from, len } => tracks
+                .iter()
+                .find(|(track_id, _)| track_id.id == *track)
+                .ok_or_else(|| no_track(*track))
+                .and_then(|(_, steps)| read_steps(steps, *from, *len))
+                .map(|steps| {
+                    Some(FromTracker::Steps {
+                        id: *id,
+                        track: *track as u8,
+                        from: *from as u16,
+                        steps,
+                    })
+                })
+                .and_then(|reply| {
+                    // the host has to ask for fewer steps at a time.
+                    encode(&reply)
+                        .map(|_| reply)
+                        .map_err(|e| format!("can't send {len} steps: {e}"))
+                }),
+            RemoteCmd::WriteSteps { track, from, steps } => write_remote_steps(
+                &mut tracks,
+                *track,
+                *from,
+                steps,
+                history.as_deref_mut(),
+                budget.as_deref().map_or(0, |budget| budget.0),
+            )
+            .map(|_| None),
+        };
+
+        reply_to_host(*id, reply, &mut to_host);
+    }
+}
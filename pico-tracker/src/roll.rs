@@ -0,0 +1,99 @@
+use crate::MidiNote;
+use bevy::prelude::*;
+use core::fmt::Display;
+use serde::{Deserialize, Serialize};
+
+/// retriggers a step's notes, spreading the hits evenly over the step.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Hash, Serialize, Deserialize)]
+pub struct Roll {
+    /// how many extra times to "roll" what ever is being played. a value of 1 would produce
+    /// two 64th notes.
+    pub times: u8,
+    /// how much the velocity changes from one hit to the next.
+    #[serde(default)]
+    pub vel_ramp: i8,
+    /// how many semitones each hit is moved from the last.
+    #[serde(default)]
+    pub pitch_step: i8,
+}
+
+impl Default for Roll {
+    fn default() -> Self {
+        Self {
+            times: 1,
+            vel_ramp: 0,
+            pitch_step: 0,
+        }
+    }
+}
+
+/// notes to start on a pulse of a step.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct Hit {
+    /// pulses after the start of the step.
+    pub pulse: usize,
+    pub notes: Vec<MidiNote>,
+    pub vel: u8,
+}
+
+impl Roll {
+    /// the most extra hits, so the number of hits fits in one digit.
+    pub const MAX_TIMES: u8 = 8;
+
+    /// the hits of the roll, spread evenly over `span` pulses starting at `start`. there is at
+    /// most one hit per pulse.
+    pub fn hits(&self, notes: &[MidiNote], vel: u8, start: usize, span: usize) -> Vec<Hit> {
+        let n_hits = (self.times as usize + 1).min(span.max(1));
+
+        (0..n_hits)
+            .map(|i| Hit {
+                pulse: start + i * span / n_hits,
+                notes: notes
+                    .iter()
+                    .map(|note| {
+                        (*note as isize + i as isize * self.pitch_step as isize).clamp(0, 127)
+                            as MidiNote
+                    })
+                    .collect(),
+                vel: (vel as isize + i as isize * self.vel_ramp as isize).clamp(1, 127) as u8,
+            })
+            .collect()
+    }
+
+    /// the most extra hits a step of `pulses_per_step` pulses has room for, and that fit in one
+    /// digit.
+    pub fn max_times(pulses_per_step: usize) -> u8 {
+        pulses_per_step
+            .saturating_sub(1)
+            .clamp(1, Self::MAX_TIMES as usize) as u8
+    }
+
+    /// nudges the roll, up/down being `vertical` and left/right `horizontal`. vertically
+    /// changes the number of hits, up to what fits in a step, horizontally the velocity ramp, 8
+    /// at a time.
+    pub fn adjust(&mut self, vertical: isize, horizontal: isize, pulses_per_step: usize) {
+        let max_times = Self::max_times(pulses_per_step) as isize;
+
+        self.times = (self.times as isize + vertical).clamp(1, max_times) as u8;
+        self.vel_ramp = (self.vel_ramp as isize + horizontal * 8).clamp(-64, 64) as i8;
+    }
+}
+
+/// shown as `R`, the number of hits, then `<`/`>` for a velocity ramp up/down and `^`/`v` for
+/// a pitch step up/down, e.g. `R4<-`.
+impl Display for Roll {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        let ramp = match self.vel_ramp {
+            1.. => '<',
+            ..0 => '>',
+            0 => '-',
+        };
+        let pitch = match self.pitch_step {
+            1.. => '^',
+            ..0 => 'v',
+            0 => '-',
+        };
+
+        write!(f, "R{}{ramp}{pitch}", self.times.saturating_add(1))
+    }
+}
@@ -0,0 +1,194 @@
+//! routing tracks to the host's midi devices.
+use crate::{
+    TrackID,
+    host::{HostClosed, HostLink, HostReply},
+};
+use bevy::prelude::*;
+use pico_tracker_types::{FromHost, FromTracker, capability};
+use serde::{Deserialize, Serialize};
+
+/// the host device, and the channel on it, that a track's midi is sent to.
+#[derive(Clone, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct Route {
+    pub dev: String,
+    /// 0-15, shown as 1-16.
+    pub dev_channel: u8,
+}
+
+impl Route {
+    /// moves the device channel, wrapping around.
+    pub fn adjust_channel(&mut self, by: isize) {
+        self.dev_channel = (self.dev_channel as isize + by).rem_euclid(16) as u8;
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub enum RouteStatus {
+    /// changed on the routing screen, but not sent yet.
+    Edited,
+    /// sent as soon as there's a host that can route.
+    #[default]
+    Unsent,
+    /// sent with this request id, waiting for the host to answer.
+    Waiting(u32),
+    Connected,
+    Failed(String),
+}
+
+impl RouteStatus {
+    /// four characters for the routing screen.
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::Edited => "edit",
+            Self::Unsent => "----",
+            Self::Waiting(_) => "....",
+            Self::Connected => " ok ",
+            Self::Failed(_) => "FAIL",
+        }
+    }
+}
+
+/// where a track is routed on the host, saved with the project. `None` leaves the host's routing
+/// for the track as it is.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Component)]
+pub struct TrackRoute {
+    pub route: Option<Route>,
+    pub status: RouteStatus,
+}
+
+/// the device names from the host's last `Devs` message.
+#[derive(Resource, Clone, Debug, Default, PartialEq, Eq)]
+pub struct HostDevs {
+    pub names: Vec<String>,
+    /// a `RequestDevs` was sent and the host hasn't answered yet.
+    pub waiting: bool,
+}
+
+impl HostDevs {
+    /// the device after (or before, if `by` is negative) `dev` in the list, with `None` (no
+    /// route) between the last and first devices.
+    pub fn cycle(&self, dev: Option<&str>, by: isize) -> Option<String> {
+        let n = self.names.len() as isize + 1;
+        // 0 is no route, devices start at 1.
+        let i = dev
+            .and_then(|dev| self.names.iter().position(|name| name == dev))
+            .map(|i| i as isize + 1)
+            .unwrap_or(0);
+        let i = (i + by).rem_euclid(n);
+
+        (i > 0).then(|| self.names[i as usize - 1].clone())
+    }
+}
+
+/// asks the host for its device list.
+pub fn request_devs(devs: &mut HostDevs, to_host: &mut EventWriter<FromTracker>) {
+    devs.waiting = true;
+    to_host.write(FromTracker::RequestDevs);
+}
+
+/// sends a `Connect` for the track, the tracker channel being the track's index. returns the id
+/// the host answers with.
+pub fn connect(
+    track: usize,
+    route: &Route,
+    link: &mut HostLink,
+    to_host: &mut EventWriter<FromTracker>,
+) -> u32 {
+    let id = link.request(format!("routing channel {}", track + 1));
+
+    to_host.write(FromTracker::Connect {
+        id: Some(id),
+        channel: track as u8,
+        dev: route.dev.clone(),
+        dev_channel: route.dev_channel,
+    });
+
+    id
+}
+
+/// keeps the device list up to date, and re-sends every route when the host says hello, in case
+/// it was restarted.
+pub fn routing_messages(
+    mut from_host: EventReader<FromHost>,
+    mut devs: ResMut<HostDevs>,
+    mut tracks: Query<&mut TrackRoute>,
+) {
+    for message in from_host.read() {
+        match message {
+            FromHost::Devs { dev_names } => {
+                devs.names = dev_names.clone();
+                devs.waiting = false;
+            }
+            FromHost::Hello { .. } => {
+                devs.waiting = false;
+
+                for mut route in tracks.iter_mut() {
+                    if route.route.is_some() && route.status != RouteStatus::Edited {
+                        route.status = RouteStatus::Unsent;
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// once the host closes the serial port, marks the routes to be sent again when it comes back.
+pub fn close_routes(
+    mut closed: EventReader<HostClosed>,
+    mut devs: ResMut<HostDevs>,
+    mut tracks: Query<&mut TrackRoute>,
+) {
+    if closed.read().count() == 0 {
+        return;
+    }
+
+    devs.waiting = false;
+
+    for mut route in tracks.iter_mut() {
+        if route.route.is_some() && route.status != RouteStatus::Edited {
+            route.status = RouteStatus::Unsent;
+        }
+    }
+}
+
+/// sends the routes that haven't been sent, once there's a host that can route them.
+pub fn send_routes(
+    mut link: ResMut<HostLink>,
+    mut tracks: Query<(&TrackID, &mut TrackRoute)>,
+    mut to_host: EventWriter<FromTracker>,
+) {
+    if !link.is_compatible() || !link.has_capability(capability::ROUTING) {
+        return;
+    }
+
+    for (id, mut route) in tracks.iter_mut() {
+        if route.status != RouteStatus::Unsent {
+            continue;
+        }
+
+        let Some(to) = route.route.clone() else {
+            continue;
+        };
+
+        let request = connect(id.id, &to, &mut link, &mut to_host);
+        route.status = RouteStatus::Waiting(request);
+    }
+}
+
+/// marks routes as connected or failed when the host answers.
+pub fn route_replies(mut replies: EventReader<HostReply>, mut tracks: Query<&mut TrackRoute>) {
+    for reply in replies.read() {
+        let Some(mut route) = tracks
+            .iter_mut()
+            .find(|route| route.status == RouteStatus::Waiting(reply.id))
+        else {
+            continue;
+        };
+
+        route.status = match &reply.result {
+            Ok(()) => RouteStatus::Connected,
+            Err(e) => RouteStatus::Failed(e.clone()),
+        };
+    }
+}
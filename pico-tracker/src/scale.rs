@@ -0,0 +1,184 @@
+use crate::{
+    Intervals, MidiNote,
+    chord::{Chord, ChordQuality},
+    display_midi_note,
+    transform::transpose_note,
+};
+use bevy::prelude::*;
+use core::fmt::Display;
+use serde::{Deserialize, Serialize};
+use strum_macros::Display;
+
+#[derive(Clone, Copy, Default, Debug, PartialEq, Eq, Hash, Display, Serialize, Deserialize)]
+pub enum Scale {
+    #[default]
+    #[strum(to_string = "Maj")]
+    Major,
+    #[strum(to_string = "Min")]
+    Minor,
+    #[strum(to_string = "Dor")]
+    Dorian,
+    #[strum(to_string = "Phr")]
+    Phrygian,
+    #[strum(to_string = "Lyd")]
+    Lydian,
+    #[strum(to_string = "Mix")]
+    Mixolydian,
+    #[strum(to_string = "Loc")]
+    Locrian,
+    #[strum(to_string = "MaP")]
+    MajorPentatonic,
+    #[strum(to_string = "MiP")]
+    MinorPentatonic,
+    /// bit `n` is set if the note `n` semitones above the root is in the scale.
+    #[strum(to_string = "Cus")]
+    Custom(u16),
+}
+
+impl Scale {
+    /// the built in scales, in the order they are cycled through.
+    pub const PRESETS: [Scale; 9] = [
+        Self::Major,
+        Self::Minor,
+        Self::Dorian,
+        Self::Phrygian,
+        Self::Lydian,
+        Self::Mixolydian,
+        Self::Locrian,
+        Self::MajorPentatonic,
+        Self::MinorPentatonic,
+    ];
+
+    /// the scale as a bit mask, bit `n` being set if the note `n` semitones above the root is in
+    /// the scale.
+    pub fn mask(&self) -> u16 {
+        let intervals: &[u8] = match self {
+            Self::Major => &[0, 2, 4, 5, 7, 9, 11],
+            Self::Minor => &[0, 2, 3, 5, 7, 8, 10],
+            Self::Dorian => &[0, 2, 3, 5, 7, 9, 10],
+            Self::Phrygian => &[0, 1, 3, 5, 7, 8, 10],
+            Self::Lydian => &[0, 2, 4, 6, 7, 9, 11],
+            Self::Mixolydian => &[0, 2, 4, 5, 7, 9, 10],
+            Self::Locrian => &[0, 1, 3, 5, 6, 8, 10],
+            Self::MajorPentatonic => &[0, 2, 4, 7, 9],
+            Self::MinorPentatonic => &[0, 3, 5, 7, 10],
+            Self::Custom(mask) => return mask & 0xfff,
+        };
+
+        intervals.iter().fold(0, |mask, i| mask | 1 << i)
+    }
+
+    /// whether the note `interval` semitones above the root is in the scale.
+    pub fn contains(&self, interval: u8) -> bool {
+        self.mask() & 1 << (interval % 12) != 0
+    }
+
+    /// a custom scale made of the root and every note in `notes`.
+    pub fn custom(root: u8, notes: impl IntoIterator<Item = MidiNote>) -> Self {
+        let mask = notes
+            .into_iter()
+            .fold(1, |mask, note| mask | 1 << ((note + 12 - root % 12) % 12));
+
+        Self::Custom(mask)
+    }
+
+    /// the preset `by` places along from this one. custom scales cycle back to the first preset.
+    pub fn cycle(&self, by: isize) -> Self {
+        let i = Self::PRESETS
+            .iter()
+            .position(|scale| scale == self)
+            .map(|i| i as isize + by)
+            .unwrap_or(0);
+
+        Self::PRESETS[i.rem_euclid(Self::PRESETS.len() as isize) as usize]
+    }
+}
+
+/// the project's root note and scale.
+#[derive(Clone, Copy, Default, Debug, PartialEq, Eq, Hash, Resource, Serialize, Deserialize)]
+pub struct Key {
+    /// 0 is C, 11 is B.
+    pub root: u8,
+    pub scale: Scale,
+    /// when set, moving a note up or down steps through the scale instead of semitones.
+    #[serde(default)]
+    pub lock: bool,
+}
+
+impl Key {
+    pub fn contains(&self, note: MidiNote) -> bool {
+        self.scale.contains((note % 12 + 12 - self.root % 12) % 12)
+    }
+
+    /// the closest note in the key, rounding down when two are equally close.
+    pub fn quantise(&self, note: MidiNote) -> MidiNote {
+        (0..12u8)
+            .flat_map(|dist| [note.checked_sub(dist), note.checked_add(dist)])
+            .flatten()
+            .find(|note| *note < 128 && self.contains(*note))
+            .unwrap_or(note)
+    }
+
+    /// moves `note` up or down `by` notes of the scale, stopping at the last note in the key
+    /// before the end of the midi note range. a note that's out of the key moves to the next note
+    /// in the key first.
+    pub fn step(&self, note: MidiNote, by: i16) -> MidiNote {
+        if self.scale.mask() == 0 {
+            return transpose_note(note, by);
+        }
+
+        (0..by.abs()).fold(note, |note, _| {
+            let mut next = note;
+
+            loop {
+                let moved = transpose_note(next, by.signum());
+
+                if moved == next {
+                    return note;
+                } else if self.contains(moved) {
+                    return moved;
+                }
+
+                next = moved;
+            }
+        })
+    }
+
+    /// the chord built out of the key's notes, stacking thirds on `note`. a triad, or a seventh
+    /// chord if `seventh` is set. chord tones that have no note in the key are left out, `None`
+    /// if what's left isn't a named chord.
+    pub fn chord(&self, note: MidiNote, seventh: bool) -> Option<Chord> {
+        let note = self.quantise(note);
+        let tones: [&[Intervals]; 4] = [
+            &[Intervals::Root],
+            &[Intervals::MajThird, Intervals::MinThird],
+            &[
+                Intervals::Fifth,
+                Intervals::FlatFifth,
+                Intervals::SharpFifth,
+            ],
+            &[Intervals::Seventh, Intervals::FlatSeventh],
+        ];
+
+        let intervals = tones
+            .into_iter()
+            .take(if seventh { 4 } else { 3 })
+            .filter_map(|options| {
+                options
+                    .iter()
+                    .find(|interval| self.contains(note + interval.semitones()))
+                    .map(|interval| interval.semitones())
+            })
+            .collect::<Vec<u8>>();
+
+        ChordQuality::from_intervals(&intervals).map(Chord::new)
+    }
+}
+
+impl Display for Key {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        let root = display_midi_note(self.root % 12);
+
+        write!(f, "{}{}", &root[..2], self.scale)
+    }
+}
@@ -0,0 +1,201 @@
+//! project sync: the host listing, fetching, pushing and deleting the files on the SD card. see
+//! `pico_tracker_types::sync` for how files are chunked.
+use crate::{
+    host::reply_to_host,
+    logging::Log,
+    project::{append_file, copy_file, delete_file, file_crc, list_files, read_file_at},
+};
+use bevy::prelude::*;
+use pico_tracker_types::{
+    FromHost, FromTracker,
+    codec::encode,
+    sync::{CHUNK_LEN, MAX_FILE_LEN, TEMP_FILE, crc32, from_hex, to_hex},
+};
+use picocalc_bevy::FileSystemStruct;
+
+/// the file the host is pushing, which is kept in `TEMP_FILE` until it's finished.
+#[derive(Resource, Clone, Debug, Default, PartialEq, Eq)]
+pub struct Push {
+    /// `None` when nothing is being pushed.
+    pub name: Option<String>,
+    /// how much of the file has arrived.
+    pub len: u32,
+}
+
+/// the checksum of the file the host is fetching, worked out on its first chunk so the rest don't
+/// read the whole file again.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Fetch {
+    name: String,
+    len: u32,
+    crc: u32,
+}
+
+fn read_chunk(
+    fs: &mut FileSystemStruct,
+    fetch: &mut Option<Fetch>,
+    id: Option<u32>,
+    name: &str,
+    offset: u32,
+) -> Result<FromTracker, String> {
+    let (chunk, file_len) = read_file_at(fs, name, offset, CHUNK_LEN)?;
+
+    let file_crc = match fetch {
+        Some(fetch) if offset != 0 && fetch.name == name && fetch.len == file_len => fetch.crc,
+        _ => {
+            let (len, crc) = file_crc(fs, name)?;
+            *fetch = Some(Fetch {
+                name: name.into(),
+                len,
+                crc,
+            });
+            crc
+        }
+    };
+
+    Ok(FromTracker::FileChunk {
+        id,
+        name: name.into(),
+        offset,
+        data: to_hex(&chunk),
+        crc: crc32(&chunk),
+        file_len,
+        file_crc,
+    })
+}
+
+fn write_chunk(
+    fs: &mut FileSystemStruct,
+    push: &mut Push,
+    name: &str,
+    offset: u32,
+    data: &str,
+    crc: u32,
+) -> Result<(), String> {
+    let bytes = from_hex(data).ok_or_else(|| format!("the chunk at {offset} isn't hex"))?;
+
+    if crc32(&bytes) != crc {
+        return Err(format!("the chunk at {offset} failed its checksum"));
+    }
+
+    if offset == 0 {
+        // starts over, dropping whatever was pushed before. there's no temp file to delete if
+        // nothing was.
+        let _ = delete_file(fs, TEMP_FILE);
+        *push = Push {
+            name: Some(name.into()),
+            len: 0,
+        };
+    } else if push.name.as_deref() != Some(name) {
+        return Err(format!("{name} isn't being pushed, start from offset 0"));
+    } else if offset != push.len {
+        return Err(format!("expected the chunk at {}, got {offset}", push.len));
+    }
+
+    if offset as usize + bytes.len() > MAX_FILE_LEN as usize {
+        *push = Push::default();
+        let _ = delete_file(fs, TEMP_FILE);
+
+        return Err(format!("{name} is over {MAX_FILE_LEN} bytes"));
+    }
+
+    match append_file(fs, TEMP_FILE, &bytes) {
+        Ok(len) => {
+            push.len = len;
+            Ok(())
+        }
+        Err(e) => {
+            *push = Push::default();
+            Err(e)
+        }
+    }
+}
+
+fn finish_file(
+    fs: &mut FileSystemStruct,
+    push: &mut Push,
+    name: &str,
+    len: u32,
+    crc: u32,
+) -> Result<(), String> {
+    if push.name.as_deref() != Some(name) {
+        return Err(format!("{name} isn't being pushed"));
+    }
+
+    *push = Push::default();
+
+    if len > MAX_FILE_LEN {
+        let _ = delete_file(fs, TEMP_FILE);
+
+        return Err(format!("{name} is over {MAX_FILE_LEN} bytes"));
+    }
+
+    // read back a block at a time, so what's checked is what the card kept.
+    let res = file_crc(fs, TEMP_FILE).and_then(|(file_len, file_crc)| {
+        if file_len != len || file_crc != crc {
+            return Err(format!(
+                "{name} arrived as {file_len} bytes with checksum {file_crc:08x}, expected {len} \
+                 bytes with {crc:08x}"
+            ));
+        }
+
+        copy_file(fs, TEMP_FILE, name)
+    });
+    let _ = delete_file(fs, TEMP_FILE);
+
+    res
+}
+
+/// answers the host's project sync requests.
+pub fn sync_files(
+    mut from_host: EventReader<FromHost>,
+    mut fs: NonSendMut<FileSystemStruct>,
+    mut push: ResMut<Push>,
+    mut fetch: Local<Option<Fetch>>,
+    mut to_host: EventWriter<FromTracker>,
+    mut log: EventWriter<Log>,
+) {
+    for message in from_host.read() {
+        let (id, reply) = match message {
+            FromHost::ListFiles { id } => (
+                id,
+                list_files(&mut fs)
+                    .map(|files| FromTracker::Files { id: *id, files })
+                    .and_then(|reply| {
+                        // a reply too big for a frame would never arrive.
+                        encode(&reply)
+                            .map(|_| Some(reply))
+                            .map_err(|e| format!("too many files to list: {e}"))
+                    }),
+            ),
+            FromHost::ReadFile { id, name, offset } => (
+                id,
+                read_chunk(&mut fs, &mut fetch, *id, name, *offset).map(Some),
+            ),
+            FromHost::WriteFile {
+                id,
+                name,
+                offset,
+                data,
+                crc,
+            } => (
+                id,
+                write_chunk(&mut fs, &mut push, name, *offset, data, *crc).map(|_| None),
+            ),
+            FromHost::FinishFile { id, name, len, crc } => {
+                let res = finish_file(&mut fs, &mut push, name, *len, *crc);
+
+                match res {
+                    Ok(_) => log.write(Log::info(format!("received {name} from the host"))),
+                    Err(ref e) => log.write(Log::error(format!("failed to receive {name}: {e}"))),
+                };
+
+                (id, res.map(|_| None))
+            }
+            FromHost::DeleteFile { id, name } => (id, delete_file(&mut fs, name).map(|_| None)),
+            _ => continue,
+        };
+
+        reply_to_host(*id, reply, &mut to_host);
+    }
+}
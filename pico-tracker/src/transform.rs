@@ -0,0 +1,93 @@
+use crate::{MidiNote, Step, Track, midi_plugin::DEFAULT_VEL, scale::Key};
+use bevy::prelude::*;
+use core::fmt::{Debug, Display};
+use rand::{Rng, rngs::SmallRng};
+
+/// a musical transformation applied to a range of steps.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Transform {
+    /// move notes up or down by this many semitones.
+    Transpose(i16),
+    Reverse,
+    /// rotate the steps right by this many steps, negative values rotate left.
+    Rotate(isize),
+    /// copy the first step of the range over every n-th step.
+    FillEvery(usize),
+    /// nudge each note's velocity by up to `vel` either side of what it is, and its delay by up to
+    /// `timing` pulses either side, so accents and swing survive.
+    Humanise {
+        vel: u8,
+        timing: u8,
+    },
+    /// move every note to the closest note in the key.
+    Quantise(Key),
+}
+
+/// moves `note` by `by` semitones, stopping at the ends of the midi note range.
+pub fn transpose_note(note: MidiNote, by: i16) -> MidiNote {
+    (note as i16 + by).clamp(0, 127) as MidiNote
+}
+
+impl Transform {
+    /// applies the transform to `n_steps` steps of `track` starting at `first_step`.
+    pub fn apply(&self, track: &mut Track, first_step: usize, n_steps: usize, rng: &mut SmallRng) {
+        match track {
+            Track::Midi { steps } => self.apply_steps(step_range(steps, first_step, n_steps), rng),
+            Track::SF2 { steps } => self.apply_steps(step_range(steps, first_step, n_steps), rng),
+        }
+    }
+
+    fn apply_steps<Cmd>(&self, steps: &mut [Step<Cmd>], rng: &mut SmallRng)
+    where
+        Cmd: Clone + Default + PartialEq + PartialOrd + Display + ToString + Debug,
+    {
+        if steps.is_empty() {
+            return;
+        }
+
+        match *self {
+            Self::Transpose(by) => steps
+                .iter_mut()
+                .filter_map(|step| step.note.as_mut())
+                .for_each(|note| *note = transpose_note(*note, by)),
+            Self::Reverse => steps.reverse(),
+            Self::Rotate(by) => {
+                let by = by.rem_euclid(steps.len() as isize) as usize;
+                steps.rotate_right(by);
+            }
+            Self::FillEvery(n) => {
+                let first = steps[0].clone();
+
+                steps
+                    .iter_mut()
+                    .step_by(n.max(1))
+                    .skip(1)
+                    .for_each(|step| *step = first.clone());
+            }
+            Self::Humanise { vel, timing } => {
+                for step in steps.iter_mut().filter(|step| step.note.is_some()) {
+                    let offset = rng.random_range(-(vel as i16)..=vel as i16);
+                    let step_vel = step.vel.unwrap_or(DEFAULT_VEL) as i16;
+                    step.vel = Some((step_vel + offset).clamp(1, 127) as u8);
+                    // a note can't start before its step.
+                    let offset = rng.random_range(-(timing as i16)..=timing as i16);
+                    step.delay = (step.delay as i16 + offset).clamp(0, u8::MAX as i16) as u8;
+                }
+            }
+            Self::Quantise(key) => steps
+                .iter_mut()
+                .filter_map(|step| step.note.as_mut())
+                .for_each(|note| *note = key.quantise(*note)),
+        }
+    }
+}
+
+fn step_range<Cmd>(steps: &mut [Step<Cmd>], first_step: usize, n_steps: usize) -> &mut [Step<Cmd>]
+where
+    Cmd: Clone + Default + PartialEq + PartialOrd + Display + ToString + Debug,
+{
+    let first_step = first_step.min(steps.len());
+    let last_step = (first_step + n_steps).min(steps.len());
+
+    &mut steps[first_step..last_step]
+}